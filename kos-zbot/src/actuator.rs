@@ -1,74 +1,810 @@
-use crate::firmware::feetech::{FeetechActuatorType, FeetechSupervisor};
+use crate::calibration::{CalibrationConfig, CalibrationHandle, CalibrationStartup, Calibrator, OperationSink};
+use crate::calibration_store::CalibrationStore;
+use crate::config::{EStopConfig, JointConfig, LimitPolicy, RobotConfig, WatchdogAction};
+use crate::error::{to_kos_error, ZBotError};
+use crate::estop::{EStop, EStopSource, EStopState};
+use crate::firmware::feetech::{
+    FeetechActuator, FeetechBus, FeetechSupervisor, ServoEvent, UnknownServoModel, MAX_SERVO_ID,
+};
+use crate::firmware::feetech_serial::BROADCAST_ID;
+use crate::firmware::liveness::{LivenessEvent, ReadingAge};
+use crate::firmware::loop_timing::{loop_period, LoopTimer, LoopTiming};
+use crate::firmware::stats::{PrometheusWriter, SupervisorStats};
+use crate::firmware::feetech_scan::ScanOptions;
+use crate::joint::JointDescriptor;
+use crate::scan::{start_scan, ScanHandle};
+use crate::trajectory::{ServoWaypoint, Trajectory, TrajectoryQueue, TrajectoryStatus};
+use crate::watchdog::{CommandWatchdog, WatchdogState};
 use eyre::Result;
 use kos::hal::{Actuator, Operation};
 use kos::kos_proto::{
     actuator::*,
-    common::{ActionResponse, ActionResult, Error as KosError, ErrorCode},
+    common::{ActionResponse, ActionResult},
 };
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use tracing::{debug,warn};
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 
 pub struct ZBotActuator {
     supervisor: Arc<RwLock<FeetechSupervisor>>,
-    command_rate: f64, // Hz
+    command_period: Arc<watch::Sender<Duration>>,
+    command_timing: Arc<std::sync::Mutex<LoopTiming>>,
+    phase_lock: Arc<AtomicBool>,
+    joints: Arc<HashMap<u8, JointDescriptor>>,
+    limit_policy: LimitPolicy,
+    limit_stats: Arc<LimitStats>,
+    watchdog: Arc<CommandWatchdog>,
+    estop: Arc<EStop>,
+    calibration: CalibrationConfig,
+    calibrator: Calibrator,
+    calibration_groups: Vec<Vec<u8>>,
+    robot_serial: String,
+    trajectory: Arc<std::sync::Mutex<TrajectoryQueue>>,
     desired_positions: Arc<RwLock<HashMap<u8, f32>>>,
     desired_velocities: Arc<RwLock<HashMap<u8, f32>>>,
-    command_task_running: Arc<AtomicBool>,
+    command_task: Arc<std::sync::Mutex<Option<JoinHandle<()>>>>,
+    scan: Arc<std::sync::Mutex<Option<ScanHandle>>>,
+    operations: Option<Arc<dyn OperationSink>>,
+}
+
+/// The e-stop, as an actuator id for `configure_actuator`. Only
+/// `torque_enabled` may be set: `false` latches the stop and `true` clears
+/// it. Any other field is rejected. It is the servo broadcast id, so it
+/// cannot clash with a joint.
+pub const ESTOP_ACTUATOR_ID: u32 = BROADCAST_ID as u32;
+
+/// Prefix of the `faults` entry that carries the age of the reading in an
+/// actuator state, followed by the age in ms: `reading_age_ms=12`.
+pub const READING_AGE_FAULT_PREFIX: &str = "reading_age_ms=";
+
+/// Counters for commands that hit a joint limit.
+#[derive(Debug, Default)]
+pub struct LimitStats {
+    pub clamped: AtomicU64,
+    pub rejected: AtomicU64,
+    /// Command ticks in which a position was held back by `max_step_deg`.
+    pub step_limited: AtomicU64,
 }
 
 impl ZBotActuator {
-    pub async fn new(actuator_list: &[u8]) -> Result<Self> {
-        let mut supervisor = FeetechSupervisor::new()?;
+    pub async fn new(bus: Arc<dyn FeetechBus>, config: &RobotConfig) -> Result<Self> {
+        let joints = &config.joints;
+        let mut supervisor = FeetechSupervisor::new(bus)?;
+        supervisor.set_poll_rate(config.control.poll_rate_hz)?;
+        supervisor.set_liveness_config(config.liveness.clone());
+
+        for joint in joints {
+            let id = joint.servo_id;
+            let result = match joint.servo_type {
+                Some(actuator_type) => supervisor.add_servo(id, actuator_type).await,
+                None => supervisor.discover_servo(id).await,
+            };
+            // An unsupported servo should not take the rest of the robot down.
+            match result {
+                Err(e) if e.downcast_ref::<UnknownServoModel>().is_some() => {
+                    error!("Skipping joint {} (servo {}): {}", joint.name, id, e);
+                }
+                result => result?,
+            }
+        }
+
+        {
+            let mut protection = supervisor.protection.lock().unwrap();
+            for joint in joints {
+                protection.set_limits(joint.servo_id, config.protection_limits(joint));
+            }
+        }
+
+        {
+            let mut servos = supervisor.servos.write().await;
+            for joint in joints {
+                if let Some(servo) = servos.get_mut(&joint.servo_id) {
+                    Self::apply_joint_defaults(servo.as_mut(), joint);
+                }
+            }
+        }
 
-        for id in actuator_list {
-            supervisor.add_servo(*id, FeetechActuatorType::Sts3215).await?;
+        if config.calibration.startup != CalibrationStartup::Ignore {
+            match CalibrationStore::load(&config.calibration.store_path, config.serial()) {
+                Ok(store) => {
+                    let restore = config.calibration.startup == CalibrationStartup::Restore;
+                    store.verify(&mut *supervisor.servos.write().await, restore);
+                }
+                Err(e) => warn!("Skipping calibration check: {}", e),
+            }
         }
 
-        Ok(Self {
-            supervisor: Arc::new(RwLock::new(supervisor)),
-            command_rate: 50.0, // Default 50Hz
+        let estop = Arc::new(EStop::new());
+        estop.spawn_input_monitor(&config.estop);
+
+        let supervisor = Arc::new(RwLock::new(supervisor));
+        let actuator = Self {
+            calibrator: Calibrator::new(supervisor.clone()),
+            supervisor,
+            command_period: Arc::new(watch::channel(loop_period(config.control.command_rate_hz)?).0),
+            command_timing: Arc::new(std::sync::Mutex::new(LoopTiming::default())),
+            phase_lock: Arc::new(AtomicBool::new(config.control.phase_lock)),
+            joints: Arc::new(
+                joints
+                    .iter()
+                    .map(|joint| (joint.servo_id, JointDescriptor::from_config(joint, config.units)))
+                    .collect(),
+            ),
+            limit_policy: config.limit_policy,
+            limit_stats: Arc::new(LimitStats::default()),
+            watchdog: Arc::new(CommandWatchdog::new(&config.watchdog, joints)),
+            estop,
+            calibration: config.calibration.clone(),
+            calibration_groups: config.calibration_groups(),
+            robot_serial: config.serial().to_string(),
+            trajectory: Arc::new(std::sync::Mutex::new(TrajectoryQueue::new())),
             desired_positions: Arc::new(RwLock::new(HashMap::new())),
             desired_velocities: Arc::new(RwLock::new(HashMap::new())),
-            command_task_running: Arc::new(AtomicBool::new(false)),
-        })
+            command_task: Arc::new(std::sync::Mutex::new(None)),
+            scan: Arc::new(std::sync::Mutex::new(None)),
+            operations: None,
+        };
+        actuator.spawn_estop_task();
+        actuator.spawn_fault_latch_task(&config.estop).await;
+        actuator.spawn_servo_event_task(joints.clone()).await;
+        if config.liveness.discovery_interval_ms > 0 {
+            let supervisor = actuator.supervisor.read().await;
+            let missing = supervisor.missing_servos();
+            if !missing.is_empty() {
+                info!("Waiting for missing servos {:?} to appear on the bus", missing);
+            }
+            supervisor.spawn_discovery(Duration::from_millis(config.liveness.discovery_interval_ms));
+        }
+        Ok(actuator)
+    }
+
+    /// Writes a joint's configured PID gains and acceleration to its servo.
+    fn apply_joint_defaults(servo: &mut dyn FeetechActuator, joint: &JointConfig) {
+        if joint.kp.is_some() || joint.ki.is_some() || joint.kd.is_some() {
+            if let Err(e) = servo.set_pid(joint.kp, joint.ki, joint.kd) {
+                warn!("Failed to set default PID on joint {}: {}", joint.name, e);
+            }
+        }
+        if let Some(acceleration) = joint.acceleration {
+            if let Err(e) = servo.set_acceleration(acceleration) {
+                warn!("Failed to set default acceleration on joint {}: {}", joint.name, e);
+            }
+        }
+    }
+
+    /// Re-applies the joint configuration to servos that appear on the bus
+    /// after startup or come back after losing power.
+    async fn spawn_servo_event_task(&self, joints: Vec<JointConfig>) {
+        let supervisor = self.supervisor.clone();
+        let mut events = supervisor.read().await.subscribe_servo_events();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Missed {} servo events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if matches!(event, ServoEvent::ProtectionTripped { .. } | ServoEvent::StatusFault { .. }) {
+                    continue;
+                }
+                let Some(joint) = joints.iter().find(|joint| joint.servo_id == event.id()) else {
+                    continue;
+                };
+                let supervisor = supervisor.read().await;
+                let mut servos = supervisor.servos.write().await;
+                if let Some(servo) = servos.get_mut(&joint.servo_id) {
+                    info!("Re-applying configuration of joint {} after {:?}", joint.name, event);
+                    Self::apply_joint_defaults(servo.as_mut(), joint);
+                }
+            }
+        });
+    }
+
+    /// Joint descriptors sorted by actuator id, so clients can share the same
+    /// name, sign, offset and gear ratio tables.
+    pub fn joints(&self) -> Vec<JointDescriptor> {
+        let mut joints: Vec<_> = self.joints.values().cloned().collect();
+        joints.sort_by_key(|joint| joint.servo_id);
+        joints
+    }
+
+    pub fn joint_by_name(&self, name: &str) -> Option<&JointDescriptor> {
+        self.joints.values().find(|joint| joint.name == name)
+    }
+
+    pub fn limit_stats(&self) -> &LimitStats {
+        &self.limit_stats
+    }
+
+    /// Stops the command loop and the servo poll loop, and releases the bus.
+    pub async fn shutdown(&self) {
+        Self::stop_command_task(&self.command_task).await;
+        self.supervisor.read().await.shutdown().await;
+    }
+
+    /// Publishes calibration progress to `operations`.
+    pub fn with_operations(mut self, operations: Arc<dyn OperationSink>) -> Self {
+        self.calibrator.set_operations(operations.clone());
+        self.operations = Some(operations);
+        self
+    }
+
+    /// Scans the bus for servos in the background. Only one scan runs at a
+    /// time. EEPROM limits are only repaired when `options.repair` is set.
+    pub async fn start_scan(&self, options: ScanOptions) -> Result<ScanHandle> {
+        if options.ids.is_empty() || options.attempts == 0 {
+            return Err(ZBotError::InvalidArgument("A scan needs at least one id and one attempt".to_string()).into());
+        }
+        if let Some(id) = options.ids.iter().find(|&&id| id > MAX_SERVO_ID) {
+            return Err(ZBotError::OutOfRange(format!("Servo id {} is not in 0..={}", id, MAX_SERVO_ID)).into());
+        }
+        let bus = self.supervisor.read().await.bus.clone();
+        let mut scan = self.scan.lock().unwrap();
+        if let Some(running) = scan.as_ref().filter(|scan| !scan.is_finished()) {
+            return Err(ZBotError::InvalidArgument(format!("A bus scan is already running ({})", running.name)).into());
+        }
+        let handle = start_scan(bus, options, self.operations.clone());
+        *scan = Some(handle.clone());
+        Ok(handle)
+    }
+
+    /// The latest bus scan, running or finished.
+    pub fn scan(&self) -> Option<ScanHandle> {
+        self.scan.lock().unwrap().clone()
+    }
+
+    /// The latest calibration of a servo, running or finished.
+    pub fn calibration(&self, id: u8) -> Option<CalibrationHandle> {
+        self.calibrator.handle(id)
+    }
+
+    pub fn cancel_calibration(&self, id: u8) -> bool {
+        self.calibrator.cancel(id)
+    }
+
+    fn is_calibrating(&self, id: u8) -> bool {
+        self.calibrator.is_calibrating(id)
+    }
+
+    pub fn command_rate(&self) -> f64 {
+        1.0 / self.command_period.borrow().as_secs_f64()
+    }
+
+    /// Changes the command loop rate. Takes effect on the next tick.
+    pub fn set_command_rate(&self, rate_hz: f64) -> Result<()> {
+        let period = loop_period(rate_hz)?;
+        if self.command_period.send_replace(period) != period {
+            info!("Command rate set to {} Hz", rate_hz);
+        }
+        Ok(())
+    }
+
+    pub fn phase_lock(&self) -> bool {
+        self.phase_lock.load(Ordering::Relaxed)
+    }
+
+    /// Sends each command broadcast right after a telemetry read.
+    pub fn set_phase_lock(&self, enabled: bool) {
+        if self.phase_lock.swap(enabled, Ordering::Relaxed) != enabled {
+            info!("Phase lock {}", if enabled { "enabled" } else { "disabled" });
+        }
+    }
+
+    pub fn command_timing(&self) -> LoopTiming {
+        self.command_timing.lock().unwrap().clone()
+    }
+
+    pub async fn poll_rate(&self) -> f64 {
+        self.supervisor.read().await.poll_rate()
+    }
+
+    pub async fn set_poll_rate(&self, rate_hz: f64) -> Result<()> {
+        self.supervisor.read().await.set_poll_rate(rate_hz)
+    }
+
+    pub async fn poll_timing(&self) -> LoopTiming {
+        self.supervisor.read().await.poll_timing()
+    }
+
+    pub async fn supervisor_stats(&self) -> SupervisorStats {
+        self.supervisor.read().await.stats()
+    }
+
+    /// Online state and age of the last new reading of every joint, by
+    /// actuator id. Ages are on the monotonic clock.
+    pub async fn reading_ages(&self) -> Vec<ReadingAge> {
+        let supervisor = self.supervisor.read().await;
+        let now = Instant::now();
+        let mut ids: Vec<_> = self.joints.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id| {
+                let liveness = supervisor.liveness(id);
+                ReadingAge {
+                    actuator_id: id as u32,
+                    online: liveness.online,
+                    age_ms: liveness.age(now).map(|age| age.as_millis() as u64),
+                }
+            })
+            .collect()
+    }
+
+    /// Servos going online or offline.
+    pub async fn subscribe_liveness(&self) -> broadcast::Receiver<LivenessEvent> {
+        self.supervisor.read().await.subscribe_liveness()
+    }
+
+    /// Supervisor, command loop and safety metrics in the Prometheus text
+    /// format.
+    pub async fn prometheus_metrics(&self) -> String {
+        let mut metrics = PrometheusWriter::new();
+        metrics.supervisor(&self.supervisor_stats().await);
+        metrics.loop_timing("command", "command loop", &self.command_timing());
+        metrics.counter(
+            "zbot_commands_clamped_total",
+            "Commands moved inside a joint limit",
+            self.limit_stats.clamped.load(Ordering::Relaxed),
+        );
+        metrics.counter(
+            "zbot_commands_rejected_total",
+            "Commands dropped for exceeding a joint limit",
+            self.limit_stats.rejected.load(Ordering::Relaxed),
+        );
+        metrics.counter(
+            "zbot_commands_step_limited_total",
+            "Command ticks held back by max_step_deg",
+            self.limit_stats.step_limited.load(Ordering::Relaxed),
+        );
+        metrics.gauge(
+            "zbot_estop_latched",
+            "1 while the e-stop is latched",
+            if self.estop.is_latched() { 1.0 } else { 0.0 },
+        );
+        metrics.gauge(
+            "zbot_watchdog_tripped",
+            "1 while the command watchdog is tripped",
+            if self.watchdog_state() == WatchdogState::Armed { 0.0 } else { 1.0 },
+        );
+        metrics.gauge(
+            "zbot_trajectory_remaining_seconds",
+            "Time until the queued trajectory has played",
+            self.trajectory_status().remaining_s,
+        );
+        metrics.finish()
+    }
+
+    /// Queues a joint-space trajectory, interpolated by the command loop at
+    /// the command rate. A direct command to a joint takes it off the
+    /// trajectory. Returns notes on clamped waypoints; under
+    /// `LimitPolicy::Reject` any violation rejects the whole trajectory.
+    pub async fn submit_trajectory(&self, trajectory: &Trajectory) -> Result<Vec<String>> {
+        if let Some(error) = self.estop_error() {
+            return Err(error.into());
+        }
+        if trajectory.waypoints.is_empty() {
+            return Err(ZBotError::InvalidArgument("Trajectory has no waypoints".to_string()).into());
+        }
+
+        let mut waypoints: Vec<ServoWaypoint> = Vec::with_capacity(trajectory.waypoints.len());
+        let mut violations = Vec::new();
+        let mut rejected = false;
+        let mut previous_time = None;
+        for (index, waypoint) in trajectory.waypoints.iter().enumerate() {
+            let time = waypoint.time_from_start_s;
+            if !(time.is_finite() && time >= 0.0) || previous_time.is_some_and(|previous| time <= previous) {
+                return Err(ZBotError::InvalidArgument(format!(
+                    "Waypoint {}: time_from_start_s must be non-negative and increasing",
+                    index
+                ))
+                .into());
+            }
+            previous_time = Some(time);
+
+            let mut targets = HashMap::new();
+            for (&actuator_id, &position) in &waypoint.positions {
+                let id = Self::servo_id(actuator_id)?;
+                let joint = self
+                    .joints
+                    .get(&id)
+                    .ok_or_else(|| ZBotError::InvalidArgument(format!("No joint configured for actuator {}", actuator_id)))?;
+                if self.is_calibrating(id) {
+                    return Err(ZBotError::InvalidArgument(format!("{} is calibrating", joint.name)).into());
+                }
+                let velocity = waypoint.velocities.get(&actuator_id).map(|&velocity| joint.to_servo_velocity(velocity));
+                if velocity.is_some_and(|velocity| !velocity.is_finite()) {
+                    return Err(ZBotError::InvalidArgument(format!(
+                        "Waypoint {}: {} velocity is not finite",
+                        index, joint.name
+                    ))
+                    .into());
+                }
+
+                let mut notes = Vec::new();
+                let requested = joint.to_servo_position(position);
+                let position = self.check_limit("position", requested, joint.limits.clamp_position(requested), &mut notes);
+                violations.extend(notes.into_iter().map(|note| format!("waypoint {} {}: {}", index, joint.name, note)));
+                match position {
+                    Some(position) => {
+                        targets.insert(id, (position, velocity));
+                    }
+                    None => rejected = true,
+                }
+            }
+            waypoints.push((time, targets));
+        }
+        if rejected {
+            return Err(ZBotError::OutOfRange(format!("Trajectory rejected: {}", violations.join(", "))).into());
+        }
+
+        // Joints not already on the trajectory start from their last command,
+        // or where they are if they have none.
+        let mut start = self.desired_positions.read().await.clone();
+        {
+            let supervisor = self.supervisor.read().await;
+            let servos = supervisor.servos.read().await;
+            for (&id, servo) in servos.iter() {
+                start.entry(id).or_insert_with(|| servo.info().position_deg);
+            }
+        }
+
+        self.trajectory.lock().unwrap().submit(
+            Instant::now(),
+            &waypoints,
+            trajectory.interpolation,
+            trajectory.mode,
+            |id| start.get(&id).copied(),
+        );
+        self.watchdog.feed();
+        self.start_command_task();
+        Ok(violations)
+    }
+
+    pub fn trajectory_status(&self) -> TrajectoryStatus {
+        self.trajectory.lock().unwrap().status(Instant::now())
+    }
+
+    /// Stops the trajectory; joints hold their last setpoint.
+    pub fn cancel_trajectory(&self) -> bool {
+        self.trajectory.lock().unwrap().clear()
+    }
+
+    /// Shared e-stop, for fault detectors and other services.
+    pub fn estop(&self) -> &Arc<EStop> {
+        &self.estop
+    }
+
+    /// Stops the command task and drops all torque whenever the e-stop latches.
+    fn spawn_estop_task(&self) {
+        let supervisor = self.supervisor.clone();
+        let desired_positions = self.desired_positions.clone();
+        let desired_velocities = self.desired_velocities.clone();
+        let command_task = self.command_task.clone();
+        let calibrator = self.calibrator.clone();
+        let trajectory = self.trajectory.clone();
+        let mut state = self.estop.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let latched = matches!(*state.borrow_and_update(), EStopState::Latched { .. });
+                if latched {
+                    Self::stop_command_task(&command_task).await;
+                    calibrator.cancel_all();
+                    trajectory.lock().unwrap().clear();
+                    desired_positions.write().await.clear();
+                    desired_velocities.write().await.clear();
+                    match supervisor.write().await.disable_all_torque().await {
+                        Ok(()) => info!("E-stop: torque disabled on all servos"),
+                        Err(e) => error!("E-stop: failed to disable torque: {}", e),
+                    }
+                }
+                if state.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Latches the e-stop on the servo faults selected in `[estop]`.
+    async fn spawn_fault_latch_task(&self, config: &EStopConfig) {
+        if !config.latch_on_protection_trip && config.latch_on_status_faults.is_empty() {
+            return;
+        }
+        let supervisor = self.supervisor.clone();
+        let estop = self.estop.clone();
+        let calibrator = self.calibrator.clone();
+        let latch_on_protection_trip = config.latch_on_protection_trip;
+        let latch_on_status_faults = config.latch_on_status_faults.clone();
+        let mut events = supervisor.read().await.subscribe_servo_events();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Missed {} servo events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let reason = match event {
+                    ServoEvent::ProtectionTripped { id } if latch_on_protection_trip => {
+                        let faults = supervisor.read().await.protection.lock().unwrap().faults(id);
+                        format!("servo {} protection tripped: {}", id, faults.join(", "))
+                    }
+                    ServoEvent::StatusFault { id } if !calibrator.is_calibrating(id) => {
+                        let supervisor = supervisor.read().await;
+                        let faults: Vec<String> = supervisor
+                            .servos
+                            .read()
+                            .await
+                            .get(&id)
+                            .map(|servo| servo.info().faults)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter(|fault| latch_on_status_faults.contains(fault))
+                            .collect();
+                        if faults.is_empty() {
+                            continue;
+                        }
+                        format!("servo {} reports {}", id, faults.join(", "))
+                    }
+                    _ => continue,
+                };
+                estop.trigger(EStopSource::Fault, reason);
+            }
+        });
+    }
+
+    fn estop_error(&self) -> Option<ZBotError> {
+        match self.estop.state() {
+            EStopState::Clear => None,
+            EStopState::Latched { source, reason, .. } => Some(ZBotError::EStopLatched(format!(
+                "E-stop latched by {:?}: {}",
+                source, reason
+            ))),
+        }
+    }
+
+    pub fn watchdog_state(&self) -> WatchdogState {
+        self.watchdog.state()
+    }
+
+    pub fn subscribe_watchdog(&self) -> watch::Receiver<WatchdogState> {
+        self.watchdog.subscribe()
+    }
+
+    /// Replaces the desired pose of every commanded joint once the client has
+    /// gone silent.
+    async fn apply_watchdog_action(
+        watchdog: &CommandWatchdog,
+        supervisor: &RwLock<FeetechSupervisor>,
+        desired_positions: &RwLock<HashMap<u8, f32>>,
+        desired_velocities: &RwLock<HashMap<u8, f32>>,
+    ) {
+        let mut positions = desired_positions.write().await;
+        let mut velocities = desired_velocities.write().await;
+        let mut supervisor = supervisor.write().await;
+        let ids: BTreeSet<u8> = positions.keys().chain(velocities.keys()).copied().collect();
+
+        if watchdog.action() == WatchdogAction::DisableTorque {
+            for id in ids {
+                if let Err(e) = supervisor.disable_torque(id).await {
+                    error!("Watchdog failed to disable torque on servo {}: {}", id, e);
+                }
+            }
+            positions.clear();
+            velocities.clear();
+            return;
+        }
+
+        let servos = supervisor.servos.read().await;
+        for id in ids {
+            let safe_position = watchdog.safe_pose().get(&id).copied();
+            match (watchdog.action(), safe_position) {
+                (WatchdogAction::SafePose, Some(position)) => {
+                    positions.insert(id, position);
+                    velocities.insert(id, watchdog.safe_pose_speed_deg_per_s());
+                }
+                _ => {
+                    if let Some(servo) = servos.get(&id) {
+                        positions.insert(id, servo.info().position_deg);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies the limit policy to a servo-space value. Returns the value to
+    /// send, or `None` if the command must be dropped, and records why.
+    fn check_limit(&self, field: &str, requested: f32, allowed: f32, violations: &mut Vec<String>) -> Option<f32> {
+        if !requested.is_finite() {
+            self.limit_stats.rejected.fetch_add(1, Ordering::Relaxed);
+            violations.push(format!("{} is not finite", field));
+            return None;
+        }
+        if requested == allowed {
+            return Some(requested);
+        }
+        match self.limit_policy {
+            LimitPolicy::Clamp => {
+                self.limit_stats.clamped.fetch_add(1, Ordering::Relaxed);
+                violations.push(format!("{} {:.2} clamped to {:.2} (servo degrees)", field, requested, allowed));
+                Some(allowed)
+            }
+            LimitPolicy::Reject => {
+                self.limit_stats.rejected.fetch_add(1, Ordering::Relaxed);
+                violations.push(format!("{} {:.2} exceeds limit {:.2} (servo degrees)", field, requested, allowed));
+                None
+            }
+        }
+    }
+
+    /// Stops the command loop and waits until it has, so a loop started
+    /// afterwards never runs alongside it.
+    async fn stop_command_task(command_task: &std::sync::Mutex<Option<JoinHandle<()>>>) {
+        let Some(task) = command_task.lock().unwrap().take() else {
+            return;
+        };
+        task.abort();
+        let _ = task.await;
     }
 
     fn start_command_task(&self) {
-        if self.command_task_running.load(Ordering::SeqCst) {
+        let mut command_task = self.command_task.lock().unwrap();
+        if command_task.as_ref().is_some_and(|task| !task.is_finished()) {
             return; // Task already running
         }
 
         let supervisor = self.supervisor.clone();
+        let joints = self.joints.clone();
+        let limit_stats = self.limit_stats.clone();
+        let watchdog = self.watchdog.clone();
+        let estop = self.estop.clone();
+        let trajectory = self.trajectory.clone();
         let desired_positions = self.desired_positions.clone();
         let desired_velocities = self.desired_velocities.clone();
-        let command_task_running = self.command_task_running.clone();
-        let period = Duration::from_secs_f64(1.0 / self.command_rate);
+        let mut command_period = self.command_period.subscribe();
+        let command_timing = self.command_timing.clone();
+        let phase_lock = self.phase_lock.clone();
 
-        command_task_running.store(true, Ordering::SeqCst);
+        *command_task = Some(tokio::spawn(async move {
+            let mut timer = LoopTimer::new(*command_period.borrow_and_update(), command_timing);
+            let telemetry = supervisor.read().await.telemetry_notify();
+            // Last position sent to each servo, for step limiting.
+            let mut last_sent: HashMap<u8, f32> = HashMap::new();
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(period);
+            loop {
+                if command_period.has_changed().unwrap_or(false) {
+                    timer.set_period(*command_period.borrow_and_update());
+                }
+                timer.tick().await;
+                if phase_lock.load(Ordering::Relaxed) {
+                    // Bounded, so a stalled poll loop cannot stall the watchdog.
+                    let _ = tokio::time::timeout(timer.period(), telemetry.notified()).await;
+                }
+                if estop.is_latched() {
+                    break;
+                }
 
-            while command_task_running.load(Ordering::SeqCst) {
-                interval.tick().await;
+                {
+                    // Sampled under the desired-position lock, so a direct
+                    // command that takes a joint off the trajectory wins.
+                    let mut positions = desired_positions.write().await;
+                    let mut velocities = desired_velocities.write().await;
+                    let setpoints = trajectory.lock().unwrap().advance(Instant::now());
+                    if !setpoints.is_empty() {
+                        // A playing trajectory counts as a live client.
+                        watchdog.feed();
+                    }
+                    for (id, position) in setpoints {
+                        positions.insert(id, position);
+                        // The setpoints pace the motion, so the speed is left unlimited.
+                        velocities.insert(id, 0.0);
+                    }
+                }
+
+                if watchdog.check() {
+                    Self::apply_watchdog_action(&watchdog, &supervisor, &desired_positions, &desired_velocities).await;
+                }
                 
-                let positions = desired_positions.read().await.clone();
-                let velocities = desired_velocities.read().await.clone();
+                let mut positions = desired_positions.read().await.clone();
+                let mut velocities = desired_velocities.read().await.clone();
 
-                if !positions.is_empty() || !velocities.is_empty() {
-                    if let Err(e) = supervisor.write().await.move_actuators(&positions, &velocities).await {
-                        warn!("Failed to command actuators: {}", e);
+                if positions.is_empty() && velocities.is_empty() {
+                    continue;
+                }
+
+                let mut supervisor = supervisor.write().await;
+                {
+                    let servos = supervisor.servos.read().await;
+                    for (id, position) in positions.iter_mut() {
+                        let (Some(joint), Some(servo)) = (joints.get(id), servos.get(id)) else {
+                            continue;
+                        };
+                        let info = servo.info();
+                        if !info.torque_enabled {
+                            // Start from wherever the servo was moved to by hand.
+                            last_sent.remove(id);
+                            continue;
+                        }
+                        // Trajectory samples can overshoot their waypoints
+                        // and watchdog poses bypass command checks, so every
+                        // setpoint is held inside the joint limits here.
+                        let clamped = joint.limits.clamp_position(*position);
+                        if clamped != *position {
+                            limit_stats.clamped.fetch_add(1, Ordering::Relaxed);
+                            *position = clamped;
+                        }
+                        let from = last_sent.get(id).copied().unwrap_or(info.position_deg);
+                        let limited = joint.limits.limit_step(from, *position);
+                        if limited != *position {
+                            limit_stats.step_limited.fetch_add(1, Ordering::Relaxed);
+                        }
+                        *position = limited;
+                        last_sent.insert(*id, limited);
+
+                        if joint.limits.max_velocity_deg_per_s.is_some() {
+                            let speed = velocities.entry(*id).or_insert(0.0);
+                            *speed = joint.limits.clamp_speed(*speed);
+                        }
                     }
                 }
+
+                if let Err(e) = supervisor.move_actuators(&positions, &velocities).await {
+                    warn!("Failed to command actuators: {}", e);
+                }
             }
-        });
+        }));
     }
 
-    fn to_action_response<E: std::fmt::Display>(result: Result<(), E>) -> ActionResponse {
+    fn configure_estop(&self, config: &ConfigureActuatorRequest) -> Result<()> {
+        let other_fields = [
+            ("kp", config.kp.is_some()),
+            ("kd", config.kd.is_some()),
+            ("ki", config.ki.is_some()),
+            ("max_torque", config.max_torque.is_some()),
+            ("protective_torque", config.protective_torque.is_some()),
+            ("protection_time", config.protection_time.is_some()),
+            ("new_actuator_id", config.new_actuator_id.is_some()),
+            ("zero_position", config.zero_position.is_some()),
+            ("acceleration", config.acceleration.is_some()),
+        ];
+        let rejected: Vec<&str> = other_fields.iter().filter(|(_, set)| *set).map(|(name, _)| *name).collect();
+        if !rejected.is_empty() {
+            return Err(ZBotError::InvalidArgument(format!(
+                "Actuator {} is the e-stop and only takes torque_enabled, got {}",
+                ESTOP_ACTUATOR_ID,
+                rejected.join(", ")
+            ))
+            .into());
+        }
+        match config.torque_enabled {
+            Some(false) => {
+                self.estop.trigger(EStopSource::Service, "requested by client");
+            }
+            Some(true) => {
+                self.estop.clear(EStopSource::Service);
+            }
+            None => {
+                return Err(ZBotError::InvalidArgument(format!(
+                    "Actuator {} is the e-stop; set torque_enabled to latch or clear it",
+                    ESTOP_ACTUATOR_ID
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn to_action_response(result: Result<()>) -> ActionResponse {
         match result {
             Ok(()) => ActionResponse {
                 success: true,
@@ -76,10 +812,7 @@ impl ZBotActuator {
             },
             Err(e) => ActionResponse {
                 success: false,
-                error: Some(KosError {
-                    code: ErrorCode::HardwareFailure as i32,
-                    message: e.to_string(),
-                }),
+                error: Some(to_kos_error(&e)),
             },
         }
     }
@@ -90,40 +823,113 @@ impl ZBotActuator {
             error: None,
         }
     }
+
+    /// The servo id of an actuator id. KOS ids are 32 bits wide; truncating
+    /// them would address another servo, or all of them.
+    fn servo_id(actuator_id: u32) -> Result<u8, ZBotError> {
+        u8::try_from(actuator_id)
+            .map_err(|_| ZBotError::OutOfRange(format!("Actuator id {} is out of range", actuator_id)))
+    }
 }
 
 
 #[tonic::async_trait]
 impl Actuator for ZBotActuator {
     async fn command_actuators(&self, commands: Vec<ActuatorCommand>) -> Result<Vec<ActionResult>> {
+        if let Some(error) = self.estop_error() {
+            return Ok(commands
+                .into_iter()
+                .map(|cmd| ActionResult {
+                    actuator_id: cmd.actuator_id,
+                    success: false,
+                    error: Some(error.clone().into()),
+                })
+                .collect());
+        }
+
+        self.watchdog.feed();
+
         let mut results = Vec::new();
         let mut positions = self.desired_positions.write().await;
         let mut velocities = self.desired_velocities.write().await;
 
         for cmd in commands {
+            let id = match Self::servo_id(cmd.actuator_id) {
+                Ok(id) => id,
+                Err(e) => {
+                    results.push(ActionResult {
+                        actuator_id: cmd.actuator_id,
+                        success: false,
+                        error: Some(e.into()),
+                    });
+                    continue;
+                }
+            };
+            let Some(joint) = self.joints.get(&id) else {
+                results.push(ActionResult {
+                    actuator_id: cmd.actuator_id,
+                    success: false,
+                    error: Some(ZBotError::InvalidArgument(format!("No joint configured for actuator {}", cmd.actuator_id)).into()),
+                });
+                continue;
+            };
+            if self.is_calibrating(id) {
+                results.push(ActionResult {
+                    actuator_id: cmd.actuator_id,
+                    success: false,
+                    error: Some(ZBotError::InvalidArgument(format!("{} is calibrating", joint.name)).into()),
+                });
+                continue;
+            }
+
+            let mut violations = Vec::new();
+            let position = cmd.position.map(|position| {
+                let requested = joint.to_servo_position(position);
+                self.check_limit("position", requested, joint.limits.clamp_position(requested), &mut violations)
+            });
+            // The broadcast frame carries a speed limit, so only the magnitude matters.
+            let velocity = cmd.velocity.map(|velocity| {
+                let requested = joint.to_servo_velocity(velocity).abs();
+                let allowed = joint.limits.max_velocity_deg_per_s.map_or(requested, |max| requested.min(max));
+                self.check_limit("velocity", requested, allowed, &mut violations)
+            });
+
+            if position == Some(None) || velocity == Some(None) {
+                results.push(ActionResult {
+                    actuator_id: cmd.actuator_id,
+                    success: false,
+                    error: Some(ZBotError::OutOfRange(format!("{}: {}", joint.name, violations.join(", "))).into()),
+                });
+                continue;
+            }
+
+            self.trajectory.lock().unwrap().remove(id);
+
             // Track if we should remove this actuator from continuous command
             let mut remove_actuator = true;
             
-            if let Some(position) = cmd.position {
-                positions.insert(cmd.actuator_id as u8, position as f32);
+            if let Some(Some(position)) = position {
+                positions.insert(id, position);
                 remove_actuator = false;
             }
             
-            if let Some(velocity) = cmd.velocity {
-                velocities.insert(cmd.actuator_id as u8, velocity as f32);
+            if let Some(Some(velocity)) = velocity {
+                velocities.insert(id, velocity);
                 remove_actuator = false;
             }
 
             // If neither position nor velocity was specified, remove the actuator from continuous command
             if remove_actuator {
-                positions.remove(&(cmd.actuator_id as u8));
-                velocities.remove(&(cmd.actuator_id as u8));
+                positions.remove(&id);
+                velocities.remove(&id);
             }
 
+            // Clamped commands still succeed, but the client is told about it.
             results.push(ActionResult {
                 actuator_id: cmd.actuator_id,
                 success: true,
-                error: None,
+                error: (!violations.is_empty())
+                    .then(|| ZBotError::OutOfRange(format!("{}: {}", joint.name, violations.join(", "))).into()),
             });
         }
 
@@ -135,9 +941,31 @@ impl Actuator for ZBotActuator {
         Ok(results)
     }
 
+    /// Applies the given fields to one servo. `ESTOP_ACTUATOR_ID` is the
+    /// e-stop: `torque_enabled = false` latches it, `true` clears it, and any
+    /// other field is rejected.
     async fn configure_actuator(&self, config: ConfigureActuatorRequest) -> Result<ActionResponse> {
+        if config.actuator_id == ESTOP_ACTUATOR_ID {
+            return Ok(Self::to_action_response(self.configure_estop(&config)));
+        }
+        if config.torque_enabled == Some(true) {
+            if let Some(error) = self.estop_error() {
+                return Ok(ActionResponse {
+                    success: false,
+                    error: Some(error.into()),
+                });
+            }
+        }
+
+        let id = match Self::servo_id(config.actuator_id) {
+            Ok(id) => id,
+            Err(e) => return Ok(Self::to_action_response(Err(e.into()))),
+        };
+        if config.torque_enabled == Some(false) && self.cancel_calibration(id) {
+            info!("Calibration of servo {} cancelled by torque off", id);
+        }
+
         let mut supervisor = self.supervisor.write().await;
-        let id = config.actuator_id as u8;
         let mut errors = Vec::new();
         debug!("configure_actuator [id]:{}", id);
         
@@ -169,7 +997,7 @@ impl Actuator for ZBotActuator {
                     }
                 }
             } else {
-                return Ok(Self::to_action_response(Err(eyre::eyre!("Servo not found"))));
+                return Ok(Self::to_action_response(Err(ZBotError::ServoNotFound(id).into())));
             }
         } // <-- servo lock dropoed>
     
@@ -185,7 +1013,23 @@ impl Actuator for ZBotActuator {
         }
         
         if let Some(new_actuator_id) = config.new_actuator_id {
-            let result = supervisor.change_id(id, new_actuator_id as u8).await;
+            let result = match u8::try_from(new_actuator_id) {
+                Ok(new_id) if self.is_calibrating(id) => Err(ZBotError::InvalidArgument(format!(
+                    "Servo {} is calibrating, not moving it to id {}",
+                    id, new_id
+                ))
+                .into()),
+                Ok(new_id) => {
+                    // Neither id should keep a target meant for the other servo.
+                    for id in [id, new_id] {
+                        self.trajectory.lock().unwrap().remove(id);
+                        self.desired_positions.write().await.remove(&id);
+                        self.desired_velocities.write().await.remove(&id);
+                    }
+                    supervisor.change_id(id, new_id).await
+                }
+                Err(_) => Err(ZBotError::OutOfRange(format!("Servo id {} is out of range", new_actuator_id)).into()),
+            };
             if let Err(e) = result {
                 errors.push(e);
             }
@@ -205,21 +1049,55 @@ impl Actuator for ZBotActuator {
     ) -> Result<Vec<ActuatorStateResponse>> {
         let supervisor = self.supervisor.read().await;
         let servos = supervisor.servos.read().await;
+        let protection = supervisor.protection.lock().unwrap();
+        let now = Instant::now();
 
         let mut states = Vec::new();
         for id in actuator_ids {
-            if let Some(servo) = servos.get(&(id as u8)) {
+            let servo_id = match Self::servo_id(id) {
+                Ok(servo_id) => servo_id,
+                Err(e) => {
+                    states.push(ActuatorStateResponse {
+                        actuator_id: id,
+                        online: false,
+                        position: None,
+                        velocity: None,
+                        torque: None,
+                        temperature: None,
+                        voltage: None,
+                        current: None,
+                        faults: vec![e.to_string()],
+                    });
+                    continue;
+                }
+            };
+            let servo = servos.get(&servo_id);
+            let joint = self.joints.get(&servo_id);
+            if let (Some(servo), Some(joint)) = (servo, joint) {
                 let info = servo.info();
+                let mut faults = info.faults;
+                faults.extend(protection.faults(servo_id));
+                // The state message has no field for the sample age, so it
+                // rides along in `faults` in a fixed form for controllers to
+                // parse. Readings old enough to take the servo offline are
+                // also flagged as stale.
+                if let Some(at) = info.last_reading_at {
+                    let age_ms = now.saturating_duration_since(at).as_millis();
+                    faults.push(format!("{}{}", READING_AGE_FAULT_PREFIX, age_ms));
+                    if !info.online {
+                        faults.push(format!("Stale reading: {} ms old", age_ms));
+                    }
+                }
                 states.push(ActuatorStateResponse {
                     actuator_id: id,
                     online: info.online,
-                    position: Some(info.position_deg as f64),
-                    velocity: Some(info.speed_deg_per_s as f64),
-                    torque: Some(info.load_percent as f64),
+                    position: Some(joint.to_joint_position(info.position_deg)),
+                    velocity: Some(joint.to_joint_velocity(info.speed_deg_per_s)),
+                    torque: Some(joint.to_joint_load(info.torque_nm)),
                     temperature: Some(info.temperature_c as f64),
                     voltage: Some(info.voltage_v),
-                    current: Some(info.current_ma), // Convert mA to A
-                    faults: info.faults,
+                    current: Some(info.current_ma / 1000.0), // Convert mA to A
+                    faults,
                 });
             } else {
                 states.push(ActuatorStateResponse {
@@ -239,7 +1117,132 @@ impl Actuator for ZBotActuator {
         Ok(states)
     }
 
-    async fn calibrate_actuator(&self, _request: CalibrateActuatorRequest) -> Result<Operation> {
-        Ok(Operation::default())
+    /// Starts end-stop calibration in the background. `calibration_speed` is
+    /// in servo deg/s and `threshold_current` in mA; zero keeps the configured
+    /// default. Progress is published as the operation metadata. The
+    /// broadcast id calibrates every joint by `calibration_group` and saves
+    /// the results to the calibration store.
+    async fn calibrate_actuator(&self, request: CalibrateActuatorRequest) -> Result<Operation> {
+        let id = Self::servo_id(request.actuator_id)?;
+        if let Some(error) = self.estop_error() {
+            return Err(error.into());
+        }
+        if id != BROADCAST_ID && !self.joints.contains_key(&id) {
+            return Err(ZBotError::InvalidArgument(format!("No joint configured for actuator {}", id)).into());
+        }
+
+        let mut config = self.calibration.clone();
+        if request.calibration_speed > 0.0 {
+            config.speed_deg_per_s = request.calibration_speed as f32;
+        }
+        if request.threshold_current > 0.0 {
+            config.current_threshold_ma = request.threshold_current;
+        }
+
+        if id == BROADCAST_ID {
+            let joint_names = self
+                .joints
+                .values()
+                .map(|joint| (joint.servo_id, joint.name.clone()))
+                .collect();
+            if let Some(id) = self.joints.keys().find(|&&id| self.is_calibrating(id)) {
+                return Err(ZBotError::InvalidArgument(format!("Servo {} is already calibrating", id)).into());
+            }
+            // Keep the command loop from fighting the calibration.
+            self.trajectory.lock().unwrap().clear();
+            self.desired_positions.write().await.clear();
+            self.desired_velocities.write().await.clear();
+            let (name, progress) = self.calibrator.start_batch(
+                self.calibration_groups.clone(),
+                &config,
+                joint_names,
+                &self.robot_serial,
+            )?;
+            let operation = progress.borrow().to_operation(&name);
+            return Ok(operation);
+        }
+
+        self.trajectory.lock().unwrap().remove(id);
+        self.desired_positions.write().await.remove(&id);
+        self.desired_velocities.write().await.remove(&id);
+        let (handle, _) = self.calibrator.start(id, &config).await?;
+        Ok(handle.progress().to_operation(&handle.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::feetech_sim::SimulatedBus;
+
+    const CONFIG: &str = r#"
+        name = "sim-test"
+        units = "degrees"
+        [liveness]
+        discovery_interval_ms = 0
+        [watchdog]
+        timeout_ms = 0
+        [calibration]
+        startup = "ignore"
+        [servo_bus]
+        type = "simulated"
+        [[joints]]
+        name = "elbow"
+        servo_id = 1
+        [[joints]]
+        name = "wrist"
+        servo_id = 2
+    "#;
+
+    /// Polls `f` until it returns `Some` or `timeout` passes.
+    async fn wait_for<T, F: std::future::Future<Output = Option<T>>>(timeout: Duration, f: impl Fn() -> F) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(value) = f().await {
+                return Some(value);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        None
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn actuator_moves_servos_to_commanded_positions() {
+        let bus = Arc::new(SimulatedBus::with_servos(&[1, 2]));
+        let config = RobotConfig::from_toml(CONFIG).unwrap();
+        let actuator = ZBotActuator::new(bus.clone(), &config).await.unwrap();
+
+        let response = actuator
+            .configure_actuator(ConfigureActuatorRequest {
+                actuator_id: 1,
+                torque_enabled: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(bus.with_servo(1, |servo| servo.torque_enabled()), Some(true));
+
+        let results = actuator
+            .command_actuators(vec![ActuatorCommand {
+                actuator_id: 1,
+                position: Some(45.0),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+        assert!(results[0].success, "{:?}", results[0].error);
+
+        let position = wait_for(Duration::from_secs(2), || async {
+            let state = actuator.get_actuators_state(vec![1]).await.unwrap().remove(0);
+            state.position.filter(|position| (position - 45.0).abs() < 1.0)
+        })
+        .await;
+        assert!(position.is_some(), "servo 1 did not reach 45 deg");
+        // The other joint was never commanded and stays where it was.
+        let state = actuator.get_actuators_state(vec![2]).await.unwrap().remove(0);
+        assert!(state.online);
+        assert!(state.position.unwrap().abs() < 1.0);
+        actuator.shutdown().await;
     }
 }
@@ -0,0 +1,225 @@
+use crate::actuator::ZBotActuator;
+use crate::error::{error_code, ZBotError};
+use crate::firmware::feetech_scan::ScanOptions;
+use crate::firmware::loop_timing::{loop_period, LoopTiming};
+use crate::http::{serve, Request, Response};
+use crate::scan::{ScanHandle, ScanProgress};
+use crate::trajectory::{Trajectory, TrajectoryStatus};
+use eyre::Result;
+use kos::kos_proto::common::ErrorCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+
+/// Port of the control API when a client is only given the runtime's host.
+pub const DEFAULT_API_PORT: u16 = 9103;
+
+/// Reply to the `/scan` routes. `name` is also the operation name in the
+/// KOS operations service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanOperation {
+    pub name: String,
+    pub progress: ScanProgress,
+}
+
+impl From<&ScanHandle> for ScanOperation {
+    fn from(handle: &ScanHandle) -> Self {
+        Self {
+            name: handle.name.clone(),
+            progress: handle.progress(),
+        }
+    }
+}
+
+/// Body of every failed API request.
+#[derive(Debug, Serialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+/// Reply to `POST /trajectory`.
+#[derive(Debug, Serialize)]
+struct TrajectoryAccepted {
+    /// Waypoints clamped to joint limits.
+    violations: Vec<String>,
+    status: TrajectoryStatus,
+}
+
+/// Body of `POST /control`. Fields left out keep their value.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControlUpdate {
+    command_rate_hz: Option<f64>,
+    poll_rate_hz: Option<f64>,
+    phase_lock: Option<bool>,
+}
+
+/// Reply to `GET` and `POST /control`.
+#[derive(Debug, Serialize)]
+struct ControlState {
+    command_rate_hz: f64,
+    poll_rate_hz: f64,
+    phase_lock: bool,
+    command_timing: LoopTiming,
+    poll_timing: LoopTiming,
+}
+
+impl ControlState {
+    async fn read(actuator: &ZBotActuator) -> Self {
+        Self {
+            command_rate_hz: actuator.command_rate(),
+            poll_rate_hz: actuator.poll_rate().await,
+            phase_lock: actuator.phase_lock(),
+            command_timing: actuator.command_timing(),
+            poll_timing: actuator.poll_timing().await,
+        }
+    }
+}
+
+/// Serves the JSON control API on `listen`, for what the KOS actuator
+/// service has no messages for. Returns once the socket is bound.
+///
+/// - `GET /joints`: every joint descriptor, as `ZBotActuator::joints`.
+/// - `GET /joints/<name>`: one joint descriptor.
+/// - `GET /readings`: online state and age of the last reading of every
+///   joint, see `ZBotActuator::reading_ages`.
+/// - `POST /trajectory`: queues a JSON `Trajectory`, see
+///   `ZBotActuator::submit_trajectory`.
+/// - `GET /trajectory`: the `TrajectoryStatus`.
+/// - `DELETE /trajectory`: stops the trajectory; joints hold their last
+///   setpoint.
+/// - `GET /control`: loop rates, phase lock and loop timing.
+/// - `POST /control`: sets any of `command_rate_hz`, `poll_rate_hz` and
+///   `phase_lock`. Nothing changes unless every value is valid.
+/// - `POST /scan`: starts a bus scan with the JSON `ScanOptions` in the
+///   body, all defaults when empty. See `ZBotActuator::start_scan`.
+/// - `GET /scan`: the latest scan, running or finished.
+/// - `DELETE /scan`: cancels the running scan.
+pub async fn serve_api(listen: &str, actuator: Arc<ZBotActuator>) -> Result<()> {
+    serve(listen, "control API", move |request| {
+        let actuator = actuator.clone();
+        async move { route(request, &actuator).await }
+    })
+    .await?;
+    info!("Serving the control API on http://{}", listen);
+    Ok(())
+}
+
+async fn route(request: Request, actuator: &ZBotActuator) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/joints") => Response::json("200 OK", &actuator.joints()),
+        ("GET", path) if path.starts_with("/joints/") => match actuator.joint_by_name(&path["/joints/".len()..]) {
+            Some(joint) => Response::json("200 OK", joint),
+            None => Response::text("404 Not Found", "No such joint\n"),
+        },
+        (_, path) if path.starts_with("/joints") => {
+            Response::text("405 Method Not Allowed", "Only GET is supported\n")
+        }
+        ("GET", "/readings") => Response::json("200 OK", &actuator.reading_ages().await),
+        (_, "/readings") => Response::text("405 Method Not Allowed", "Only GET is supported\n"),
+        ("POST", "/trajectory") => {
+            let result = match parse::<Trajectory>(&request) {
+                Ok(trajectory) => actuator.submit_trajectory(&trajectory).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(violations) => Response::json(
+                    "200 OK",
+                    &TrajectoryAccepted {
+                        violations,
+                        status: actuator.trajectory_status(),
+                    },
+                ),
+                Err(e) => error_response(&e),
+            }
+        }
+        ("GET", "/trajectory") => Response::json("200 OK", &actuator.trajectory_status()),
+        ("DELETE", "/trajectory") => {
+            actuator.cancel_trajectory();
+            Response::json("200 OK", &actuator.trajectory_status())
+        }
+        (_, "/trajectory") => Response::text("405 Method Not Allowed", "Use GET, POST or DELETE\n"),
+        ("GET", "/control") => Response::json("200 OK", &ControlState::read(actuator).await),
+        ("POST", "/control") => {
+            let result = match parse::<ControlUpdate>(&request) {
+                Ok(update) => update_control(actuator, update).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => Response::json("200 OK", &ControlState::read(actuator).await),
+                Err(e) => error_response(&e),
+            }
+        }
+        (_, "/control") => Response::text("405 Method Not Allowed", "Use GET or POST\n"),
+        ("POST", "/scan") => {
+            let options = if request.body.is_empty() {
+                Ok(ScanOptions::default())
+            } else {
+                parse::<ScanOptions>(&request)
+            };
+            let result = match options {
+                Ok(options) => actuator.start_scan(options).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(handle) => Response::json("200 OK", &ScanOperation::from(&handle)),
+                Err(e) => error_response(&e),
+            }
+        }
+        ("GET" | "DELETE", "/scan") => match actuator.scan() {
+            Some(handle) => {
+                if request.method == "DELETE" {
+                    handle.cancel();
+                }
+                Response::json("200 OK", &ScanOperation::from(&handle))
+            }
+            None => Response::text("404 Not Found", "No scan has run\n"),
+        },
+        (_, "/scan") => Response::text("405 Method Not Allowed", "Use GET, POST or DELETE\n"),
+        _ => Response::text("404 Not Found", "Not found\n"),
+    }
+}
+
+async fn update_control(actuator: &ZBotActuator, update: ControlUpdate) -> Result<()> {
+    // Check both rates first, so a bad one leaves everything unchanged.
+    for rate_hz in [update.command_rate_hz, update.poll_rate_hz].into_iter().flatten() {
+        loop_period(rate_hz)?;
+    }
+    if let Some(rate_hz) = update.command_rate_hz {
+        actuator.set_command_rate(rate_hz)?;
+    }
+    if let Some(rate_hz) = update.poll_rate_hz {
+        actuator.set_poll_rate(rate_hz).await?;
+    }
+    if let Some(enabled) = update.phase_lock {
+        actuator.set_phase_lock(enabled);
+    }
+    Ok(())
+}
+
+fn parse<T: DeserializeOwned>(request: &Request) -> Result<T> {
+    serde_json::from_slice(&request.body)
+        .map_err(|e| ZBotError::InvalidArgument(format!("Invalid {} {} body: {}", request.method, request.path, e)).into())
+}
+
+/// Maps the `ErrorCode` of `error` onto an HTTP status.
+fn error_response(error: &eyre::Report) -> Response {
+    let code = error_code(error);
+    let status = match code {
+        ErrorCode::InvalidArgument => "400 Bad Request",
+        ErrorCode::Unauthorized => "403 Forbidden",
+        ErrorCode::NotImplemented => "501 Not Implemented",
+        ErrorCode::HardwareFailure => "503 Service Unavailable",
+        ErrorCode::Timeout => "504 Gateway Timeout",
+        _ => "500 Internal Server Error",
+    };
+    Response::json(
+        status,
+        &ApiError {
+            code: format!("{:?}", code),
+            message: error.to_string(),
+        },
+    )
+}
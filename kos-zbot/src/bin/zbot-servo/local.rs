@@ -0,0 +1,373 @@
+use crate::{EepromCheck, Outcome, Reading};
+use eyre::{eyre, Result};
+use kos_zbot::feetech::{AssemblyStep, FeetechActuator, FeetechSupervisor};
+use kos_zbot::feetech_eeprom::{read_eeprom, write_eeprom, EepromDifference, EepromImage, EepromProfile};
+use kos_zbot::feetech_scan::{scan_bus, ScanOptions, ScannedServo};
+use kos_zbot::{run_sysid, Calibrator, JointLimits, RobotConfig, SysidConfig, SysidRun};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+
+/// How long `read` waits for servos to come online.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The servo bus of this machine, as described by the robot config.
+pub struct Local {
+    config: RobotConfig,
+    supervisor: FeetechSupervisor,
+    /// Ids that could not be added, with the reason.
+    failed: BTreeMap<u8, String>,
+}
+
+impl Local {
+    pub fn open(config: RobotConfig) -> Result<Self> {
+        let bus = config.servo_bus.open(&config.servo_ids())?;
+        let supervisor = FeetechSupervisor::new(bus)?;
+        supervisor.set_poll_rate(config.control.poll_rate_hz)?;
+        supervisor.set_liveness_config(config.liveness.clone());
+        Ok(Self {
+            config,
+            supervisor,
+            failed: BTreeMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &RobotConfig {
+        &self.config
+    }
+
+    pub fn supervisor(&self) -> &FeetechSupervisor {
+        &self.supervisor
+    }
+
+    /// Stops polling and releases the bus.
+    pub async fn shutdown(&self) {
+        self.supervisor.shutdown().await;
+    }
+
+    /// Starts adding configured servos that appear on the bus later, unless
+    /// discovery is disabled.
+    pub fn spawn_discovery(&self) -> Option<JoinHandle<()>> {
+        let interval = self.config.liveness.discovery_interval_ms;
+        (interval > 0).then(|| self.supervisor.spawn_discovery(Duration::from_millis(interval)))
+    }
+
+    /// Adds `ids` to the supervisor, using the configured servo type or the
+    /// model the servo reports.
+    pub async fn attach(&mut self, ids: &[u8]) -> Result<()> {
+        for &id in ids {
+            if self.supervisor.servos.read().await.contains_key(&id) {
+                continue;
+            }
+            let servo_type = self
+                .config
+                .joints
+                .iter()
+                .find(|joint| joint.servo_id == id)
+                .and_then(|joint| joint.servo_type);
+            let added = match servo_type {
+                Some(servo_type) => self.supervisor.add_servo(id, servo_type).await,
+                None => self.supervisor.discover_servo(id).await,
+            };
+            if let Err(e) = added {
+                self.failed.insert(id, e.to_string());
+            }
+        }
+        for id in self.supervisor.missing_servos() {
+            self.failed.insert(id, format!("No servo answered on id {}", id));
+        }
+        Ok(())
+    }
+
+    async fn with_servo<T>(&self, id: u8, f: impl FnOnce(&mut dyn FeetechActuator) -> Result<T>) -> Result<T> {
+        if let Some(reason) = self.failed.get(&id) {
+            return Err(eyre!("{}", reason));
+        }
+        let mut servos = self.supervisor.servos.write().await;
+        let servo = servos
+            .get_mut(&id)
+            .ok_or_else(|| eyre!("Servo with id {} not found", id))?;
+        f(servo.as_mut())
+    }
+
+    async fn for_each(
+        &mut self,
+        ids: &[u8],
+        f: impl Fn(&mut dyn FeetechActuator) -> Result<()>,
+    ) -> Result<Vec<Outcome>> {
+        self.attach(ids).await?;
+        let mut outcomes = Vec::new();
+        for &id in ids {
+            outcomes.push(Outcome::new(id, self.with_servo(id, &f).await));
+        }
+        Ok(outcomes)
+    }
+
+    pub async fn scan(&mut self, ids: Option<Vec<u8>>, repair: bool, progress: bool) -> Result<Vec<ScannedServo>> {
+        let mut options = ScanOptions {
+            repair,
+            ..Default::default()
+        };
+        if let Some(ids) = ids {
+            options.ids = ids;
+        }
+        if progress {
+            eprintln!("Scanning {} ids...", options.ids.len());
+        }
+        let bus = self.supervisor.bus.clone();
+        let found = tokio::task::spawn_blocking(move || scan_bus(&*bus, &options, &AtomicBool::new(false), |_, _| {}))
+            .await?;
+        Ok(found)
+    }
+
+    /// Waits up to `READ_TIMEOUT` for attached servos among `ids` to come
+    /// online.
+    async fn wait_online(&self, ids: &[u8]) {
+        let deadline = tokio::time::Instant::now() + READ_TIMEOUT;
+        while tokio::time::Instant::now() < deadline
+            && ids
+                .iter()
+                .any(|&id| !self.failed.contains_key(&id) && !self.supervisor.liveness(id).online)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Waits for the selected servos to come online and returns their state.
+    pub async fn read(&mut self, ids: &[u8]) -> Result<Vec<Reading>> {
+        self.attach(ids).await?;
+        self.wait_online(ids).await;
+        Ok(self.readings(ids).await)
+    }
+
+    /// Latest state of servos already attached.
+    pub async fn readings(&self, ids: &[u8]) -> Vec<Reading> {
+        let servos = self.supervisor.servos.read().await;
+        ids.iter()
+            .map(|&id| match servos.get(&id) {
+                Some(servo) => {
+                    let info = servo.info();
+                    Reading {
+                        id,
+                        online: info.online,
+                        position: Some(info.position_deg as f64),
+                        velocity: Some(info.speed_deg_per_s as f64),
+                        torque: Some(info.torque_nm as f64),
+                        temperature_c: Some(info.temperature_c as f64),
+                        voltage_v: Some(info.voltage_v),
+                        current_a: Some(info.current_ma / 1000.0),
+                        faults: info.faults,
+                    }
+                }
+                None => Reading {
+                    id,
+                    online: false,
+                    position: None,
+                    velocity: None,
+                    torque: None,
+                    temperature_c: None,
+                    voltage_v: None,
+                    current_a: None,
+                    faults: self.failed.get(&id).cloned().into_iter().collect(),
+                },
+            })
+            .collect()
+    }
+
+    pub async fn set_id(&mut self, from: u8, to: u8) -> Result<()> {
+        self.supervisor.change_id(from, to).await
+    }
+
+    /// Gives each servo plugged in on `factory_id` the next of `ids`.
+    pub async fn assemble(&mut self, factory_id: u8, ids: &[u8], progress: bool) -> Result<()> {
+        self.supervisor
+            .assign_ids(factory_id, ids, |step| match step {
+                AssemblyStep::Waiting { id } if progress => {
+                    println!("Plug in the servo for id {} (it must answer on id {})", id, factory_id)
+                }
+                AssemblyStep::Assigned { id } if progress => println!("Assigned id {}", id),
+                _ => {}
+            })
+            .await
+    }
+
+    /// Batch calibrates the selected servos by calibration group and saves
+    /// the results to the calibration store.
+    pub async fn calibrate(
+        &mut self,
+        ids: &[u8],
+        speed: Option<f32>,
+        current_threshold: Option<f32>,
+        progress: bool,
+    ) -> Result<Vec<Outcome>> {
+        self.attach(ids).await?;
+        let selected = |id: &u8| ids.contains(id) && !self.failed.contains_key(id);
+        let mut groups: Vec<Vec<u8>> = self
+            .config
+            .calibration_groups()
+            .into_iter()
+            .map(|group| group.into_iter().filter(selected).collect::<Vec<_>>())
+            .filter(|group| !group.is_empty())
+            .collect();
+        let grouped: Vec<u8> = groups.iter().flatten().copied().collect();
+        groups.extend(ids.iter().filter(|id| selected(id) && !grouped.contains(id)).map(|&id| vec![id]));
+
+        let mut config = self.config.calibration.clone();
+        if let Some(speed) = speed {
+            config.speed_deg_per_s = speed;
+        }
+        if let Some(current_threshold) = current_threshold {
+            config.current_threshold_ma = current_threshold;
+        }
+        let joint_names: HashMap<u8, String> = self
+            .config
+            .joints
+            .iter()
+            .map(|joint| (joint.servo_id, joint.name.clone()))
+            .collect();
+
+        let calibrator = Calibrator::new(Arc::new(RwLock::new(self.supervisor.clone())));
+        let (_, mut batch) = calibrator.start_batch(groups, &config, joint_names, self.config.serial())?;
+        let result = loop {
+            let current = batch.borrow_and_update().clone();
+            if progress && !current.running.is_empty() {
+                println!("Calibrating {:?}", current.running);
+            }
+            if current.finished || batch.changed().await.is_err() {
+                break current;
+            }
+        };
+        if progress {
+            println!("Calibration saved to {}", config.store_path);
+        }
+
+        Ok(ids
+            .iter()
+            .map(|&id| {
+                let outcome = if let Some(reason) = self.failed.get(&id) {
+                    Err(eyre!("{}", reason))
+                } else if result.calibrated.contains(&id) {
+                    Ok(())
+                } else {
+                    let message = calibrator
+                        .handle(id)
+                        .and_then(|handle| handle.progress().message)
+                        .or_else(|| result.message.clone())
+                        .unwrap_or_else(|| "Calibration failed".to_string());
+                    Err(eyre!("{}", message))
+                };
+                Outcome::new(id, outcome)
+            })
+            .collect())
+    }
+
+    pub async fn reset_calibration(&mut self, ids: &[u8]) -> Result<Vec<Outcome>> {
+        self.for_each(ids, |servo| servo.write_calibration_data(-180.0, 180.0, 0.0))
+            .await
+    }
+
+    pub async fn set_acceleration(&mut self, ids: &[u8], acceleration: f32) -> Result<Vec<Outcome>> {
+        self.for_each(ids, |servo| servo.set_acceleration(acceleration)).await
+    }
+
+    pub async fn set_pid(
+        &mut self,
+        ids: &[u8],
+        kp: Option<f32>,
+        ki: Option<f32>,
+        kd: Option<f32>,
+    ) -> Result<Vec<Outcome>> {
+        self.for_each(ids, |servo| servo.set_pid(kp, ki, kd)).await
+    }
+
+    pub async fn dump_eeprom(&mut self, ids: &[u8]) -> (Vec<EepromImage>, Vec<Outcome>) {
+        let bus = self.supervisor.bus.clone();
+        let ids = ids.to_vec();
+        let results = tokio::task::spawn_blocking(move || {
+            ids.iter()
+                .map(|&id| (id, read_eeprom(&*bus, id)))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut images = Vec::new();
+        let mut outcomes = Vec::new();
+        for (id, result) in results {
+            match result {
+                Ok(image) => {
+                    images.push(image);
+                    outcomes.push(Outcome::new(id, Ok(())));
+                }
+                Err(e) => {
+                    eprintln!("Failed to read EEPROM of servo {}: {}", id, e);
+                    outcomes.push(Outcome::new(id, Err(e)));
+                }
+            }
+        }
+        (images, outcomes)
+    }
+
+    pub async fn diff_eeprom(&mut self, targets: &[(u8, EepromProfile)]) -> Vec<EepromCheck> {
+        let bus = self.supervisor.bus.clone();
+        let targets = targets.to_vec();
+        tokio::task::spawn_blocking(move || {
+            targets
+                .iter()
+                .map(|(id, profile)| {
+                    match read_eeprom(&*bus, *id).and_then(|image| profile.diff(&image)) {
+                        Ok(differences) => EepromCheck {
+                            id: *id,
+                            differences,
+                            error: None,
+                        },
+                        Err(e) => EepromCheck {
+                            id: *id,
+                            differences: Vec::new(),
+                            error: Some(e.to_string()),
+                        },
+                    }
+                })
+                .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Returns the registers written to each servo, with their old values.
+    pub async fn restore_eeprom(
+        &mut self,
+        targets: &[(u8, EepromProfile)],
+    ) -> Vec<(u8, Result<Vec<EepromDifference>>)> {
+        let bus = self.supervisor.bus.clone();
+        let targets = targets.to_vec();
+        tokio::task::spawn_blocking(move || {
+            targets
+                .iter()
+                .map(|(id, profile)| (*id, write_eeprom(&*bus, *id, profile)))
+                .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Runs the excitation on `ids`, within their configured joint limits.
+    pub async fn sysid(&mut self, ids: &[u8], config: &SysidConfig) -> Result<SysidRun> {
+        self.attach(ids).await?;
+        if let Some((id, reason)) = self.failed.iter().find(|(id, _)| ids.contains(id)) {
+            return Err(eyre!("Servo {}: {}", id, reason));
+        }
+        self.wait_online(ids).await;
+        let limits: BTreeMap<u8, JointLimits> = ids
+            .iter()
+            .map(|&id| {
+                let joint = self.config.joints.iter().find(|joint| joint.servo_id == id);
+                (id, joint.map(JointLimits::from_config).unwrap_or_default())
+            })
+            .collect();
+        run_sysid(&mut self.supervisor, &limits, config).await
+    }
+}
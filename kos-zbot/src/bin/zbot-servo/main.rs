@@ -0,0 +1,683 @@
+mod local;
+mod monitor;
+mod remote;
+
+use clap::{Parser, Subcommand};
+use eyre::{eyre, Result};
+use kos_zbot::feetech::MAX_SERVO_ID;
+use kos_zbot::feetech_eeprom::{load_eeprom_profiles, save_eeprom_images, EepromDifference, EepromProfile};
+use kos_zbot::feetech_scan::ScannedServo;
+use kos_zbot::{Excitation, RobotConfig, SysidConfig, SysidFit};
+use local::Local;
+use remote::Remote;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Inspect and configure the Feetech servos of a Zeroth-01.
+///
+/// Commands run on the local bus described by the robot config
+/// ($ZBOT_CONFIG or /etc/kos/zbot.toml), or with --remote against a running
+/// kos-zbot. Exits with 1 if any servo failed.
+#[derive(Debug, Parser)]
+#[command(name = "zbot-servo", version)]
+struct Cli {
+    /// Print results as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Address of a running kos-zbot, e.g. 192.168.42.1:50051.
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    remote: Option<String>,
+    /// Address of the control API of the --remote kos-zbot, if not on port
+    /// 9103 of the same host.
+    #[arg(long, global = true, value_name = "HOST:PORT", requires = "remote")]
+    api: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Probe the bus for servos.
+    Scan {
+        /// Ids to probe; every id when omitted.
+        #[arg(long)]
+        ids: Option<IdSelection>,
+        /// Write defaults over temperature and voltage limits that read zero.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Read the state of servos once.
+    Read { ids: IdSelection },
+    /// Print the state of servos until interrupted.
+    Watch {
+        ids: IdSelection,
+        #[arg(long, default_value_t = 100)]
+        interval_ms: u64,
+    },
+    /// Show servos and bus counters in a live dashboard.
+    Monitor {
+        #[arg(default_value = "all")]
+        ids: IdSelection,
+        #[arg(long, default_value_t = 100)]
+        interval_ms: u64,
+    },
+    /// Change the id of a servo.
+    SetId {
+        /// Current id, or with --assemble the id new servos answer on.
+        from: u8,
+        /// New id, or with --assemble the ids to hand out in order.
+        to: IdSelection,
+        /// Wait for servos to be plugged in one at a time, giving each the
+        /// next id.
+        #[arg(long)]
+        assemble: bool,
+    },
+    /// Find the end stops of servos and save them to the calibration store.
+    /// Joints sharing a calibration group run together.
+    Calibrate {
+        ids: IdSelection,
+        /// Servo speed while seeking the end stops, deg/s.
+        #[arg(long)]
+        speed: Option<f32>,
+        /// Current that marks an end stop, mA.
+        #[arg(long)]
+        current_threshold: Option<f32>,
+    },
+    /// Reset end stops to the full range and the offset to zero.
+    ResetCal { ids: IdSelection },
+    /// Set the acceleration of servos.
+    SetAccel {
+        ids: IdSelection,
+        /// deg/s²
+        acceleration: f32,
+    },
+    /// Set the position loop gains of servos.
+    SetPid {
+        ids: IdSelection,
+        #[arg(long)]
+        kp: Option<f32>,
+        #[arg(long)]
+        ki: Option<f32>,
+        #[arg(long)]
+        kd: Option<f32>,
+    },
+    /// Save the EEPROM of servos.
+    DumpEeprom {
+        ids: IdSelection,
+        /// YAML for .yaml and .yml, JSON otherwise. JSON on stdout when
+        /// omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Compare the EEPROM of servos with saved images or a golden profile.
+    DiffEeprom {
+        /// A list of images, each compared with the servo it was read from,
+        /// or a single image or profile.
+        file: PathBuf,
+        /// Servos to compare; required for a profile without an id.
+        #[arg(long)]
+        ids: Option<IdSelection>,
+    },
+    /// Write saved images or a golden profile to the EEPROM of servos.
+    /// Firmware version, id and baud rate are never written.
+    RestoreEeprom {
+        /// A list of images, each written to the servo it was read from, or
+        /// a single image or profile.
+        file: PathBuf,
+        /// Servos to write; required for a profile without an id.
+        #[arg(long)]
+        ids: Option<IdSelection>,
+    },
+    /// Drive servos with a position step, square wave or chirp, record the
+    /// response at the poll rate and fit first- and second-order models.
+    Sysid {
+        ids: IdSelection,
+        /// CSV with one row per reading.
+        #[arg(short, long, default_value = "sysid.csv")]
+        output: PathBuf,
+        /// Hold at the start position before and after the excitation.
+        #[arg(long, default_value_t = 500)]
+        settle_ms: u64,
+        /// deg/s; 0 is the servo maximum.
+        #[arg(long, default_value_t = 0.0)]
+        speed: f32,
+        #[command(subcommand)]
+        excitation: ExcitationCommand,
+    },
+}
+
+/// Offsets from where each servo starts, in degrees.
+#[derive(Debug, Clone, Copy, Subcommand)]
+enum ExcitationCommand {
+    /// Step out, hold, step back and hold again.
+    Step {
+        #[arg(long, default_value_t = 10.0, allow_negative_numbers = true)]
+        amplitude: f32,
+        #[arg(long, default_value_t = 1000)]
+        hold_ms: u64,
+    },
+    /// Alternate between +amplitude and -amplitude.
+    Square {
+        #[arg(long, default_value_t = 10.0)]
+        amplitude: f32,
+        #[arg(long, default_value_t = 1000)]
+        period_ms: u64,
+        #[arg(long, default_value_t = 5)]
+        cycles: u32,
+    },
+    /// Sine sweeping linearly in frequency.
+    Chirp {
+        #[arg(long, default_value_t = 3.0)]
+        amplitude: f32,
+        #[arg(long, default_value_t = 0.5)]
+        start_hz: f64,
+        #[arg(long, default_value_t = 5.0)]
+        end_hz: f64,
+        #[arg(long, default_value_t = 10000)]
+        duration_ms: u64,
+    },
+}
+
+impl From<ExcitationCommand> for Excitation {
+    fn from(command: ExcitationCommand) -> Self {
+        match command {
+            ExcitationCommand::Step { amplitude, hold_ms } => Excitation::Step {
+                amplitude_deg: amplitude,
+                hold_ms,
+            },
+            ExcitationCommand::Square {
+                amplitude,
+                period_ms,
+                cycles,
+            } => Excitation::Square {
+                amplitude_deg: amplitude,
+                period_ms,
+                cycles,
+            },
+            ExcitationCommand::Chirp {
+                amplitude,
+                start_hz,
+                end_hz,
+                duration_ms,
+            } => Excitation::Chirp {
+                amplitude_deg: amplitude,
+                start_hz,
+                end_hz,
+                duration_ms,
+            },
+        }
+    }
+}
+
+/// Servo ids given as `all` (every joint of the robot config) or a comma
+/// separated list of ids and ranges, e.g. `11-16,21`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IdSelection {
+    All,
+    Ids(Vec<u8>),
+}
+
+impl FromStr for IdSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(IdSelection::All);
+        }
+        let parse_id = |id: &str| match id.trim().parse::<u8>() {
+            Ok(id) if (1..=MAX_SERVO_ID).contains(&id) => Ok(id),
+            _ => Err(format!("'{}' is not a servo id (1-{})", id, MAX_SERVO_ID)),
+        };
+        let mut ids = Vec::new();
+        for part in s.split(',') {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse_id(first)?, parse_id(last)?);
+                    if first > last {
+                        return Err(format!("Empty id range '{}'", part));
+                    }
+                    ids.extend(first..=last);
+                }
+                None => ids.push(parse_id(part)?),
+            }
+        }
+        let mut seen = HashSet::new();
+        ids.retain(|&id| seen.insert(id));
+        Ok(IdSelection::Ids(ids))
+    }
+}
+
+impl IdSelection {
+    fn resolve(&self, config: &RobotConfig) -> Vec<u8> {
+        match self {
+            IdSelection::All => config.servo_ids(),
+            IdSelection::Ids(ids) => ids.clone(),
+        }
+    }
+}
+
+enum Target {
+    Local(Box<Local>),
+    Remote(Remote),
+}
+
+impl Target {
+    fn local(&mut self, command: &str) -> Result<&mut Local> {
+        match self {
+            Target::Local(local) => Ok(local),
+            Target::Remote(_) => Err(eyre!("{} needs direct bus access; run it on the robot", command)),
+        }
+    }
+}
+
+/// State of one servo. Local reads are in servo degrees and newton metres,
+/// remote reads in the runtime's joint units.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reading {
+    pub id: u8,
+    pub online: bool,
+    pub position: Option<f64>,
+    pub velocity: Option<f64>,
+    pub torque: Option<f64>,
+    pub temperature_c: Option<f64>,
+    pub voltage_v: Option<f32>,
+    pub current_a: Option<f32>,
+    pub faults: Vec<String>,
+}
+
+/// Result of a command on one servo.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome {
+    pub id: u8,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    pub fn new(id: u8, result: Result<()>) -> Self {
+        Self {
+            id,
+            ok: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// EEPROM registers of one servo that differ from its profile.
+#[derive(Debug, Clone, Serialize)]
+pub struct EepromCheck {
+    pub id: u8,
+    pub differences: Vec<EepromDifference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_scan(found: &[ScannedServo]) {
+    for servo in found {
+        let status = if servo.faults.is_empty() {
+            "OK".to_string()
+        } else {
+            servo.faults.join(", ")
+        };
+        match servo.model {
+            Some(model) => println!(
+                "{:3} {:?}  angles {}-{}  mode {}  status {}  {:.1} V  {} °C  limits {} °C {:.1}-{:.1} V",
+                servo.id,
+                model,
+                servo.min_angle,
+                servo.max_angle,
+                servo.mode,
+                status,
+                servo.voltage_v,
+                servo.temperature_c,
+                servo.max_temperature_limit_c,
+                servo.min_voltage_limit_v,
+                servo.max_voltage_limit_v
+            ),
+            None => println!("{:3} unknown model {:#06x}", servo.id, servo.model_id),
+        }
+        for repair in &servo.repairs {
+            println!("    repaired {} at {:#04x}: {} -> {}", repair.register, repair.address, repair.from, repair.to);
+        }
+        if let Some(error) = &servo.error {
+            println!("    error: {}", error);
+        }
+    }
+    println!("Found {} servos", found.len());
+}
+
+fn print_readings(readings: &[Reading]) {
+    let value = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.2}", value));
+    println!(
+        "{:>3} {:>7} {:>9} {:>9} {:>7} {:>6} {:>6} {:>6}  faults",
+        "id", "online", "position", "velocity", "torque", "temp", "volt", "amps"
+    );
+    for reading in readings {
+        println!(
+            "{:>3} {:>7} {:>9} {:>9} {:>7} {:>6} {:>6} {:>6}  {}",
+            reading.id,
+            reading.online,
+            value(reading.position),
+            value(reading.velocity),
+            value(reading.torque),
+            value(reading.temperature_c),
+            value(reading.voltage_v.map(f64::from)),
+            value(reading.current_a.map(f64::from)),
+            reading.faults.join(", ")
+        );
+    }
+}
+
+fn print_differences(id: u8, differences: &[EepromDifference], expected: &str, actual: &str) {
+    for difference in differences {
+        println!(
+            "{:3} {} ({:#04x}): {} {}, {} {}",
+            id, difference.register, difference.address, expected, difference.expected, actual, difference.actual
+        );
+    }
+}
+
+/// Pairs servos with the profile to use for them. A list of images goes
+/// by the id in each image; a single profile applies to every selected id.
+fn profile_targets(profiles: Vec<EepromProfile>, ids: Option<Vec<u8>>) -> Result<Vec<(u8, EepromProfile)>> {
+    if let [profile] = &profiles[..] {
+        let ids = ids
+            .or_else(|| profile.id().map(|id| vec![id]))
+            .ok_or_else(|| eyre!("The profile has no id; select servos with --ids"))?;
+        return Ok(ids.into_iter().map(|id| (id, profile.clone())).collect());
+    }
+    let by_id: BTreeMap<u8, EepromProfile> = profiles
+        .into_iter()
+        .map(|profile| Ok((profile.id().ok_or_else(|| eyre!("Every image in a list needs an id"))?, profile)))
+        .collect::<Result<_>>()?;
+    match ids {
+        None => Ok(by_id.into_iter().collect()),
+        Some(ids) => ids
+            .into_iter()
+            .map(|id| {
+                let profile = by_id.get(&id).ok_or_else(|| eyre!("No image for servo {}", id))?;
+                Ok((id, profile.clone()))
+            })
+            .collect(),
+    }
+}
+
+/// Prints per-servo outcomes and returns whether all succeeded.
+fn print_fits(fits: &[SysidFit]) {
+    for fit in fits {
+        println!(
+            "{:3} {} readings every {:.1} ms",
+            fit.actuator_id,
+            fit.samples,
+            fit.sample_period_s * 1000.0
+        );
+        match &fit.first_order {
+            Some(model) => println!(
+                "    first order:  gain {:.3}  tau {:.1} ms  delay {:.1} ms  rmse {:.2} deg",
+                model.gain,
+                model.time_constant_s * 1000.0,
+                model.delay_s * 1000.0,
+                model.rmse_deg
+            ),
+            None => println!("    first order:  no stable fit"),
+        }
+        match &fit.second_order {
+            Some(model) => println!(
+                "    second order: gain {:.3}  wn {:.2} Hz  zeta {:.2}  delay {:.1} ms  rmse {:.2} deg",
+                model.gain,
+                model.natural_frequency_hz,
+                model.damping_ratio,
+                model.delay_s * 1000.0,
+                model.rmse_deg
+            ),
+            None => println!("    second order: no stable fit"),
+        }
+    }
+}
+
+fn report(json: bool, outcomes: &[Outcome]) -> Result<bool> {
+    if json {
+        print_json(outcomes)?;
+    } else {
+        for outcome in outcomes {
+            match &outcome.error {
+                None => println!("{:3} ok", outcome.id),
+                Some(error) => println!("{:3} failed: {}", outcome.id, error),
+            }
+        }
+    }
+    Ok(outcomes.iter().all(|outcome| outcome.ok))
+}
+
+/// Runs the command, returning whether every servo succeeded.
+async fn run(cli: Cli) -> Result<bool> {
+    let config = RobotConfig::load()?;
+    let mut target = match &cli.remote {
+        Some(address) => Target::Remote(Remote::connect(address, cli.api.as_deref()).await?),
+        None => Target::Local(Box::new(Local::open(config.clone())?)),
+    };
+    let result = execute(cli.command, cli.json, &config, &mut target).await;
+    if let Target::Local(local) = &target {
+        local.shutdown().await;
+    }
+    result
+}
+
+async fn execute(command: Command, json: bool, config: &RobotConfig, target: &mut Target) -> Result<bool> {
+    match command {
+        Command::Scan { ids, repair } => {
+            let ids = ids.map(|ids| ids.resolve(config));
+            let found = match target {
+                Target::Local(local) => local.scan(ids, repair, !json).await?,
+                Target::Remote(remote) => remote.scan(ids, repair, !json).await?,
+            };
+            if json {
+                print_json(&found)?;
+            } else {
+                print_scan(&found);
+            }
+            Ok(found.iter().all(|servo| servo.error.is_none()))
+        }
+        Command::Read { ids } => {
+            let ids = ids.resolve(config);
+            let readings = match target {
+                Target::Local(local) => local.read(&ids).await?,
+                Target::Remote(remote) => remote.read(&ids).await?,
+            };
+            if json {
+                print_json(&readings)?;
+            } else {
+                print_readings(&readings);
+            }
+            Ok(readings.iter().all(|reading| reading.online))
+        }
+        Command::Watch { ids, interval_ms } => {
+            let ids = ids.resolve(config);
+            if let Target::Local(local) = target {
+                local.attach(&ids).await?;
+            }
+            let mut interval = tokio::time::interval(Duration::from_millis(interval_ms.max(1)));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = tokio::signal::ctrl_c() => return Ok(true),
+                }
+                let readings = match target {
+                    Target::Local(local) => local.readings(&ids).await,
+                    Target::Remote(remote) => remote.read(&ids).await?,
+                };
+                if json {
+                    println!("{}", serde_json::to_string(&readings)?);
+                } else {
+                    print_readings(&readings);
+                    println!();
+                }
+            }
+        }
+        Command::Monitor { ids, interval_ms } => {
+            let ids = ids.resolve(config);
+            let local = target.local("monitor")?;
+            monitor::run(local, &ids, Duration::from_millis(interval_ms.max(10))).await?;
+            Ok(true)
+        }
+        Command::SetId { from, to, assemble } => {
+            let to = to.resolve(config);
+            if assemble {
+                let assigned = target.local("set-id --assemble")?.assemble(from, &to, !json).await;
+                let outcomes: Vec<Outcome> = match &assigned {
+                    Ok(()) => to.iter().map(|&id| Outcome::new(id, Ok(()))).collect(),
+                    Err(e) => vec![Outcome::new(from, Err(eyre!("{}", e)))],
+                };
+                return report(json, &outcomes);
+            }
+            let [to] = to[..] else {
+                return Err(eyre!("set-id takes one new id unless --assemble is given"));
+            };
+            let result = match target {
+                Target::Local(local) => local.set_id(from, to).await,
+                Target::Remote(remote) => remote.set_id(from, to).await,
+            };
+            report(json, &[Outcome::new(to, result)])
+        }
+        Command::Calibrate {
+            ids,
+            speed,
+            current_threshold,
+        } => {
+            let outcomes = match target {
+                Target::Local(local) => {
+                    let ids = ids.resolve(config);
+                    local.calibrate(&ids, speed, current_threshold, !json).await?
+                }
+                Target::Remote(remote) => {
+                    let ids = match ids {
+                        IdSelection::All => None,
+                        IdSelection::Ids(ids) => Some(ids),
+                    };
+                    remote.calibrate(ids, speed, current_threshold, !json).await?
+                }
+            };
+            report(json, &outcomes)
+        }
+        Command::ResetCal { ids } => {
+            let ids = ids.resolve(config);
+            let outcomes = target.local("reset-cal")?.reset_calibration(&ids).await?;
+            report(json, &outcomes)
+        }
+        Command::SetAccel { ids, acceleration } => {
+            let ids = ids.resolve(config);
+            let outcomes = match target {
+                Target::Local(local) => local.set_acceleration(&ids, acceleration).await?,
+                Target::Remote(remote) => remote.set_acceleration(&ids, acceleration).await,
+            };
+            report(json, &outcomes)
+        }
+        Command::SetPid { ids, kp, ki, kd } => {
+            if kp.is_none() && ki.is_none() && kd.is_none() {
+                return Err(eyre!("Give at least one of --kp, --ki and --kd"));
+            }
+            let ids = ids.resolve(config);
+            let outcomes = match target {
+                Target::Local(local) => local.set_pid(&ids, kp, ki, kd).await?,
+                Target::Remote(remote) => remote.set_pid(&ids, kp, ki, kd).await,
+            };
+            report(json, &outcomes)
+        }
+        Command::DumpEeprom { ids, output } => {
+            let ids = ids.resolve(config);
+            let (images, outcomes) = target.local("dump-eeprom")?.dump_eeprom(&ids).await;
+            match output {
+                Some(path) => {
+                    save_eeprom_images(&path, &images)?;
+                    if !json {
+                        println!("Saved {} EEPROM images to {}", images.len(), path.display());
+                    }
+                }
+                None => print_json(&images)?,
+            }
+            Ok(outcomes.iter().all(|outcome| outcome.ok))
+        }
+        Command::DiffEeprom { file, ids } => {
+            let targets = profile_targets(load_eeprom_profiles(&file)?, ids.map(|ids| ids.resolve(config)))?;
+            let checks = target.local("diff-eeprom")?.diff_eeprom(&targets).await;
+            if json {
+                print_json(&checks)?;
+            } else {
+                for check in &checks {
+                    match &check.error {
+                        Some(error) => println!("{:3} failed: {}", check.id, error),
+                        None if check.differences.is_empty() => println!("{:3} matches", check.id),
+                        None => print_differences(check.id, &check.differences, "expected", "read"),
+                    }
+                }
+            }
+            Ok(checks.iter().all(|check| check.error.is_none() && check.differences.is_empty()))
+        }
+        Command::RestoreEeprom { file, ids } => {
+            let targets = profile_targets(load_eeprom_profiles(&file)?, ids.map(|ids| ids.resolve(config)))?;
+            let results = target.local("restore-eeprom")?.restore_eeprom(&targets).await;
+            let mut outcomes = Vec::new();
+            for (id, result) in results {
+                if let (false, Ok(changes)) = (json, &result) {
+                    print_differences(id, changes, "wrote", "was");
+                }
+                outcomes.push(Outcome::new(id, result.map(|_| ())));
+            }
+            report(json, &outcomes)
+        }
+        Command::Sysid {
+            ids,
+            output,
+            settle_ms,
+            speed,
+            excitation,
+        } => {
+            let ids = ids.resolve(config);
+            let sysid = SysidConfig {
+                excitation: excitation.into(),
+                settle_ms,
+                speed_deg_per_s: speed,
+            };
+            if !json {
+                println!(
+                    "Running {:?} on {:?} for {:.1} s",
+                    sysid.excitation,
+                    ids,
+                    sysid.excitation.duration().as_secs_f64()
+                );
+            }
+            let run = target.local("sysid")?.sysid(&ids, &sysid).await?;
+            run.write_csv(&output)?;
+            if json {
+                print_json(&run.fits)?;
+            } else {
+                println!("Saved {} readings to {}", run.samples.len(), output.display());
+                print_fits(&run.fits);
+            }
+            Ok(run.fits.iter().all(|fit| fit.first_order.is_some() || fit.second_order.is_some()))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::local::Local;
+use eyre::Result;
+use kos_zbot::feetech::FeetechActuatorInfo;
+use kos_zbot::stats::{ServoStats, SupervisorStats};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::{Duration, Instant};
+
+/// One servo as last polled.
+struct ServoRow {
+    id: u8,
+    joint: String,
+    /// `None` while the servo has not answered.
+    info: Option<FeetechActuatorInfo>,
+    protection_faults: Vec<String>,
+    stats: Option<ServoStats>,
+}
+
+struct Snapshot {
+    at: Instant,
+    stats: SupervisorStats,
+    servos: Vec<ServoRow>,
+}
+
+/// Counter deltas between two snapshots, per second.
+#[derive(Default)]
+struct Rates {
+    poll_hz: f64,
+    reads: f64,
+    retries: f64,
+    faults: f64,
+}
+
+impl Rates {
+    fn between(previous: &Snapshot, current: &Snapshot) -> Self {
+        let seconds = current.at.duration_since(previous.at).as_secs_f64();
+        if seconds <= 0.0 {
+            return Self::default();
+        }
+        let rate = |previous: u64, current: u64| current.saturating_sub(previous) as f64 / seconds;
+        Self {
+            poll_hz: rate(previous.stats.poll.ticks, current.stats.poll.ticks),
+            reads: rate(previous.stats.bus_reads, current.stats.bus_reads),
+            retries: rate(previous.stats.bus_retries, current.stats.bus_retries),
+            faults: rate(previous.stats.bus_faults, current.stats.bus_faults),
+        }
+    }
+}
+
+async fn snapshot(local: &Local, ids: &[u8]) -> Snapshot {
+    let supervisor = local.supervisor();
+    let stats = supervisor.stats();
+    let servos = supervisor.servos.read().await;
+    let protection = supervisor.protection.lock().unwrap();
+    let servos = ids
+        .iter()
+        .map(|&id| ServoRow {
+            id,
+            joint: local
+                .config()
+                .joints
+                .iter()
+                .find(|joint| joint.servo_id == id)
+                .map_or(String::new(), |joint| joint.name.clone()),
+            info: servos.get(&id).map(|servo| servo.info()),
+            protection_faults: protection.faults(id),
+            stats: stats.servos.get(&id).cloned(),
+        })
+        .collect();
+    Snapshot {
+        at: Instant::now(),
+        stats,
+        servos,
+    }
+}
+
+fn render(frame: &mut Frame, snapshot: &Snapshot, rates: &Rates) {
+    let [header, table, footer] = Layout::vertical([Constraint::Length(4), Constraint::Min(3), Constraint::Length(1)])
+        .areas(frame.area());
+
+    let stats = &snapshot.stats;
+    let bus = Paragraph::new(vec![
+        Line::from(format!(
+            "bus    reads {} ({:.0}/s)   retries {} ({:.1}/s)   faults {} ({:.1}/s)   failed polls {}",
+            stats.bus_reads, rates.reads, stats.bus_retries, rates.retries, stats.bus_faults, rates.faults, stats.poll_errors
+        )),
+        Line::from(format!(
+            "poll   {:.1} Hz of {:.1} Hz   jitter {:.0} us mean, {} us max   overruns {}   missed ticks {}",
+            rates.poll_hz,
+            stats.poll.rate_hz,
+            stats.poll.mean_jitter_us,
+            stats.poll.max_jitter_us,
+            stats.poll.overruns,
+            stats.poll.missed_ticks
+        )),
+    ])
+    .block(Block::bordered().title(" zbot-servo monitor "));
+    frame.render_widget(bus, header);
+
+    let rows = snapshot.servos.iter().map(|servo| {
+        let Some(info) = &servo.info else {
+            return Row::new(vec![servo.id.to_string(), servo.joint.clone(), "missing".to_string()])
+                .style(Style::default().fg(Color::DarkGray));
+        };
+        let faults: Vec<&str> = info
+            .faults
+            .iter()
+            .chain(&servo.protection_faults)
+            .map(String::as_str)
+            .collect();
+        let style = if !faults.is_empty() {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else if !info.online {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        let reads = servo.stats.as_ref();
+        Row::new(vec![
+            servo.id.to_string(),
+            servo.joint.clone(),
+            if info.online { "online" } else { "offline" }.to_string(),
+            if info.torque_enabled { "on" } else { "off" }.to_string(),
+            format!("{:.1}", info.position_deg),
+            format!("{:.1}", info.speed_deg_per_s),
+            format!("{:.0}", info.load_percent),
+            format!("{:.0}", info.current_ma),
+            format!("{:.1}", info.voltage_v),
+            format!("{:.0}", info.temperature_c),
+            reads.map_or("-".to_string(), |reads| format!("{:.0}", reads.read_success_rate * 100.0)),
+            reads
+                .and_then(|reads| reads.last_reading_age_ms)
+                .map_or("-".to_string(), |age| age.to_string()),
+            faults.join(", "),
+        ])
+        .style(style)
+    });
+    let widths = [
+        Constraint::Length(3),
+        Constraint::Length(20),
+        Constraint::Length(7),
+        Constraint::Length(6),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Length(6),
+        Constraint::Length(7),
+        Constraint::Length(6),
+        Constraint::Length(5),
+        Constraint::Length(6),
+        Constraint::Length(7),
+        Constraint::Min(10),
+    ];
+    let table_widget = Table::new(rows, widths)
+        .header(
+            Row::new(vec![
+                "id", "joint", "state", "torque", "pos °", "vel °/s", "load %", "cur mA", "volt", "°C", "read %", "age ms",
+                "faults",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered());
+    frame.render_widget(table_widget, table);
+    frame.render_widget(Paragraph::new("q quit"), footer);
+}
+
+fn quit_requested() -> Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+async fn run_terminal(terminal: &mut DefaultTerminal, local: &Local, ids: &[u8], interval: Duration) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    let mut previous = snapshot(local, ids).await;
+    let mut rates = Rates::default();
+    loop {
+        ticker.tick().await;
+        if quit_requested()? {
+            return Ok(());
+        }
+        let current = snapshot(local, ids).await;
+        // Rates over at least a second, so they do not flicker.
+        let rates_due = current.at.duration_since(previous.at) >= Duration::from_secs(1);
+        if rates_due {
+            rates = Rates::between(&previous, &current);
+        }
+        terminal.draw(|frame| render(frame, &current, &rates))?;
+        if rates_due {
+            previous = current;
+        }
+    }
+}
+
+/// Shows the selected servos and the bus counters until `q` is pressed.
+/// Servos that are plugged in later show up through discovery.
+pub async fn run(local: &mut Local, ids: &[u8], interval: Duration) -> Result<()> {
+    local.attach(ids).await?;
+    let discovery = local.spawn_discovery();
+    let mut terminal = ratatui::init();
+    let result = run_terminal(&mut terminal, local, ids, interval).await;
+    ratatui::restore();
+    if let Some(discovery) = discovery {
+        discovery.abort();
+    }
+    result
+}
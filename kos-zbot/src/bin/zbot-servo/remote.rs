@@ -0,0 +1,295 @@
+use crate::{Outcome, Reading};
+use eyre::{eyre, Result};
+use kos::google_proto::longrunning::operations_client::OperationsClient;
+use kos::google_proto::longrunning::{GetOperationRequest, Operation};
+use kos::kos_proto::actuator::actuator_service_client::ActuatorServiceClient;
+use kos::kos_proto::actuator::{CalibrateActuatorRequest, ConfigureActuatorRequest, GetActuatorsStateRequest};
+use kos_zbot::feetech_scan::{ScanOptions, ScannedServo};
+use kos_zbot::feetech_serial::BROADCAST_ID;
+use kos_zbot::{BatchCalibrationProgress, CalibrationPhase, CalibrationProgress, ScanOperation, ScanProgress, DEFAULT_API_PORT};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tonic::transport::Channel;
+
+/// Interval at which long-running operations are polled.
+const OPERATION_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A running kos-zbot, reached through its actuator and operations services,
+/// and its control API for what those have no messages for.
+pub struct Remote {
+    actuators: ActuatorServiceClient<Channel>,
+    operations: OperationsClient<Channel>,
+    api: String,
+}
+
+fn metadata<T: DeserializeOwned>(operation: &Operation) -> Result<T> {
+    let metadata = operation
+        .metadata
+        .as_ref()
+        .ok_or_else(|| eyre!("Operation {} has no metadata", operation.name))?;
+    serde_json::from_slice(&metadata.value)
+        .map_err(|e| eyre!("Unexpected metadata {} on {}: {}", metadata.type_url, operation.name, e))
+}
+
+impl Remote {
+    /// Connects to the gRPC services at `address`. The control API is at
+    /// `api`, or on `DEFAULT_API_PORT` of the same host.
+    pub async fn connect(address: &str, api: Option<&str>) -> Result<Self> {
+        let api = match api {
+            Some(api) => api.to_string(),
+            None => {
+                let authority = address.split("://").last().unwrap_or(address);
+                let authority = authority.split('/').next().unwrap_or(authority);
+                let host = authority.rsplit_once(':').map_or(authority, |(host, _)| host);
+                format!("{}:{}", host, DEFAULT_API_PORT)
+            }
+        };
+        let endpoint = if address.contains("://") {
+            address.to_string()
+        } else {
+            format!("http://{}", address)
+        };
+        let actuators = ActuatorServiceClient::connect(endpoint.clone())
+            .await
+            .map_err(|e| eyre!("Failed to connect to {}: {}", endpoint, e))?;
+        let operations = OperationsClient::connect(endpoint.clone())
+            .await
+            .map_err(|e| eyre!("Failed to connect to {}: {}", endpoint, e))?;
+        Ok(Self {
+            actuators,
+            operations,
+            api,
+        })
+    }
+
+    /// Sends a JSON request to the control API and decodes the JSON reply.
+    async fn api_request<T: DeserializeOwned>(&self, method: &str, path: &str, body: &impl Serialize) -> Result<T> {
+        let body = serde_json::to_string(body)?;
+        let mut stream = TcpStream::connect(&self.api)
+            .await
+            .map_err(|e| eyre!("Failed to connect to the control API at {}: {}", self.api, e))?;
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            self.api,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| eyre!("Malformed reply from the control API at {}", self.api))?;
+        let status = head.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            let message = serde_json::from_str::<serde_json::Value>(body)
+                .ok()
+                .and_then(|error| error["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| body.trim().to_string());
+            return Err(eyre!("{} {} failed ({}): {}", method, path, status, message));
+        }
+        serde_json::from_str(body).map_err(|e| eyre!("Unexpected reply to {} {}: {}", method, path, e))
+    }
+
+    pub async fn read(&mut self, ids: &[u8]) -> Result<Vec<Reading>> {
+        let response = self
+            .actuators
+            .get_actuators_state(GetActuatorsStateRequest {
+                actuator_ids: ids.iter().map(|&id| id as u32).collect(),
+            })
+            .await
+            .map_err(|e| eyre!("get_actuators_state failed: {}", e.message()))?
+            .into_inner();
+        Ok(response
+            .states
+            .into_iter()
+            .map(|state| Reading {
+                id: state.actuator_id as u8,
+                online: state.online,
+                position: state.position,
+                velocity: state.velocity,
+                torque: state.torque,
+                temperature_c: state.temperature,
+                voltage_v: state.voltage,
+                current_a: state.current,
+                faults: state.faults,
+            })
+            .collect())
+    }
+
+    async fn configure(&mut self, request: ConfigureActuatorRequest) -> Result<()> {
+        let response = self
+            .actuators
+            .configure_actuator(request)
+            .await
+            .map_err(|e| eyre!("configure_actuator failed: {}", e.message()))?
+            .into_inner();
+        if response.success {
+            return Ok(());
+        }
+        Err(eyre!(
+            "{}",
+            response.error.map_or("Request failed".to_string(), |error| error.message)
+        ))
+    }
+
+    pub async fn set_id(&mut self, from: u8, to: u8) -> Result<()> {
+        self.configure(ConfigureActuatorRequest {
+            actuator_id: from as u32,
+            new_actuator_id: Some(to as u32),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn set_acceleration(&mut self, ids: &[u8], acceleration: f32) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        for &id in ids {
+            let result = self
+                .configure(ConfigureActuatorRequest {
+                    actuator_id: id as u32,
+                    acceleration: Some(acceleration as f64),
+                    ..Default::default()
+                })
+                .await;
+            outcomes.push(Outcome::new(id, result));
+        }
+        outcomes
+    }
+
+    pub async fn set_pid(&mut self, ids: &[u8], kp: Option<f32>, ki: Option<f32>, kd: Option<f32>) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        for &id in ids {
+            let result = self
+                .configure(ConfigureActuatorRequest {
+                    actuator_id: id as u32,
+                    kp: kp.map(f64::from),
+                    ki: ki.map(f64::from),
+                    kd: kd.map(f64::from),
+                    ..Default::default()
+                })
+                .await;
+            outcomes.push(Outcome::new(id, result));
+        }
+        outcomes
+    }
+
+    /// Polls `operation` until it is done, calling `on_update` with each
+    /// new state.
+    async fn wait(&mut self, mut operation: Operation, mut on_update: impl FnMut(&Operation)) -> Result<Operation> {
+        loop {
+            on_update(&operation);
+            if operation.done {
+                return Ok(operation);
+            }
+            tokio::time::sleep(OPERATION_POLL_INTERVAL).await;
+            operation = self
+                .operations
+                .get_operation(GetOperationRequest {
+                    name: operation.name.clone(),
+                })
+                .await
+                .map_err(|e| eyre!("get_operation {} failed: {}", operation.name, e.message()))?
+                .into_inner();
+        }
+    }
+
+    async fn start_calibration(&mut self, actuator_id: u32, speed: Option<f32>, current_threshold: Option<f32>) -> Result<Operation> {
+        Ok(self
+            .actuators
+            .calibrate_actuator(CalibrateActuatorRequest {
+                actuator_id,
+                calibration_speed: speed.unwrap_or(0.0) as f64,
+                threshold_current: current_threshold.unwrap_or(0.0),
+            })
+            .await
+            .map_err(|e| eyre!("calibrate_actuator failed: {}", e.message()))?
+            .into_inner())
+    }
+
+    /// Scans `ids`, or every id, on the runtime's bus, repairing zero EEPROM
+    /// limits only with `repair`.
+    pub async fn scan(&mut self, ids: Option<Vec<u8>>, repair: bool, progress: bool) -> Result<Vec<ScannedServo>> {
+        let defaults = ScanOptions::default();
+        let options = ScanOptions {
+            ids: ids.unwrap_or(defaults.ids),
+            repair,
+            ..defaults
+        };
+        let scan: ScanOperation = self.api_request("POST", "/scan", &options).await?;
+        let operation = self
+            .wait(scan.progress.to_operation(&scan.name), |operation| {
+                if let (true, Ok(scan)) = (progress, metadata::<ScanProgress>(operation)) {
+                    eprintln!("Scanned {}/{} ids, found {}", scan.scanned, scan.total, scan.found.len());
+                }
+            })
+            .await?;
+        let scan: ScanProgress = metadata(&operation)?;
+        match scan.message {
+            Some(message) => Err(eyre!("Scan failed: {}", message)),
+            None => Ok(scan.found),
+        }
+    }
+
+    /// Calibrates `ids` one at a time, or every joint of the runtime by
+    /// calibration group when `ids` is `None`.
+    pub async fn calibrate(
+        &mut self,
+        ids: Option<Vec<u8>>,
+        speed: Option<f32>,
+        current_threshold: Option<f32>,
+        progress: bool,
+    ) -> Result<Vec<Outcome>> {
+        let Some(ids) = ids else {
+            let operation = self.start_calibration(BROADCAST_ID as u32, speed, current_threshold).await?;
+            let operation = self
+                .wait(operation, |operation| {
+                    if let (true, Ok(batch)) = (progress, metadata::<BatchCalibrationProgress>(operation)) {
+                        if !batch.running.is_empty() {
+                            println!("Calibrating {:?}", batch.running);
+                        }
+                    }
+                })
+                .await?;
+            let batch: BatchCalibrationProgress = metadata(&operation)?;
+            let message = batch.message.unwrap_or_else(|| "Calibration failed".to_string());
+            return Ok(batch
+                .calibrated
+                .iter()
+                .map(|&id| Outcome::new(id, Ok(())))
+                .chain(batch.failed.iter().map(|&id| Outcome::new(id, Err(eyre!("{}", message)))))
+                .collect());
+        };
+
+        let mut outcomes = Vec::new();
+        for id in ids {
+            let result = async {
+                let operation = self.start_calibration(id as u32, speed, current_threshold).await?;
+                let operation = self
+                    .wait(operation, |operation| {
+                        if let (true, Ok(calibration)) = (progress, metadata::<CalibrationProgress>(operation)) {
+                            println!("{:3} {:?}", id, calibration.phase);
+                        }
+                    })
+                    .await?;
+                let calibration: CalibrationProgress = metadata(&operation)?;
+                match calibration.phase {
+                    CalibrationPhase::Done => Ok(()),
+                    phase => Err(eyre!(
+                        "{}",
+                        calibration.message.unwrap_or_else(|| format!("Calibration ended {:?}", phase))
+                    )),
+                }
+            }
+            .await;
+            outcomes.push(Outcome::new(id, result));
+        }
+        Ok(outcomes)
+    }
+}
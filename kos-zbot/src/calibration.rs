@@ -0,0 +1,635 @@
+use crate::calibration_store::{CalibrationStore, ServoCalibration};
+use crate::error::ZBotError;
+use crate::firmware::feetech::{FeetechActuator, FeetechOperationMode, FeetechSupervisor};
+use eyre::{eyre, Result};
+use kos::hal::Operation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+type ServoMap = RwLock<HashMap<u8, Box<dyn FeetechActuator>>>;
+
+/// Mode and speed writes right after enabling torque are sometimes dropped,
+/// so they are repeated a few times like the original calibration tool did.
+const COMMAND_REPEATS: usize = 10;
+const COMMAND_REPEAT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// End-stop calibration parameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibrationConfig {
+    #[serde(default = "default_speed_deg_per_s")]
+    pub speed_deg_per_s: f32,
+    /// Current that marks an end stop.
+    #[serde(default = "default_current_threshold_ma")]
+    pub current_threshold_ma: f32,
+    /// Time to ignore the start-up current spike after each move starts.
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    /// Give up if an end stop is not reached within this time.
+    #[serde(default = "default_end_stop_timeout_ms")]
+    pub end_stop_timeout_ms: u64,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Calibration file written by batch calibration.
+    #[serde(default = "default_store_path")]
+    pub store_path: String,
+    /// What to do at startup when a servo's EEPROM differs from the file.
+    #[serde(default)]
+    pub startup: CalibrationStartup,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationStartup {
+    /// Don't read the calibration file.
+    Ignore,
+    /// Log servos whose EEPROM differs from the file.
+    #[default]
+    Verify,
+    /// Rewrite differing EEPROM from the file, e.g. after swapping a servo.
+    Restore,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            speed_deg_per_s: default_speed_deg_per_s(),
+            current_threshold_ma: default_current_threshold_ma(),
+            settle_ms: default_settle_ms(),
+            end_stop_timeout_ms: default_end_stop_timeout_ms(),
+            poll_interval_ms: default_poll_interval_ms(),
+            store_path: default_store_path(),
+            startup: CalibrationStartup::default(),
+        }
+    }
+}
+
+fn default_speed_deg_per_s() -> f32 {
+    15.0
+}
+
+fn default_current_threshold_ma() -> f32 {
+    1000.0
+}
+
+fn default_settle_ms() -> u64 {
+    500
+}
+
+fn default_end_stop_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_poll_interval_ms() -> u64 {
+    20
+}
+
+fn default_store_path() -> String {
+    "/etc/kos/zbot-calibration.json".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationPhase {
+    Starting,
+    SeekingMin,
+    SeekingMax,
+    Writing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl CalibrationPhase {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+
+    fn fraction(&self) -> f32 {
+        match self {
+            Self::Starting => 0.0,
+            Self::SeekingMin => 0.1,
+            Self::SeekingMax => 0.5,
+            Self::Writing => 0.9,
+            Self::Done | Self::Failed | Self::Cancelled => 1.0,
+        }
+    }
+}
+
+/// Published as the operation metadata, JSON encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibrationProgress {
+    pub actuator_id: u8,
+    pub phase: CalibrationPhase,
+    pub progress: f32,
+    pub min_position_deg: Option<f32>,
+    pub max_position_deg: Option<f32>,
+    pub message: Option<String>,
+}
+
+impl CalibrationProgress {
+    pub fn new(actuator_id: u8) -> Self {
+        Self {
+            actuator_id,
+            phase: CalibrationPhase::Starting,
+            progress: 0.0,
+            min_position_deg: None,
+            max_position_deg: None,
+            message: None,
+        }
+    }
+
+    pub fn to_operation(&self, name: &str) -> Operation {
+        Operation {
+            name: name.to_string(),
+            metadata: Some(prost_types::Any {
+                type_url: "type.kscale.dev/kos_zbot.CalibrationProgress+json".to_string(),
+                value: serde_json::to_vec(self).unwrap_or_default(),
+            }),
+            done: self.phase.is_finished(),
+            result: None,
+        }
+    }
+}
+
+/// Published as the metadata of a batch calibration operation, JSON encoded.
+/// Each servo also gets its own operation with a `CalibrationProgress`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchCalibrationProgress {
+    pub total: usize,
+    pub running: Vec<u8>,
+    pub calibrated: Vec<u8>,
+    pub failed: Vec<u8>,
+    pub finished: bool,
+    pub message: Option<String>,
+}
+
+impl BatchCalibrationProgress {
+    pub fn to_operation(&self, name: &str) -> Operation {
+        Operation {
+            name: name.to_string(),
+            metadata: Some(prost_types::Any {
+                type_url: "type.kscale.dev/kos_zbot.BatchCalibrationProgress+json".to_string(),
+                value: serde_json::to_vec(self).unwrap_or_default(),
+            }),
+            done: self.finished,
+            result: None,
+        }
+    }
+}
+
+/// Receives operation updates, normally the KOS operations service.
+#[tonic::async_trait]
+pub trait OperationSink: Send + Sync {
+    async fn publish(&self, operation: Operation);
+}
+
+/// A calibration in flight.
+#[derive(Debug, Clone)]
+pub struct CalibrationHandle {
+    pub name: String,
+    cancel: Arc<AtomicBool>,
+    progress: watch::Receiver<CalibrationProgress>,
+}
+
+impl CalibrationHandle {
+    pub fn new(name: String, actuator_id: u8) -> (Self, CalibrationTask) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, progress) = watch::channel(CalibrationProgress::new(actuator_id));
+        let handle = Self {
+            name,
+            cancel: cancel.clone(),
+            progress,
+        };
+        (handle, CalibrationTask { cancel, progress: sender })
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    pub fn progress(&self) -> CalibrationProgress {
+        self.progress.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<CalibrationProgress> {
+        self.progress.clone()
+    }
+}
+
+/// The running side of a `CalibrationHandle`.
+#[derive(Debug)]
+pub struct CalibrationTask {
+    cancel: Arc<AtomicBool>,
+    progress: watch::Sender<CalibrationProgress>,
+}
+
+impl CalibrationTask {
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut CalibrationProgress)) {
+        self.progress.send_modify(|progress| {
+            f(progress);
+            progress.progress = progress.phase.fraction();
+        });
+    }
+
+    pub fn progress(&self) -> CalibrationProgress {
+        self.progress.borrow().clone()
+    }
+
+    /// Sleeps, returning early with an error if cancelled.
+    async fn sleep(&self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            if self.cancelled() {
+                return Err(ZBotError::Cancelled("Calibration cancelled".to_string()).into());
+            }
+            tokio::time::sleep((deadline - Instant::now()).min(Duration::from_millis(20))).await;
+        }
+        Ok(())
+    }
+
+    /// Finds both end stops of servo `id` by driving into them in speed mode
+    /// and writes the resulting range and offset to its EEPROM. Torque must
+    /// already be enabled. Torque is disabled and position mode restored
+    /// whether or not calibration succeeds.
+    pub async fn run(&self, servos: &ServoMap, id: u8, config: &CalibrationConfig) -> Result<(f32, f32)> {
+        let result = self.find_end_stops(servos, id, config).await;
+
+        if let Err(e) = with_servo(servos, id, |servo| {
+            servo.set_speed(0.0)?;
+            servo.set_operation_mode(FeetechOperationMode::PositionControl)?;
+            servo.disable_torque()
+        })
+        .await
+        {
+            warn!("Failed to restore servo {} after calibration: {}", id, e);
+        }
+
+        match &result {
+            Ok((min, max)) => {
+                info!("Servo {} calibrated: {:.1} to {:.1} deg", id, min, max);
+                self.update(|p| p.phase = CalibrationPhase::Done);
+            }
+            Err(e) => {
+                let phase = if self.cancelled() {
+                    CalibrationPhase::Cancelled
+                } else {
+                    CalibrationPhase::Failed
+                };
+                warn!("Calibration of servo {} {:?}: {}", id, phase, e);
+                self.update(|p| {
+                    p.phase = phase;
+                    p.message = Some(e.to_string());
+                });
+            }
+        }
+        result
+    }
+
+    async fn find_end_stops(&self, servos: &ServoMap, id: u8, config: &CalibrationConfig) -> Result<(f32, f32)> {
+        self.update(|p| p.phase = CalibrationPhase::SeekingMin);
+        let min = self.seek_end_stop(servos, id, -config.speed_deg_per_s, config).await?;
+        self.update(|p| p.min_position_deg = Some(min));
+
+        with_servo(servos, id, |servo| servo.set_speed(0.0)).await?;
+        self.sleep(Duration::from_millis(config.settle_ms)).await?;
+
+        self.update(|p| p.phase = CalibrationPhase::SeekingMax);
+        let max = self.seek_end_stop(servos, id, config.speed_deg_per_s, config).await?;
+        self.update(|p| p.max_position_deg = Some(max));
+
+        self.update(|p| p.phase = CalibrationPhase::Writing);
+        with_servo(servos, id, |servo| {
+            servo.set_speed(0.0)?;
+            servo.write_calibration_data(min, max, 0.0)
+        })
+        .await?;
+        Ok((min, max))
+    }
+
+    /// Drives at `speed` until the current crosses the threshold and returns
+    /// the position reached.
+    async fn seek_end_stop(&self, servos: &ServoMap, id: u8, speed: f32, config: &CalibrationConfig) -> Result<f32> {
+        for _ in 0..COMMAND_REPEATS {
+            with_servo(servos, id, |servo| {
+                servo.set_operation_mode(FeetechOperationMode::SpeedControl)?;
+                servo.set_speed(speed)
+            })
+            .await?;
+            self.sleep(COMMAND_REPEAT_INTERVAL).await?;
+        }
+        self.sleep(Duration::from_millis(config.settle_ms)).await?;
+
+        let timeout = Duration::from_millis(config.end_stop_timeout_ms);
+        let started = Instant::now();
+        loop {
+            let (current, position) = with_servo(servos, id, |servo| {
+                Ok((servo.get_current().unwrap_or(0.0), servo.info().position_deg))
+            })
+            .await?;
+            if current >= config.current_threshold_ma {
+                return Ok(position);
+            }
+            if started.elapsed() > timeout {
+                return Err(ZBotError::Timeout(format!(
+                    "No end stop within {:?} (current {:.0} mA)",
+                    timeout, current
+                ))
+                .into());
+            }
+            self.sleep(Duration::from_millis(config.poll_interval_ms)).await?;
+        }
+    }
+}
+
+async fn with_servo<T>(
+    servos: &ServoMap,
+    id: u8,
+    f: impl FnOnce(&mut dyn FeetechActuator) -> Result<T>,
+) -> Result<T> {
+    let mut servos = servos.write().await;
+    let servo = servos
+        .get_mut(&id)
+        .ok_or(ZBotError::ServoNotFound(id))?;
+    f(servo.as_mut())
+}
+
+/// Starts calibrations on a supervisor's servos and keeps track of them.
+#[derive(Clone)]
+pub struct Calibrator {
+    supervisor: Arc<RwLock<FeetechSupervisor>>,
+    calibrations: Arc<Mutex<HashMap<u8, CalibrationHandle>>>,
+    /// Servos waiting for their group in a batch.
+    queued: Arc<Mutex<BTreeSet<u8>>>,
+    operations: Option<Arc<dyn OperationSink>>,
+}
+
+impl Calibrator {
+    pub fn new(supervisor: Arc<RwLock<FeetechSupervisor>>) -> Self {
+        Self {
+            supervisor,
+            calibrations: Arc::new(Mutex::new(HashMap::new())),
+            queued: Arc::new(Mutex::new(BTreeSet::new())),
+            operations: None,
+        }
+    }
+
+    /// Publishes progress of every calibration started from now on.
+    pub fn set_operations(&mut self, operations: Arc<dyn OperationSink>) {
+        self.operations = Some(operations);
+    }
+
+    /// The latest calibration of a servo, running or finished.
+    pub fn handle(&self, id: u8) -> Option<CalibrationHandle> {
+        self.calibrations.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Running, or queued in a batch.
+    pub fn is_calibrating(&self, id: u8) -> bool {
+        self.queued.lock().unwrap().contains(&id)
+            || self
+                .handle(id)
+                .is_some_and(|handle| !handle.progress().phase.is_finished())
+    }
+
+    /// Cancels a running calibration or drops the servo from its batch.
+    pub fn cancel(&self, id: u8) -> bool {
+        let dequeued = self.queued.lock().unwrap().remove(&id);
+        match self.handle(id) {
+            Some(handle) if !handle.progress().phase.is_finished() => {
+                handle.cancel();
+                true
+            }
+            _ => dequeued,
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        self.queued.lock().unwrap().clear();
+        for handle in self.calibrations.lock().unwrap().values() {
+            handle.cancel();
+        }
+    }
+
+    /// Enables torque on servo `id` and calibrates it in the background.
+    pub async fn start(
+        &self,
+        id: u8,
+        config: &CalibrationConfig,
+    ) -> Result<(CalibrationHandle, JoinHandle<Result<(f32, f32)>>)> {
+        let (handle, task) = {
+            let mut calibrations = self.calibrations.lock().unwrap();
+            if calibrations
+                .get(&id)
+                .is_some_and(|handle| !handle.progress().phase.is_finished())
+            {
+                return Err(ZBotError::InvalidArgument(format!("Servo {} is already calibrating", id)).into());
+            }
+            let name = format!("operations/calibrate_actuator/{}", Uuid::new_v4());
+            let (handle, task) = CalibrationHandle::new(name, id);
+            calibrations.insert(id, handle.clone());
+            (handle, task)
+        };
+
+        let servos = {
+            let mut supervisor = self.supervisor.write().await;
+            if let Err(e) = supervisor.enable_torque(id).await {
+                self.calibrations.lock().unwrap().remove(&id);
+                return Err(e);
+            }
+            supervisor.servos.clone()
+        };
+
+        if let Some(operations) = &self.operations {
+            operations.publish(handle.progress().to_operation(&handle.name)).await;
+            let operations = operations.clone();
+            let name = handle.name.clone();
+            let mut progress = handle.subscribe();
+            tokio::spawn(async move {
+                while progress.changed().await.is_ok() {
+                    let operation = progress.borrow_and_update().to_operation(&name);
+                    operations.publish(operation).await;
+                }
+            });
+        }
+
+        info!("Calibrating servo {} ({})", id, handle.name);
+        let config = config.clone();
+        let join = tokio::spawn(async move { task.run(&servos, id, &config).await });
+        Ok((handle, join))
+    }
+
+    /// Calibrates `groups` one after another, the servos of a group in
+    /// parallel, saving each group's results to the calibration store.
+    /// Stops after a group in which a calibration was cancelled.
+    pub fn start_batch(
+        &self,
+        groups: Vec<Vec<u8>>,
+        config: &CalibrationConfig,
+        joint_names: HashMap<u8, String>,
+        robot_serial: &str,
+    ) -> Result<(String, watch::Receiver<BatchCalibrationProgress>)> {
+        let ids: BTreeSet<u8> = groups.iter().flatten().copied().collect();
+        {
+            let mut queued = self.queued.lock().unwrap();
+            if let Some(id) = ids.iter().find(|&&id| {
+                queued.contains(&id)
+                    || self
+                        .handle(id)
+                        .is_some_and(|handle| !handle.progress().phase.is_finished())
+            }) {
+                return Err(ZBotError::InvalidArgument(format!("Servo {} is already calibrating", id)).into());
+            }
+            queued.extend(&ids);
+        }
+
+        let name = format!("operations/calibrate_actuator/{}", Uuid::new_v4());
+        let (sender, progress) = watch::channel(BatchCalibrationProgress {
+            total: ids.len(),
+            ..Default::default()
+        });
+        if let Some(operations) = self.operations.clone() {
+            let name = name.clone();
+            let mut progress = progress.clone();
+            tokio::spawn(async move {
+                loop {
+                    let operation = progress.borrow_and_update().to_operation(&name);
+                    operations.publish(operation).await;
+                    if progress.changed().await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        info!("Batch calibrating {} servos in {} groups ({})", ids.len(), groups.len(), name);
+        let calibrator = self.clone();
+        let config = config.clone();
+        let robot_serial = robot_serial.to_string();
+        tokio::spawn(async move {
+            let result = calibrator
+                .run_batch(&groups, &config, &joint_names, &robot_serial, &sender)
+                .await;
+            calibrator.queued.lock().unwrap().retain(|id| !ids.contains(id));
+            if let Err(e) = &result {
+                error!("Batch calibration failed: {}", e);
+            }
+            sender.send_modify(|progress| {
+                progress.running.clear();
+                progress.finished = true;
+                if let Err(e) = result {
+                    progress.message = Some(e.to_string());
+                }
+            });
+        });
+
+        Ok((name, progress))
+    }
+
+    async fn run_batch(
+        &self,
+        groups: &[Vec<u8>],
+        config: &CalibrationConfig,
+        joint_names: &HashMap<u8, String>,
+        robot_serial: &str,
+        progress: &watch::Sender<BatchCalibrationProgress>,
+    ) -> Result<()> {
+        let mut store = CalibrationStore::load(&config.store_path, robot_serial)?;
+        let servos = self.supervisor.read().await.servos.clone();
+
+        for group in groups {
+            let mut running = Vec::new();
+            for &id in group {
+                if !self.queued.lock().unwrap().remove(&id) {
+                    continue;
+                }
+                match self.start(id, config).await {
+                    Ok((_, join)) => running.push((id, join)),
+                    Err(e) => {
+                        warn!("Failed to start calibration of servo {}: {}", id, e);
+                        progress.send_modify(|progress| progress.failed.push(id));
+                    }
+                }
+            }
+            progress.send_modify(|progress| progress.running = running.iter().map(|(id, _)| *id).collect());
+
+            let mut cancelled = false;
+            for (id, join) in running {
+                let result = join.await.map_err(|e| eyre!("Calibration task failed: {}", e))?;
+                let calibration = match result {
+                    Ok((min, max)) => with_servo(&servos, id, |servo| servo.read_calibration_registers())
+                        .await
+                        .map(|registers| {
+                            let joint = joint_names.get(&id).map_or("", String::as_str);
+                            ServoCalibration::new(joint, min, max, registers)
+                        }),
+                    Err(e) => Err(e),
+                };
+                cancelled |= self
+                    .handle(id)
+                    .is_some_and(|handle| handle.progress().phase == CalibrationPhase::Cancelled);
+                progress.send_modify(|progress| {
+                    progress.running.retain(|&running| running != id);
+                    match &calibration {
+                        Ok(_) => progress.calibrated.push(id),
+                        Err(_) => progress.failed.push(id),
+                    }
+                });
+                if let Ok(calibration) = calibration {
+                    store.servos.insert(id, calibration);
+                }
+            }
+
+            store.save(&config.store_path)?;
+            if cancelled {
+                return Err(ZBotError::Cancelled("Batch calibration cancelled".to_string()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::feetech::FeetechActuatorType;
+    use crate::firmware::feetech_sim::{SimServo, SimulatedBus};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn calibration_finds_both_end_stops() {
+        let bus = Arc::new(SimulatedBus::new());
+        bus.insert_servo(SimServo::new(1, FeetechActuatorType::Sts3215).with_end_stops(-30.0, 40.0));
+        let mut supervisor = FeetechSupervisor::new(bus.clone()).unwrap();
+        supervisor.add_servo(1, FeetechActuatorType::Sts3215).await.unwrap();
+        let supervisor = Arc::new(RwLock::new(supervisor));
+        let calibrator = Calibrator::new(supervisor.clone());
+        let config = CalibrationConfig {
+            speed_deg_per_s: 90.0,
+            settle_ms: 100,
+            end_stop_timeout_ms: 5000,
+            ..Default::default()
+        };
+
+        let (handle, join) = calibrator.start(1, &config).await.unwrap();
+        let (min, max) = join.await.unwrap().unwrap();
+
+        let progress = handle.progress();
+        assert_eq!(progress.phase, CalibrationPhase::Done, "{:?}", progress.message);
+        assert_eq!(progress.min_position_deg, Some(min));
+        assert_eq!(progress.max_position_deg, Some(max));
+        assert!((min + 30.0).abs() < 3.0, "min end stop at {}", min);
+        assert!((max - 40.0).abs() < 3.0, "max end stop at {}", max);
+        assert_eq!(bus.with_servo(1, |servo| servo.torque_enabled()), Some(false));
+        supervisor.read().await.shutdown().await;
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::ZBotError;
+use crate::firmware::feetech::{CalibrationRegisters, FeetechActuator};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Calibration results of one servo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServoCalibration {
+    pub joint: String,
+    /// End stops found by calibration, in servo degrees before the offset
+    /// was written.
+    pub min_position_deg: f32,
+    pub max_position_deg: f32,
+    /// What calibration left in EEPROM; these are verified and restored.
+    pub registers: CalibrationRegisters,
+    /// Unix time, in seconds.
+    pub calibrated_at: u64,
+}
+
+impl ServoCalibration {
+    pub fn new(joint: &str, min_position_deg: f32, max_position_deg: f32, registers: CalibrationRegisters) -> Self {
+        Self {
+            joint: joint.to_string(),
+            min_position_deg,
+            max_position_deg,
+            registers,
+            calibrated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        }
+    }
+}
+
+/// Outcome of checking one servo against the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationCheck {
+    Match,
+    Mismatch {
+        expected: CalibrationRegisters,
+        found: CalibrationRegisters,
+    },
+    Restored,
+    /// The servo is not on the bus or did not answer.
+    Unavailable,
+}
+
+/// Per-robot calibration file, keyed by servo id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationStore {
+    pub robot_serial: String,
+    pub servos: BTreeMap<u8, ServoCalibration>,
+}
+
+impl CalibrationStore {
+    pub fn new(robot_serial: &str) -> Self {
+        Self {
+            robot_serial: robot_serial.to_string(),
+            servos: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the store at `path`. A missing file gives an empty store for
+    /// `robot_serial`. A store written for another robot is an error, so a
+    /// calibration run never overwrites it.
+    pub fn load<P: AsRef<Path>>(path: P, robot_serial: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new(robot_serial)),
+            Err(e) => {
+                return Err(ZBotError::Io(format!("Failed to read calibration store {}: {}", path.display(), e)).into())
+            }
+        };
+        let store: Self = serde_json::from_str(&contents).map_err(|e| {
+            ZBotError::InvalidArgument(format!("Failed to parse calibration store {}: {}", path.display(), e))
+        })?;
+        if store.robot_serial != robot_serial {
+            return Err(ZBotError::InvalidArgument(format!(
+                "Calibration store {} belongs to robot '{}', not '{}'",
+                path.display(),
+                store.robot_serial,
+                robot_serial
+            ))
+            .into());
+        }
+        Ok(store)
+    }
+
+    /// Writes the store atomically, so a crash never leaves a partial file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ZBotError::Io(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+        let contents = serde_json::to_string_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| ZBotError::Io(format!("Failed to write calibration store {}: {}", path.display(), e)))?;
+        info!("Saved calibration of {} servos to {}", self.servos.len(), path.display());
+        Ok(())
+    }
+
+    /// Compares each stored servo's EEPROM with the store, rewriting it when
+    /// `restore` is set.
+    pub fn verify(
+        &self,
+        servos: &mut HashMap<u8, Box<dyn FeetechActuator>>,
+        restore: bool,
+    ) -> BTreeMap<u8, CalibrationCheck> {
+        let mut results = BTreeMap::new();
+        for (&id, calibration) in &self.servos {
+            let check = match servos.get_mut(&id) {
+                Some(servo) => check_servo(servo.as_mut(), calibration, restore),
+                None => CalibrationCheck::Unavailable,
+            };
+            match check {
+                CalibrationCheck::Match => {}
+                CalibrationCheck::Mismatch { expected, found } => warn!(
+                    "Servo {} ({}) calibration differs from the store: expected {:?}, found {:?}",
+                    id, calibration.joint, expected, found
+                ),
+                CalibrationCheck::Restored => {
+                    info!("Restored calibration of servo {} ({})", id, calibration.joint)
+                }
+                CalibrationCheck::Unavailable => {
+                    warn!("Cannot verify calibration of servo {} ({})", id, calibration.joint)
+                }
+            }
+            results.insert(id, check);
+        }
+        results
+    }
+}
+
+fn check_servo(servo: &mut dyn FeetechActuator, calibration: &ServoCalibration, restore: bool) -> CalibrationCheck {
+    let found = match servo.read_calibration_registers() {
+        Ok(found) => found,
+        Err(e) => {
+            warn!("Failed to read calibration of servo {}: {}", servo.id(), e);
+            return CalibrationCheck::Unavailable;
+        }
+    };
+    let expected = calibration.registers;
+    if found == expected {
+        return CalibrationCheck::Match;
+    }
+    if restore {
+        match servo.write_calibration_registers(&expected) {
+            Ok(()) => return CalibrationCheck::Restored,
+            Err(e) => warn!("Failed to restore calibration of servo {}: {}", servo.id(), e),
+        }
+    }
+    CalibrationCheck::Mismatch { expected, found }
+}
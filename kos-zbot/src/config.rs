@@ -0,0 +1,646 @@
+use crate::calibration::CalibrationConfig;
+use crate::error::{prefixed, ZBotError};
+use crate::firmware::feetech::{FeetechActuatorType, FeetechBus, MailboxBus, DEFAULT_POLL_RATE_HZ, MAX_SERVOS};
+use crate::firmware::liveness::LivenessConfig;
+use crate::firmware::loop_timing::{MAX_LOOP_RATE_HZ, MIN_LOOP_RATE_HZ};
+use crate::firmware::feetech_serial::{SerialBus, DEFAULT_BAUD_RATE};
+use crate::firmware::feetech_sim::SimulatedBus;
+use crate::firmware::feetech_servo::STATUS_FAULTS;
+use crate::firmware::protection::ProtectionLimits;
+use eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// Robot description loaded when `ZBOT_CONFIG` is not set.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/kos/zbot.toml";
+/// Environment variable overriding the robot description path.
+pub const CONFIG_PATH_ENV: &str = "ZBOT_CONFIG";
+
+const BUILTIN_CONFIG: &str = include_str!("../config/zbot.toml");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobotConfig {
+    pub name: String,
+    /// Identifies this robot's calibration file. Defaults to `name`.
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Unit of joint positions and velocities in the actuator service.
+    #[serde(default)]
+    pub units: JointUnits,
+    /// What to do with commands outside a joint's limits.
+    #[serde(default)]
+    pub limit_policy: LimitPolicy,
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// When servos count as offline and back online.
+    #[serde(default)]
+    pub liveness: LivenessConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub estop: EStopConfig,
+    /// Defaults for every joint's thermal, overload and supply protection.
+    #[serde(default)]
+    pub protection: ProtectionLimits,
+    /// Defaults for end-stop calibration; requests may override speed and
+    /// current threshold.
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
+    #[serde(default)]
+    pub servo_bus: ServoBusConfig,
+    #[serde(default)]
+    pub imu: Option<ImuConfig>,
+    #[serde(default)]
+    pub led_matrix: Option<LedMatrixConfig>,
+    pub joints: Vec<JointConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JointUnits {
+    Radians,
+    /// The units of the original actuator service, so existing clients keep
+    /// working.
+    #[default]
+    Degrees,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitPolicy {
+    /// Move to the nearest allowed value and report the clamp.
+    #[default]
+    Clamp,
+    /// Drop the command and report it as failed.
+    Reject,
+}
+
+/// Command and telemetry loop rates. Both can be changed at runtime.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlConfig {
+    #[serde(default = "default_command_rate_hz")]
+    pub command_rate_hz: f64,
+    /// Servo telemetry read rate.
+    #[serde(default = "default_poll_rate_hz")]
+    pub poll_rate_hz: f64,
+    /// Send each command broadcast right after a telemetry read, so the bus
+    /// never has both in flight.
+    #[serde(default)]
+    pub phase_lock: bool,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            command_rate_hz: default_command_rate_hz(),
+            poll_rate_hz: default_poll_rate_hz(),
+            phase_lock: false,
+        }
+    }
+}
+
+/// Where supervisor and loop statistics are exported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Address of the Prometheus `/metrics` endpoint, e.g. `127.0.0.1:9102`.
+    /// No endpoint when unset.
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Interval, in ms, of statistics published to telemetry. 0 disables it.
+    #[serde(default = "default_telemetry_interval_ms")]
+    pub telemetry_interval_ms: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            telemetry_interval_ms: default_telemetry_interval_ms(),
+        }
+    }
+}
+
+/// JSON control API for what the actuator service has no messages for.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiConfig {
+    /// Address to serve the API on, e.g. `127.0.0.1:9103`. No API when unset.
+    #[serde(default)]
+    pub listen: Option<String>,
+}
+
+/// What the actuator does once the client stops sending commands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    /// Silence, in ms, before the watchdog trips. 0 disables it.
+    #[serde(default = "default_watchdog_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub action: WatchdogAction,
+    /// Speed used to move to the safe pose.
+    #[serde(default = "default_safe_pose_speed")]
+    pub safe_pose_speed_deg_per_s: f32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: default_watchdog_timeout_ms(),
+            action: WatchdogAction::default(),
+            safe_pose_speed_deg_per_s: default_safe_pose_speed(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogAction {
+    /// Keep the joints where they are.
+    #[default]
+    Hold,
+    /// Move to each joint's `safe_position_deg`, holding joints without one.
+    SafePose,
+    /// Let the joints go limp.
+    DisableTorque,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EStopConfig {
+    /// File holding the e-stop input level, e.g. a sysfs GPIO `value` file.
+    /// Any file containing `0` or `1` works as a stand-in.
+    #[serde(default)]
+    pub input: Option<String>,
+    /// The stop is pressed when the input reads 0.
+    #[serde(default = "default_true")]
+    pub active_low: bool,
+    #[serde(default = "default_estop_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Latch when protection disables torque on any servo.
+    #[serde(default = "default_true")]
+    pub latch_on_protection_trip: bool,
+    /// Servo status faults that latch, by name, e.g. `["Voltage", "Sensor"]`.
+    /// Servos that are calibrating are ignored, since calibration drives
+    /// them into their end stops on purpose.
+    #[serde(default)]
+    pub latch_on_status_faults: Vec<String>,
+}
+
+impl Default for EStopConfig {
+    fn default() -> Self {
+        Self {
+            input: None,
+            active_low: true,
+            poll_interval_ms: default_estop_poll_interval_ms(),
+            latch_on_protection_trip: true,
+            latch_on_status_faults: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServoBusConfig {
+    /// `libfeetech` on the Milk-V small core.
+    #[default]
+    Mailbox,
+    /// Native protocol driver on a tty, e.g. a USB-to-TTL adapter.
+    Serial {
+        path: String,
+        #[serde(default = "default_baud_rate")]
+        baud_rate: u32,
+        /// The adapter echoes what it sends, as half-duplex adapters with TX
+        /// tied to RX do.
+        #[serde(default)]
+        local_echo: bool,
+    },
+    /// In-process simulated servos, one per configured joint.
+    Simulated,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JointConfig {
+    pub name: String,
+    pub servo_id: u8,
+    /// Detected from the model-number register when omitted.
+    #[serde(default)]
+    pub servo_type: Option<FeetechActuatorType>,
+    /// +1 or -1, flips the joint direction relative to the servo.
+    #[serde(default = "default_direction")]
+    pub direction: f32,
+    /// Servo position, in degrees, at which the joint is at zero.
+    #[serde(default)]
+    pub zero_offset_deg: f32,
+    /// Servo turns per joint turn.
+    #[serde(default = "default_gear_ratio")]
+    pub gear_ratio: f32,
+    #[serde(default)]
+    pub min_position_deg: Option<f32>,
+    #[serde(default)]
+    pub max_position_deg: Option<f32>,
+    /// Servo speed limit, also used when a command carries no velocity.
+    #[serde(default)]
+    pub max_velocity_deg_per_s: Option<f32>,
+    /// Largest position change sent to the servo per command tick.
+    #[serde(default)]
+    pub max_step_deg: Option<f32>,
+    /// Servo position for the watchdog's `safe_pose` action.
+    #[serde(default)]
+    pub safe_position_deg: Option<f32>,
+    /// Overrides `protection.trip_temperature_c`; derating starts the same
+    /// margin below it.
+    #[serde(default)]
+    pub max_temperature_c: Option<f32>,
+    /// Batch calibration runs joints sharing a group together, one group at
+    /// a time. Joints without a group are calibrated on their own.
+    #[serde(default)]
+    pub calibration_group: Option<u32>,
+    #[serde(default)]
+    pub kp: Option<f32>,
+    #[serde(default)]
+    pub ki: Option<f32>,
+    #[serde(default)]
+    pub kd: Option<f32>,
+    /// deg/s²
+    #[serde(default)]
+    pub acceleration: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImuType {
+    /// Try the BNO055 first and fall back to the BMI088.
+    #[default]
+    Auto,
+    Bno055,
+    Bmi088,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImuConfig {
+    pub bus: String,
+    #[serde(default, rename = "type")]
+    pub imu_type: ImuType,
+    /// Row-major rotation from sensor axes to robot axes. Only applied to the
+    /// BMI088; the BNO055 fuses orientation on chip.
+    #[serde(default)]
+    pub mounting_rotation: Option<[[f32; 3]; 3]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedMatrixConfig {
+    pub bus: String,
+    #[serde(default = "default_led_matrix_address")]
+    pub address: u16,
+}
+
+fn default_baud_rate() -> u32 {
+    DEFAULT_BAUD_RATE
+}
+
+fn default_direction() -> f32 {
+    1.0
+}
+
+fn default_gear_ratio() -> f32 {
+    1.0
+}
+
+fn default_command_rate_hz() -> f64 {
+    50.0
+}
+
+fn default_poll_rate_hz() -> f64 {
+    DEFAULT_POLL_RATE_HZ
+}
+
+fn default_telemetry_interval_ms() -> u64 {
+    1000
+}
+
+fn default_watchdog_timeout_ms() -> u64 {
+    500
+}
+
+fn default_safe_pose_speed() -> f32 {
+    30.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_estop_poll_interval_ms() -> u64 {
+    10
+}
+
+fn default_led_matrix_address() -> u16 {
+    crate::led_matrix::DISPLAY_ADDR
+}
+
+impl RobotConfig {
+    /// Loads the robot description from `ZBOT_CONFIG`, then
+    /// `DEFAULT_CONFIG_PATH`, falling back to the built-in Zeroth-01 layout.
+    pub fn load() -> Result<Self> {
+        if let Ok(path) = std::env::var(CONFIG_PATH_ENV) {
+            return Self::from_file(path);
+        }
+        if Path::new(DEFAULT_CONFIG_PATH).exists() {
+            return Self::from_file(DEFAULT_CONFIG_PATH);
+        }
+        info!("No robot config found, using built-in Zeroth-01 layout");
+        Self::builtin()
+    }
+
+    pub fn builtin() -> Result<Self> {
+        Self::from_toml(BUILTIN_CONFIG)
+    }
+
+    /// Reads a TOML or JSON (by extension) robot description and validates it.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ZBotError::Io(format!("Failed to read robot config {}: {}", path.display(), e)))?;
+        let config = if path.extension().is_some_and(|ext| ext == "json") {
+            let config: Self = serde_json::from_str(&contents).map_err(|e| {
+                ZBotError::InvalidArgument(format!("Failed to parse robot config {}: {}", path.display(), e))
+            })?;
+            config.validate()?;
+            config
+        } else {
+            Self::from_toml(&contents)
+                .map_err(prefixed(format!("Robot config {}", path.display())))?
+        };
+        info!("Loaded robot config '{}' from {}", config.name, path.display());
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)
+            .map_err(|e| ZBotError::InvalidArgument(format!("Failed to parse robot config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the description for mistakes that would otherwise only show up
+    /// once servos start moving. All problems are reported at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut names = HashSet::new();
+        let mut ids = HashSet::new();
+
+        if self.joints.is_empty() {
+            problems.push("no joints defined".to_string());
+        }
+        if self.joints.len() > MAX_SERVOS {
+            problems.push(format!(
+                "{} joints defined, the servo bus supports at most {}",
+                self.joints.len(),
+                MAX_SERVOS
+            ));
+        }
+
+        for joint in &self.joints {
+            let label = format!("joint '{}'", joint.name);
+            if joint.name.is_empty() {
+                problems.push(format!("joint with servo id {} has an empty name", joint.servo_id));
+            }
+            if !names.insert(joint.name.as_str()) {
+                problems.push(format!("{}: duplicate joint name", label));
+            }
+            if !ids.insert(joint.servo_id) {
+                problems.push(format!("{}: servo id {} is used twice", label, joint.servo_id));
+            }
+            if joint.servo_id == 0 || joint.servo_id >= 0xFE {
+                problems.push(format!("{}: servo id {} is not in 1..=253", label, joint.servo_id));
+            }
+            if joint.direction != 1.0 && joint.direction != -1.0 {
+                problems.push(format!("{}: direction must be 1 or -1, got {}", label, joint.direction));
+            }
+            if !(joint.gear_ratio.is_finite() && joint.gear_ratio > 0.0) {
+                problems.push(format!("{}: gear_ratio must be positive, got {}", label, joint.gear_ratio));
+            }
+            if let Some(position) = joint.safe_position_deg {
+                if !position.is_finite() {
+                    problems.push(format!("{}: safe_position_deg must be finite", label));
+                } else if joint.min_position_deg.is_some_and(|min| position < min)
+                    || joint.max_position_deg.is_some_and(|max| position > max)
+                {
+                    problems.push(format!(
+                        "{}: safe_position_deg ({}) is outside min_position_deg..max_position_deg",
+                        label, position
+                    ));
+                }
+            }
+            if !joint.zero_offset_deg.is_finite() {
+                problems.push(format!("{}: zero_offset_deg must be finite", label));
+            }
+            if let (Some(min), Some(max)) = (joint.min_position_deg, joint.max_position_deg) {
+                if min >= max {
+                    problems.push(format!(
+                        "{}: min_position_deg ({}) must be below max_position_deg ({})",
+                        label, min, max
+                    ));
+                }
+            }
+            for (field, value) in [
+                ("max_velocity_deg_per_s", joint.max_velocity_deg_per_s),
+                ("max_step_deg", joint.max_step_deg),
+            ] {
+                if let Some(value) = value {
+                    if !(value.is_finite() && value > 0.0) {
+                        problems.push(format!("{}: {} must be positive, got {}", label, field, value));
+                    }
+                }
+            }
+            for (field, value) in [("kp", joint.kp), ("ki", joint.ki), ("kd", joint.kd)] {
+                if let Some(value) = value {
+                    if !(0.0..=254.0).contains(&value) {
+                        problems.push(format!("{}: {} must be in 0..=254, got {}", label, field, value));
+                    }
+                }
+            }
+            if let Some(acceleration) = joint.acceleration {
+                if acceleration < 0.0 {
+                    problems.push(format!("{}: acceleration must not be negative", label));
+                }
+            }
+        }
+
+        let rates = MIN_LOOP_RATE_HZ..=MAX_LOOP_RATE_HZ;
+        for (field, rate) in [
+            ("command_rate_hz", self.control.command_rate_hz),
+            ("poll_rate_hz", self.control.poll_rate_hz),
+        ] {
+            if !rates.contains(&rate) {
+                problems.push(format!(
+                    "control: {} must be in {}..={}, got {}",
+                    field, MIN_LOOP_RATE_HZ, MAX_LOOP_RATE_HZ, rate
+                ));
+            }
+        }
+        if self.control.phase_lock && self.control.command_rate_hz > self.control.poll_rate_hz {
+            problems.push("control: phase_lock needs command_rate_hz at or below poll_rate_hz".to_string());
+        }
+        let poll_period_ms = 1000.0 / self.control.poll_rate_hz;
+        if (self.liveness.stale_after_ms as f64) < 2.0 * poll_period_ms {
+            problems.push(format!(
+                "liveness: stale_after_ms must be at least two poll periods ({:.0} ms), got {}",
+                2.0 * poll_period_ms,
+                self.liveness.stale_after_ms
+            ));
+        }
+        if self.liveness.online_after_reads == 0 {
+            problems.push("liveness: online_after_reads must be at least 1".to_string());
+        }
+        if let Some(listen) = &self.metrics.listen {
+            if listen.parse::<std::net::SocketAddr>().is_err() {
+                problems.push(format!("metrics: listen must be an address like 127.0.0.1:9102, got {:?}", listen));
+            }
+        }
+
+        for fault in &self.estop.latch_on_status_faults {
+            if !STATUS_FAULTS.contains(&fault.as_str()) {
+                problems.push(format!(
+                    "estop: unknown status fault {:?} in latch_on_status_faults, expected one of {:?}",
+                    fault, STATUS_FAULTS
+                ));
+            }
+        }
+
+        if !(self.watchdog.safe_pose_speed_deg_per_s.is_finite() && self.watchdog.safe_pose_speed_deg_per_s > 0.0) {
+            problems.push("watchdog: safe_pose_speed_deg_per_s must be positive".to_string());
+        }
+
+        let protection = &self.protection;
+        if protection.derate_temperature_c >= protection.trip_temperature_c {
+            problems.push("protection: derate_temperature_c must be below trip_temperature_c".to_string());
+        }
+        if !(0.0..=1.0).contains(&protection.derate_torque_fraction) {
+            problems.push("protection: derate_torque_fraction must be in 0..=1".to_string());
+        }
+        if protection.continuous_current_ma <= 0.0 || protection.i2t_limit_a2s <= 0.0 {
+            problems.push("protection: continuous_current_ma and i2t_limit_a2s must be positive".to_string());
+        }
+        if let (Some(sag), Some(min)) = (protection.sag_voltage_v, protection.min_voltage_v) {
+            if min >= sag {
+                problems.push("protection: min_voltage_v must be below sag_voltage_v".to_string());
+            }
+        }
+
+        let calibration = &self.calibration;
+        if !(calibration.speed_deg_per_s > 0.0 && calibration.current_threshold_ma > 0.0) {
+            problems.push("calibration: speed_deg_per_s and current_threshold_ma must be positive".to_string());
+        }
+        if calibration.poll_interval_ms == 0 {
+            problems.push("calibration: poll_interval_ms must be positive".to_string());
+        }
+        if calibration.store_path.is_empty() {
+            problems.push("calibration: store_path is empty".to_string());
+        }
+        if self.serial.as_ref().is_some_and(|serial| serial.is_empty()) {
+            problems.push("serial is empty".to_string());
+        }
+
+        if self.estop.poll_interval_ms == 0 {
+            problems.push("estop: poll_interval_ms must be positive".to_string());
+        }
+
+        if let ServoBusConfig::Serial { path, baud_rate, .. } = &self.servo_bus {
+            if path.is_empty() {
+                problems.push("servo_bus: serial path is empty".to_string());
+            }
+            if *baud_rate == 0 {
+                problems.push("servo_bus: baud_rate must be positive".to_string());
+            }
+        }
+
+        if let Some(imu) = &self.imu {
+            if let Some(rotation) = imu.mounting_rotation {
+                if !is_rotation(&rotation) {
+                    problems.push("imu: mounting_rotation is not a proper rotation matrix".to_string());
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Report::new(ZBotError::InvalidArgument(format!(
+                "Invalid robot config '{}':\n  - {}",
+                self.name,
+                problems.join("\n  - ")
+            ))))
+        }
+    }
+
+    /// Protection thresholds for a joint, with its overrides applied.
+    pub fn protection_limits(&self, joint: &JointConfig) -> ProtectionLimits {
+        let mut limits = self.protection.clone();
+        if let Some(trip) = joint.max_temperature_c {
+            let margin = limits.trip_temperature_c - limits.derate_temperature_c;
+            limits.trip_temperature_c = trip;
+            limits.derate_temperature_c = trip - margin;
+        }
+        limits
+    }
+
+    pub fn serial(&self) -> &str {
+        self.serial.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Batch calibration order: each group's servo ids, smallest group first,
+    /// then every ungrouped joint on its own.
+    pub fn calibration_groups(&self) -> Vec<Vec<u8>> {
+        let mut groups: std::collections::BTreeMap<u32, Vec<u8>> = Default::default();
+        let mut singles = Vec::new();
+        for joint in &self.joints {
+            match joint.calibration_group {
+                Some(group) => groups.entry(group).or_default().push(joint.servo_id),
+                None => singles.push(vec![joint.servo_id]),
+            }
+        }
+        groups.into_values().chain(singles).collect()
+    }
+
+    pub fn servo_ids(&self) -> Vec<u8> {
+        self.joints.iter().map(|joint| joint.servo_id).collect()
+    }
+}
+
+impl ServoBusConfig {
+    /// Opens the configured transport. A simulated bus gets one STS3215 per id.
+    pub fn open(&self, servo_ids: &[u8]) -> Result<Arc<dyn FeetechBus>> {
+        Ok(match self {
+            ServoBusConfig::Mailbox => Arc::new(MailboxBus::new()),
+            ServoBusConfig::Serial {
+                path,
+                baud_rate,
+                local_echo,
+            } => Arc::new(SerialBus::open(path, *baud_rate)?.with_local_echo(*local_echo)),
+            ServoBusConfig::Simulated => Arc::new(SimulatedBus::with_servos(servo_ids)),
+        })
+    }
+}
+
+fn is_rotation(m: &[[f32; 3]; 3]) -> bool {
+    const TOLERANCE: f32 = 1e-3;
+    for i in 0..3 {
+        for j in 0..3 {
+            let dot: f32 = (0..3).map(|k| m[i][k] * m[j][k]).sum();
+            let expected = if i == j { 1.0 } else { 0.0 };
+            if (dot - expected).abs() > TOLERANCE {
+                return false;
+            }
+        }
+    }
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    (det - 1.0).abs() < TOLERANCE
+}
@@ -0,0 +1,202 @@
+use crate::firmware::feetech::UnknownServoModel;
+use kos::kos_proto::common::{Error as KosError, ErrorCode};
+use std::fmt::{self, Display};
+
+/// Linux i2c-dev errnos for a transfer nobody acknowledged.
+const ENXIO: i32 = 6;
+const EREMOTEIO: i32 = 121;
+
+/// A failure the HAL can name. Functions still return `eyre::Result`; a
+/// `ZBotError` anywhere in the chain decides the `ErrorCode` clients see.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZBotError {
+    /// No status packet arrived within the bus timeout.
+    BusTimeout,
+    /// A mailbox call to the small core failed or timed out.
+    Mailbox { call: &'static str, code: i32 },
+    /// A status packet failed its checksum.
+    Checksum { id: u8, expected: u8, received: u8 },
+    /// A status packet was malformed, short or from another servo.
+    BadReply(String),
+    ServoNotFound(u8),
+    /// An EEPROM register did not keep a written value.
+    EepromLocked {
+        id: u8,
+        register: String,
+        written: u16,
+        read: u16,
+    },
+    /// A value outside what the hardware or the config accepts.
+    OutOfRange(String),
+    InvalidArgument(String),
+    /// Something the hardware cannot do, e.g. torque control on an STS servo.
+    Unsupported(String),
+    ProtectionTripped { id: u8, faults: String },
+    EStopLatched(String),
+    /// A model file could not be found or initialized.
+    ModelLoad(String),
+    TensorShape {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// Nothing acknowledged on an I2C bus.
+    I2cNack { bus: String },
+    /// A device could not be opened or stopped working.
+    Hardware(String),
+    /// A config, calibration or data file could not be read or written.
+    Io(String),
+    /// An operation did not finish in time, e.g. an end-stop search.
+    Timeout(String),
+    /// An operation was cancelled before it finished.
+    Cancelled(String),
+}
+
+impl ZBotError {
+    /// `Timeout` is the retryable code: the same request may succeed on the
+    /// next attempt. Every other code needs the request or the robot to
+    /// change first.
+    ///
+    /// A negative mailbox code means the mailbox call to the small core
+    /// failed or timed out, which is retryable. A positive code is the
+    /// result the small core got from the servo, e.g. its status byte, so
+    /// the servo answered and a retry would get the same answer. Corrupt
+    /// replies mean a noisy or miswired bus, not a slow one, and the
+    /// EEPROM lock and the e-stop are states of the hardware that only
+    /// change when something clears them. A file that cannot be read or
+    /// written needs its path or permissions fixed, like a bad request.
+    /// The proto has no code for a cancelled operation.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ZBotError::BusTimeout => ErrorCode::Timeout,
+            ZBotError::Mailbox { code, .. } if *code < 0 => ErrorCode::Timeout,
+            ZBotError::Timeout(_) => ErrorCode::Timeout,
+            ZBotError::ServoNotFound(_)
+            | ZBotError::OutOfRange(_)
+            | ZBotError::InvalidArgument(_)
+            | ZBotError::Io(_)
+            | ZBotError::ModelLoad(_)
+            | ZBotError::TensorShape { .. } => ErrorCode::InvalidArgument,
+            ZBotError::Unsupported(_) => ErrorCode::NotImplemented,
+            ZBotError::Mailbox { .. }
+            | ZBotError::Checksum { .. }
+            | ZBotError::BadReply(_)
+            | ZBotError::EepromLocked { .. }
+            | ZBotError::EStopLatched(_)
+            | ZBotError::ProtectionTripped { .. }
+            | ZBotError::I2cNack { .. }
+            | ZBotError::Hardware(_) => ErrorCode::HardwareFailure,
+            ZBotError::Cancelled(_) => ErrorCode::Unknown,
+        }
+    }
+}
+
+impl Display for ZBotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZBotError::BusTimeout => write!(f, "Timed out waiting for status packet"),
+            ZBotError::Mailbox { call, code } => write!(f, "{} returned {}", call, code),
+            ZBotError::Checksum { id, expected, received } => write!(
+                f,
+                "Checksum mismatch from servo {}: expected 0x{:02X}, got 0x{:02X}",
+                id, expected, received
+            ),
+            ZBotError::ServoNotFound(id) => write!(f, "Servo with id {} not found", id),
+            ZBotError::EepromLocked {
+                id,
+                register,
+                written,
+                read,
+            } => write!(
+                f,
+                "Servo {}: {} reads {} after writing {}; is the EEPROM locked?",
+                id, register, read, written
+            ),
+            ZBotError::ProtectionTripped { id, faults } => write!(f, "Servo {} protection tripped: {}", id, faults),
+            ZBotError::TensorShape { name, expected, actual } => {
+                write!(f, "Input '{}' size mismatch: expected {}, got {}", name, expected, actual)
+            }
+            ZBotError::I2cNack { bus } => write!(f, "No acknowledge on {}", bus),
+            ZBotError::BadReply(message)
+            | ZBotError::OutOfRange(message)
+            | ZBotError::InvalidArgument(message)
+            | ZBotError::Unsupported(message)
+            | ZBotError::EStopLatched(message)
+            | ZBotError::ModelLoad(message)
+            | ZBotError::Hardware(message)
+            | ZBotError::Io(message)
+            | ZBotError::Timeout(message)
+            | ZBotError::Cancelled(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ZBotError {}
+
+impl From<ZBotError> for KosError {
+    fn from(error: ZBotError) -> Self {
+        KosError {
+            code: error.code() as i32,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// The code of the outermost classified error in the chain of `error`.
+/// Errors nothing classified are `Unknown`.
+pub fn error_code(error: &eyre::Report) -> ErrorCode {
+    if let Some(error) = error.downcast_ref::<ZBotError>() {
+        return error.code();
+    }
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<ZBotError>() {
+            return error.code();
+        }
+        if cause.downcast_ref::<UnknownServoModel>().is_some() {
+            return ErrorCode::NotImplemented;
+        }
+        if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+            return match error.kind() {
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorCode::Timeout,
+                _ => ErrorCode::HardwareFailure,
+            };
+        }
+    }
+    ErrorCode::Unknown
+}
+
+pub fn is_retryable(error: &eyre::Report) -> bool {
+    error_code(error) == ErrorCode::Timeout
+}
+
+pub fn to_kos_error(error: &eyre::Report) -> KosError {
+    KosError {
+        code: error_code(error) as i32,
+        message: error.to_string(),
+    }
+}
+
+/// Prefixes the message of an error, keeping the error in the chain for
+/// `error_code`.
+pub fn prefixed<E: Into<eyre::Report>>(prefix: impl Display) -> impl FnOnce(E) -> eyre::Report {
+    move |error| {
+        let error = error.into();
+        let message = format!("{}: {}", prefix, error);
+        error.wrap_err(message)
+    }
+}
+
+/// Classifies an error from an I2C device on `bus`.
+pub fn i2c_error<E: Into<eyre::Report>>(bus: &str, error: E) -> eyre::Report {
+    let report = error.into();
+    let nack = report.chain().any(|cause| {
+        let errno = cause.downcast_ref::<std::io::Error>().and_then(std::io::Error::raw_os_error);
+        matches!(errno, Some(ENXIO | EREMOTEIO))
+    });
+    let message = format!("{}: {}", bus, report);
+    if nack {
+        report.wrap_err(ZBotError::I2cNack { bus: bus.to_string() })
+    } else {
+        report.wrap_err(ZBotError::Hardware(message))
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::EStopConfig;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// Transitions kept for `EStop::history`.
+const HISTORY_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EStopSource {
+    /// A client of the actuator service.
+    Service,
+    /// The hardware e-stop input.
+    Input,
+    /// An internal fault detector.
+    Fault,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EStopState {
+    Clear,
+    Latched {
+        source: EStopSource,
+        reason: String,
+        at: SystemTime,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct EStopTransition {
+    pub at: SystemTime,
+    pub state: EStopState,
+    /// Who cleared the stop; `None` for a latch.
+    pub cleared_by: Option<EStopSource>,
+}
+
+/// Latched emergency stop. Once triggered it stays latched, whatever the
+/// source does afterwards, until `clear` is called.
+#[derive(Debug)]
+pub struct EStop {
+    state: watch::Sender<EStopState>,
+    history: Mutex<VecDeque<EStopTransition>>,
+}
+
+impl Default for EStop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EStop {
+    pub fn new() -> Self {
+        Self {
+            state: watch::channel(EStopState::Clear).0,
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_LEN)),
+        }
+    }
+
+    pub fn state(&self) -> EStopState {
+        self.state.borrow().clone()
+    }
+
+    pub fn is_latched(&self) -> bool {
+        matches!(*self.state.borrow(), EStopState::Latched { .. })
+    }
+
+    /// Receives every latch and clear.
+    pub fn subscribe(&self) -> watch::Receiver<EStopState> {
+        self.state.subscribe()
+    }
+
+    /// Latches the stop. Returns false if it was already latched, in which
+    /// case the original source and reason are kept.
+    pub fn trigger(&self, source: EStopSource, reason: impl Into<String>) -> bool {
+        let reason = reason.into();
+        let latched = self.state.send_if_modified(|state| {
+            if let EStopState::Latched { .. } = state {
+                return false;
+            }
+            *state = EStopState::Latched {
+                source,
+                reason: reason.clone(),
+                at: SystemTime::now(),
+            };
+            true
+        });
+        if latched {
+            error!("E-stop latched by {:?}: {}", source, reason);
+            self.record(None);
+        }
+        latched
+    }
+
+    /// Releases a latched stop. Torque stays off until re-enabled per servo.
+    pub fn clear(&self, source: EStopSource) -> bool {
+        let cleared = self.state.send_if_modified(|state| {
+            if *state == EStopState::Clear {
+                return false;
+            }
+            *state = EStopState::Clear;
+            true
+        });
+        if cleared {
+            info!("E-stop cleared by {:?}", source);
+            self.record(Some(source));
+        }
+        cleared
+    }
+
+    /// Most recent transitions, oldest first.
+    pub fn history(&self) -> Vec<EStopTransition> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, cleared_by: Option<EStopSource>) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(EStopTransition {
+            at: SystemTime::now(),
+            state: self.state(),
+            cleared_by,
+        });
+    }
+
+    /// Polls the configured input and latches the stop while it is active.
+    /// An unreadable input counts as pressed, so a pulled wire stops the robot.
+    pub fn spawn_input_monitor(self: &Arc<Self>, config: &EStopConfig) {
+        let Some(path) = config.input.clone() else {
+            return;
+        };
+        let estop = self.clone();
+        let active_level = if config.active_low { "0" } else { "1" };
+        let period = Duration::from_millis(config.poll_interval_ms);
+        info!("Monitoring e-stop input {}", path);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut read_failed = false;
+            loop {
+                interval.tick().await;
+                match tokio::fs::read_to_string(&path).await {
+                    Ok(value) => {
+                        if read_failed {
+                            warn!("E-stop input {} readable again", path);
+                            read_failed = false;
+                        }
+                        if value.trim() == active_level {
+                            estop.trigger(EStopSource::Input, format!("input {} active", path));
+                        }
+                    }
+                    Err(e) => {
+                        if !read_failed {
+                            warn!("Failed to read e-stop input {}: {}", path, e);
+                            read_failed = true;
+                        }
+                        estop.trigger(EStopSource::Input, format!("input {} unreadable: {}", path, e));
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigger_latches_until_cleared() {
+        let estop = EStop::new();
+        assert!(!estop.is_latched());
+        assert!(estop.trigger(EStopSource::Input, "pressed"));
+        // A second trigger keeps the original source and reason.
+        assert!(!estop.trigger(EStopSource::Fault, "overload"));
+        assert!(matches!(
+            estop.state(),
+            EStopState::Latched { source: EStopSource::Input, ref reason, .. } if reason == "pressed"
+        ));
+
+        assert!(estop.clear(EStopSource::Service));
+        assert!(!estop.is_latched());
+        assert!(!estop.clear(EStopSource::Service), "cleared a clear stop");
+        assert!(estop.trigger(EStopSource::Fault, "overload"));
+        assert!(estop.is_latched());
+    }
+
+    #[test]
+    fn history_records_each_transition() {
+        let estop = EStop::new();
+        let mut state = estop.subscribe();
+        estop.trigger(EStopSource::Service, "stop");
+        estop.trigger(EStopSource::Service, "stop again");
+        estop.clear(EStopSource::Input);
+        assert!(state.has_changed().unwrap());
+        assert_eq!(*state.borrow_and_update(), EStopState::Clear);
+
+        let history = estop.history();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(history[0].state, EStopState::Latched { .. }));
+        assert_eq!(history[0].cleared_by, None);
+        assert_eq!(history[1].state, EStopState::Clear);
+        assert_eq!(history[1].cleared_by, Some(EStopSource::Input));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::ZBotError;
 use eyre::Result;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -45,7 +46,11 @@ impl Model {
         let c_model_path = CString::new(model_path.as_ref().to_str().unwrap())?;
         let result = unsafe { init_model(c_model_path.as_ptr()) };
         if result != 0 {
-            eyre::bail!("Failed to initialize MilkV model");
+            return Err(ZBotError::ModelLoad(format!(
+                "Failed to initialize MilkV model {}",
+                model_path.as_ref().display()
+            ))
+            .into());
         }
         Ok(Model { _private: () })
     }
@@ -63,7 +68,7 @@ impl Model {
             let name = unsafe {
                 let name_ptr = get_input_name_at(i as c_int);
                 if name_ptr.is_null() {
-                    return Err(eyre::eyre!("Failed to get input name at index {}", i));
+                    return Err(ZBotError::Hardware(format!("Failed to get input name at index {}", i)).into());
                 }
                 std::ffi::CStr::from_ptr(name_ptr)
                     .to_string_lossy()
@@ -72,17 +77,17 @@ impl Model {
 
             let input = inputs
                 .get(&name)
-                .ok_or_else(|| eyre::eyre!("Missing input tensor: {}", name))?;
+                .ok_or_else(|| ZBotError::InvalidArgument(format!("Missing input tensor: {}", name)))?;
 
             let expected_size =
                 unsafe { get_input_size_at(i as c_int) } / std::mem::size_of::<f32>();
             if input.len() != expected_size {
-                return Err(eyre::eyre!(
-                    "Input '{}' size mismatch: expected {}, got {}",
+                return Err(ZBotError::TensorShape {
                     name,
-                    expected_size,
-                    input.len()
-                ));
+                    expected: expected_size,
+                    actual: input.len(),
+                }
+                .into());
             }
 
             input_data.push(input.clone());
@@ -110,7 +115,7 @@ impl Model {
         };
 
         if result != 0 {
-            return Err(eyre::eyre!("Forward pass failed"));
+            return Err(ZBotError::Hardware("Forward pass failed".to_string()).into());
         }
 
         // Build output hashmap with correct tensor names
@@ -119,7 +124,7 @@ impl Model {
             let name = unsafe {
                 let name_ptr = get_output_name_at(i as c_int);
                 if name_ptr.is_null() {
-                    return Err(eyre::eyre!("Failed to get output name at index {}", i));
+                    return Err(ZBotError::Hardware(format!("Failed to get output name at index {}", i)).into());
                 }
                 std::ffi::CStr::from_ptr(name_ptr)
                     .to_string_lossy()
@@ -139,7 +144,7 @@ impl Model {
             let name = unsafe {
                 let name_ptr = get_input_name_at(i as c_int);
                 if name_ptr.is_null() {
-                    eyre::bail!("Failed to get input name at index {}", i);
+                    return Err(ZBotError::Hardware(format!("Failed to get input name at index {}", i)).into());
                 }
                 std::ffi::CStr::from_ptr(name_ptr)
                     .to_string_lossy()
@@ -153,7 +158,7 @@ impl Model {
             let shape_result =
                 unsafe { get_input_shape_at(i as c_int, dims.as_mut_ptr(), &mut dim_count) };
             if shape_result != 0 {
-                eyre::bail!("Failed to get input shape at index {}", i);
+                return Err(ZBotError::Hardware(format!("Failed to get input shape at index {}", i)).into());
             }
 
             info.push(TensorInfo {
@@ -175,7 +180,7 @@ impl Model {
             let name = unsafe {
                 let name_ptr = get_output_name_at(i as c_int);
                 if name_ptr.is_null() {
-                    eyre::bail!("Failed to get output name at index {}", i);
+                    return Err(ZBotError::Hardware(format!("Failed to get output name at index {}", i)).into());
                 }
                 std::ffi::CStr::from_ptr(name_ptr)
                     .to_string_lossy()
@@ -189,7 +194,7 @@ impl Model {
             let shape_result =
                 unsafe { get_output_shape_at(i as c_int, dims.as_mut_ptr(), &mut dim_count) };
             if shape_result != 0 {
-                eyre::bail!("Failed to get output shape at index {}", i);
+                return Err(ZBotError::Hardware(format!("Failed to get output shape at index {}", i)).into());
             }
 
             info.push(TensorInfo {
@@ -1,15 +1,26 @@
-use super::feetech_servo::Sts3215;
+use super::feetech_servo::{answers_on, StsServo, STS3215, STS3250};
+use super::liveness::{Liveness, LivenessConfig, LivenessEvent, LivenessMonitor};
+use super::loop_timing::{loop_period, LoopTimer, LoopTiming};
+use super::protection::{ProtectionLevel, ProtectionMonitor};
+use super::stats::{StatsCollector, SupervisorStats};
+use crate::error::ZBotError;
 use eyre::Result;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::os::raw::{c_int, c_short, c_uchar, c_uint, c_ushort};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{info, trace, warn};
+use tokio::sync::{broadcast, watch, Notify, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
 const MAX_SHMEM_DATA: usize = 2048;
 pub const MAX_SERVOS: usize = 32;
+/// Highest assignable servo id; 254 is the broadcast id.
+pub const MAX_SERVO_ID: u8 = 253;
+/// Model number register, shared by every Feetech servo.
+pub const MODEL_NUMBER_ADDRESS: u8 = 0x03;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ServoInfo {
     pub id: c_uchar,
     pub last_read_ms: c_uint,
@@ -64,17 +75,132 @@ pub struct BroadcastCommand {
 }
 
 #[link(name = "feetech")]
-#[allow(dead_code)]
 extern "C" {
     fn servo_init() -> c_int;
     fn servo_deinit();
     fn servo_write(id: c_uchar, address: c_uchar, data: *const c_uchar, length: c_uchar) -> c_int;
     fn servo_read(id: c_uchar, address: c_uchar, data: *mut c_uchar, length: c_uchar) -> c_int;
-    pub fn servo_set_active_servos(active_servos: ActiveServoList) -> c_int;
-    pub fn servo_get_info(info_buffer: *mut ServoInfoBuffer) -> c_int;
+    fn servo_set_active_servos(active_servos: ActiveServoList) -> c_int;
+    fn servo_get_info(info_buffer: *mut ServoInfoBuffer) -> c_int;
     fn servo_broadcast_command(command: BroadcastCommand) -> c_int;
 }
 
+/// Transport used to reach the servo chain.
+///
+/// Each call is a single attempt; retries are handled by `feetech_read` and
+/// `feetech_write`.
+pub trait FeetechBus: Send + Sync + std::fmt::Debug {
+    fn init(&self) -> Result<()>;
+    fn deinit(&self);
+    fn read(&self, id: u8, address: u8, data: &mut [u8]) -> Result<()>;
+    fn write(&self, id: u8, address: u8, data: &[u8]) -> Result<()>;
+    fn set_active_servos(&self, active_servos: &ActiveServoList) -> Result<()>;
+    fn get_info(&self, info_buffer: &mut ServoInfoBuffer) -> Result<()>;
+    fn broadcast_command(&self, command: &BroadcastCommand) -> Result<()>;
+}
+
+/// Bus backed by `libfeetech`, which talks to the small core over the
+/// mailbox and ION shared memory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MailboxBus;
+
+impl MailboxBus {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl FeetechBus for MailboxBus {
+    fn init(&self) -> Result<()> {
+        unsafe {
+            if servo_init() != 0 {
+                return Err(ZBotError::Hardware("Failed to initialize servo system".to_string()).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn deinit(&self) {
+        unsafe {
+            servo_deinit();
+        }
+    }
+
+    fn read(&self, id: u8, address: u8, data: &mut [u8]) -> Result<()> {
+        let result = unsafe { servo_read(id, address, data.as_mut_ptr(), data.len() as c_uchar) };
+        if result != 0 {
+            return Err(ZBotError::Mailbox {
+                call: "servo_read",
+                code: result,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn write(&self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        let result = unsafe { servo_write(id, address, data.as_ptr(), data.len() as c_uchar) };
+        if result != 0 {
+            return Err(ZBotError::Mailbox {
+                call: "servo_write",
+                code: result,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn set_active_servos(&self, active_servos: &ActiveServoList) -> Result<()> {
+        let list = ActiveServoList {
+            len: active_servos.len,
+            servo_id: active_servos.servo_id,
+        };
+        unsafe {
+            let code = servo_set_active_servos(list);
+            if code != 0 {
+                return Err(ZBotError::Mailbox {
+                    call: "servo_set_active_servos",
+                    code,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn get_info(&self, info_buffer: &mut ServoInfoBuffer) -> Result<()> {
+        unsafe {
+            let code = servo_get_info(info_buffer);
+            if code != 0 {
+                return Err(ZBotError::Mailbox {
+                    call: "servo_get_info",
+                    code,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast_command(&self, command: &BroadcastCommand) -> Result<()> {
+        let command = BroadcastCommand {
+            data_length: command.data_length,
+            data: command.data,
+        };
+        unsafe {
+            let code = servo_broadcast_command(command);
+            if code != 0 {
+                return Err(ZBotError::Mailbox {
+                    call: "servo_broadcast_command",
+                    code,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum FeetechOperationMode {
     PositionControl,
@@ -82,7 +208,8 @@ pub enum FeetechOperationMode {
     TorqueControl,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FeetechActuatorType {
     Sts3215,
     Sts3250,
@@ -108,6 +235,25 @@ impl FeetechActuatorType {
     }
 }
 
+/// Returned when a servo reports a model number that has no driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownServoModel {
+    pub id: u8,
+    pub model_id: [u8; 2],
+}
+
+impl std::fmt::Display for UnknownServoModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Servo {} reports unknown model 0x{:02X}{:02X}",
+            self.id, self.model_id[0], self.model_id[1]
+        )
+    }
+}
+
+impl std::error::Error for UnknownServoModel {}
+
 #[derive(Debug, Clone, Default)]
 pub struct FeetechActuatorInfo {
     pub id: u8,
@@ -115,25 +261,41 @@ pub struct FeetechActuatorInfo {
     pub position_deg: f32,
     pub speed_deg_per_s: f32,
     pub load_percent: f32,
+    pub torque_nm: f32,
     pub voltage_v: f32,
+    /// Present current in mA: the register times 6.5 mA.
     pub current_ma: f32,
     pub temperature_c: f32,
+    /// Read timestamp from the bus. Only meaningful for change detection.
     pub last_read_ms: u32,
-    pub last_read_local_ms: u32,
+    pub last_reading_at: Option<Instant>,
     pub online: bool,
     pub faults: Vec<String>,
 }
 
+/// Raw EEPROM end-stop and offset registers written by calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CalibrationRegisters {
+    pub min_angle: u16,
+    pub max_angle: u16,
+    pub offset: u16,
+}
+
 pub trait FeetechActuator: Send + Sync + std::fmt::Debug {
     fn id(&self) -> u8;
+    fn check_id(&self) -> Result<()>;
     fn info(&self) -> FeetechActuatorInfo;
     fn set_position(&mut self, position_deg: f32) -> Result<()>;
     fn set_speed(&mut self, speed_deg_per_s: f32) -> Result<()>;
     fn set_acceleration(&mut self, accel_deg_per_s2: f32) -> Result<()>;
+    /// Limits output torque to a fraction of the maximum. Not persisted.
+    fn set_torque_limit(&mut self, fraction: f32) -> Result<()>;
     fn enable_torque(&mut self) -> Result<()>;
     fn disable_torque(&mut self) -> Result<()>;
     fn change_id(&mut self, id: u8) -> Result<()>;
     fn update_info(&mut self, info: &ServoInfo);
+    /// Records whether the supervisor considers the servo responding.
+    fn update_liveness(&mut self, liveness: &Liveness);
     fn degrees_to_raw(&self, degrees: f32, offset: f32) -> u16;
     fn raw_to_degrees(&self, raw: u16, offset: f32) -> f32;
     fn set_pid(&mut self, p: Option<f32>, i: Option<f32>, d: Option<f32>) -> Result<()>;
@@ -141,36 +303,104 @@ pub trait FeetechActuator: Send + Sync + std::fmt::Debug {
     fn get_current(&self) -> Result<f32>;
     fn write_calibration_data(&mut self, min_angle: f32, max_angle: f32, offset: f32)
         -> Result<()>;
+    fn read_calibration_registers(&self) -> Result<CalibrationRegisters>;
+    /// Writes previously read calibration registers back to EEPROM.
+    fn write_calibration_registers(&mut self, registers: &CalibrationRegisters) -> Result<()>;
     fn set_zero_position(&mut self) -> Result<()>;
 }
 
+/// A servo joining the bus after startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServoEvent {
+    /// A configured servo that was missing answered and has been added.
+    Added { id: u8, actuator_type: FeetechActuatorType },
+    /// A tracked servo came back online after going offline. It may have been
+    /// power cycled and lost its RAM settings.
+    Reconnected { id: u8 },
+    /// A tracked servo was moved to a new id.
+    Renamed { from: u8, to: u8 },
+    /// Protection disabled torque on a servo.
+    ProtectionTripped { id: u8 },
+    /// A servo raised a status fault flag it did not report before.
+    StatusFault { id: u8 },
+}
+
+impl ServoEvent {
+    pub fn id(&self) -> u8 {
+        match *self {
+            ServoEvent::Added { id, .. }
+            | ServoEvent::Reconnected { id }
+            | ServoEvent::Renamed { to: id, .. }
+            | ServoEvent::ProtectionTripped { id }
+            | ServoEvent::StatusFault { id } => id,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FeetechSupervisor {
+    pub bus: Arc<dyn FeetechBus>,
     pub servos: Arc<RwLock<HashMap<u8, Box<dyn FeetechActuator>>>>,
     pub actuator_desired_positions: HashMap<u8, f32>,
     //pub actuator_desired_time: HashMap<u8, f32>,
-    pub actuator_desired_velocities: HashMap<u8, f32>
+    pub actuator_desired_velocities: HashMap<u8, f32>,
+    pub protection: Arc<std::sync::Mutex<ProtectionMonitor>>,
+    poll_period: Arc<watch::Sender<Duration>>,
+    poll_timing: Arc<std::sync::Mutex<LoopTiming>>,
+    stats: Arc<std::sync::Mutex<StatsCollector>>,
+    liveness: Arc<std::sync::Mutex<LivenessMonitor>>,
+    liveness_events: broadcast::Sender<LivenessEvent>,
+    /// Servos that did not answer when added, with their type if known.
+    missing: Arc<std::sync::Mutex<BTreeMap<u8, Option<FeetechActuatorType>>>>,
+    servo_events: broadcast::Sender<ServoEvent>,
+    telemetry: Arc<Notify>,
+    stop: Arc<watch::Sender<bool>>,
+    /// The poll loop, until `shutdown` takes it.
+    poll_task: Arc<std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>>,
 }
 
+/// A protection level change found by the poll loop, with what is needed to
+/// apply it once the supervisor's locks are released.
+#[derive(Debug)]
+struct ProtectionChange {
+    id: u8,
+    level: ProtectionLevel,
+    faults: String,
+    derate_fraction: f32,
+}
+
+/// Telemetry poll rate used until `set_poll_rate` is called.
+pub const DEFAULT_POLL_RATE_HZ: f64 = 125.0;
+
 impl FeetechSupervisor {
-    pub fn new() -> Result<Self> {
-        unsafe {
-            if servo_init() != 0 {
-                return Err(eyre::eyre!("Failed to initialize servo system"));
-            }
-        }
+    pub fn new(bus: Arc<dyn FeetechBus>) -> Result<Self> {
+        bus.init()?;
 
         let supervisor = Self {
+            bus,
             servos: Arc::new(RwLock::new(HashMap::new())),
             actuator_desired_positions: HashMap::new(),
             //actuator_desired_time: HashMap::new(),
-            actuator_desired_velocities: HashMap::new()
+            actuator_desired_velocities: HashMap::new(),
+            protection: Arc::new(std::sync::Mutex::new(ProtectionMonitor::default())),
+            poll_period: Arc::new(watch::channel(loop_period(DEFAULT_POLL_RATE_HZ)?).0),
+            poll_timing: Arc::new(std::sync::Mutex::new(LoopTiming::default())),
+            stats: Arc::new(std::sync::Mutex::new(StatsCollector::default())),
+            liveness: Arc::new(std::sync::Mutex::new(LivenessMonitor::default())),
+            liveness_events: broadcast::channel(64).0,
+            missing: Arc::new(std::sync::Mutex::new(BTreeMap::new())),
+            servo_events: broadcast::channel(64).0,
+            telemetry: Arc::new(Notify::new()),
+            stop: Arc::new(watch::channel(false).0),
+            poll_task: Arc::new(std::sync::Mutex::new(None)),
         };
 
         let supervisor_clone = supervisor.clone();
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(8)); // 125hz
+        let poll_task = tokio::spawn(async move {
+            let mut stop = supervisor_clone.stop.subscribe();
+            let mut poll_period = supervisor_clone.poll_period.subscribe();
+            let mut timer = LoopTimer::new(*poll_period.borrow_and_update(), supervisor_clone.poll_timing.clone());
             let mut stats_interval = tokio::time::interval(tokio::time::Duration::from_secs(5)); // 5 seconds
 
             // Stats tracking
@@ -184,8 +414,15 @@ impl FeetechSupervisor {
             };
 
             loop {
+                if *stop.borrow_and_update() {
+                    return;
+                }
+                if poll_period.has_changed().unwrap_or(false) {
+                    timer.set_period(*poll_period.borrow_and_update());
+                }
                 tokio::select! {
-                    _ = interval.tick() => {
+                    _ = stop.changed() => {}
+                    _ = timer.tick() => {
                         let mut info_buffer = ServoInfoBuffer {
                             retry_count: 0,
                             read_count: 0,
@@ -196,24 +433,92 @@ impl FeetechSupervisor {
                         };
 
                         // Move info_buffer into the blocking task and get it back
-                        let info_buffer = tokio::task::spawn_blocking(move || {
-                            unsafe { servo_get_info(&mut info_buffer); }
-                            info_buffer
+                        let bus = supervisor_clone.bus.clone();
+                        let (info_buffer, result) = tokio::task::spawn_blocking(move || {
+                            let result = bus.get_info(&mut info_buffer);
+                            (info_buffer, result)
                         })
                         .await
                         .unwrap();
 
+                        if let Err(e) = result {
+                            warn!("Failed to read servo info: {}", e);
+                            supervisor_clone.stats.lock().unwrap().record_poll_error();
+                            continue;
+                        }
+
                         // Accumulate stats
                         accumulated_stats.retry_count += info_buffer.retry_count;
                         accumulated_stats.read_count += info_buffer.read_count;
                         accumulated_stats.loop_count += info_buffer.loop_count;
                         accumulated_stats.fault_count += info_buffer.fault_count;
 
-                        let mut servos = supervisor_clone.servos.write().await;
-                        for servo in &info_buffer.servos {
-                            if servo.id != 0 {
-                                if let Some(actuator) = servos.get_mut(&servo.id) {
-                                    actuator.update_info(servo);
+                        // Protection changes are bus writes, applied once the locks
+                        // are released.
+                        let mut protection_changes = Vec::new();
+                        {
+                            let mut servos = supervisor_clone.servos.write().await;
+                            let mut protection = supervisor_clone.protection.lock().unwrap();
+                            let mut liveness = supervisor_clone.liveness.lock().unwrap();
+                            let mut stats = supervisor_clone.stats.lock().unwrap();
+                            let now = Instant::now();
+                            stats.record_bus(
+                                info_buffer.retry_count,
+                                info_buffer.read_count,
+                                info_buffer.loop_count,
+                                info_buffer.fault_count,
+                            );
+                            for (&id, actuator) in servos.iter_mut() {
+                                let reading = info_buffer.servos.iter().find(|servo| servo.id == id);
+                                let stamp = reading.map(|servo| servo.last_read_ms);
+                                stats.record_servo(id, stamp, now);
+                                if let Some(event) = liveness.update(id, stamp, now) {
+                                    Self::log_liveness(&event);
+                                    if event.reconnected {
+                                        let _ = supervisor_clone.servo_events.send(ServoEvent::Reconnected { id });
+                                    }
+                                    let _ = supervisor_clone.liveness_events.send(event);
+                                }
+                                if let Some(reading) = reading {
+                                    let previous_faults = actuator.info().faults;
+                                    actuator.update_info(reading);
+                                    if actuator.info().faults.iter().any(|fault| !previous_faults.contains(fault)) {
+                                        let _ = supervisor_clone.servo_events.send(ServoEvent::StatusFault { id });
+                                    }
+                                }
+                                actuator.update_liveness(&liveness.get(id));
+                                if let Some(level) = protection.update(id, &actuator.info(), now) {
+                                    let change = ProtectionChange {
+                                        id,
+                                        level,
+                                        faults: protection.faults(id).join(", "),
+                                        derate_fraction: protection
+                                            .limits(id)
+                                            .map_or(1.0, |limits| limits.derate_torque_fraction),
+                                    };
+                                    protection_changes.push(change);
+                                }
+                            }
+                        }
+                        supervisor_clone.telemetry.notify_waiters();
+
+                        if !protection_changes.is_empty() {
+                            let servos = supervisor_clone.servos.clone();
+                            let applied = tokio::task::spawn_blocking(move || {
+                                let mut servos = servos.blocking_write();
+                                for change in &protection_changes {
+                                    if let Some(actuator) = servos.get_mut(&change.id) {
+                                        Self::apply_protection(actuator.as_mut(), change);
+                                    }
+                                }
+                                protection_changes
+                            })
+                            .await;
+                            for change in applied.unwrap_or_default() {
+                                if change.level == ProtectionLevel::Tripped {
+                                    let _ = supervisor_clone
+                                        .servo_events
+                                        .send(ServoEvent::ProtectionTripped { id: change.id });
                                 }
                             }
                         }
@@ -236,10 +541,100 @@ impl FeetechSupervisor {
                 }
             }
         });
+        *supervisor.poll_task.lock().unwrap() = Some(poll_task);
 
         Ok(supervisor)
     }
 
+    /// Stops the poll loop and releases the bus. Clones share the bus, so
+    /// call this once, after the last use of any of them.
+    pub async fn shutdown(&self) {
+        let Some(poll_task) = self.poll_task.lock().unwrap().take() else {
+            return;
+        };
+        self.stop.send_replace(true);
+        if let Err(e) = poll_task.await {
+            error!("Servo poll loop failed: {}", e);
+        }
+        self.bus.deinit();
+    }
+
+    pub fn poll_rate(&self) -> f64 {
+        1.0 / self.poll_period.borrow().as_secs_f64()
+    }
+
+    /// Changes the telemetry poll rate. Takes effect on the next tick.
+    pub fn set_poll_rate(&self, rate_hz: f64) -> Result<()> {
+        let period = loop_period(rate_hz)?;
+        if self.poll_period.send_replace(period) != period {
+            info!("Servo poll rate set to {} Hz", rate_hz);
+        }
+        Ok(())
+    }
+
+    pub fn poll_timing(&self) -> LoopTiming {
+        self.poll_timing.lock().unwrap().clone()
+    }
+
+    /// Bus, poll loop and per-servo read statistics since startup.
+    pub fn stats(&self) -> SupervisorStats {
+        self.stats.lock().unwrap().snapshot(self.poll_timing(), Instant::now())
+    }
+
+    /// Sets when servos count as offline and back online.
+    pub fn set_liveness_config(&self, config: LivenessConfig) {
+        self.liveness.lock().unwrap().set_config(config);
+    }
+
+    pub fn liveness(&self, id: u8) -> Liveness {
+        self.liveness.lock().unwrap().get(id)
+    }
+
+    /// Servos going online or offline, as decided by the poll loop.
+    pub fn subscribe_liveness(&self) -> broadcast::Receiver<LivenessEvent> {
+        self.liveness_events.subscribe()
+    }
+
+    fn log_liveness(event: &LivenessEvent) {
+        match (event.online, event.age) {
+            (true, _) => info!("Servo {} is online", event.id),
+            (false, Some(age)) => warn!("Servo {} is not responding (last reading {} ms ago)", event.id, age.as_millis()),
+            (false, None) => warn!("Servo {} is not responding", event.id),
+        }
+    }
+
+    /// Notified right after each telemetry read has been applied.
+    pub fn telemetry_notify(&self) -> Arc<Notify> {
+        self.telemetry.clone()
+    }
+
+    /// Blocking: writes the torque limit or disables torque.
+    fn apply_protection(actuator: &mut dyn FeetechActuator, change: &ProtectionChange) {
+        let ProtectionChange {
+            id,
+            level,
+            faults,
+            derate_fraction,
+        } = change;
+        let result = match level {
+            ProtectionLevel::Normal => {
+                info!("Servo {} protection cleared", id);
+                actuator.set_torque_limit(1.0)
+            }
+            ProtectionLevel::Derated => {
+                warn!("Servo {} derated to {:.0}% torque: {}", id, derate_fraction * 100.0, faults);
+                actuator.set_torque_limit(*derate_fraction)
+            }
+            ProtectionLevel::Tripped => {
+                error!("Servo {} protection tripped, disabling torque: {}", id, faults);
+                actuator.disable_torque()
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to apply {:?} protection on servo {}: {}", level, id, e);
+        }
+    }
+
     pub async fn update_active_servos(&mut self) -> Result<()> {
         let servos = self.servos.write().await;
         let servo_ids = servos.iter().map(|(id, _)| *id).collect::<Vec<_>>();
@@ -258,37 +653,105 @@ impl FeetechSupervisor {
             active_servos.servo_id[i] = id as c_uchar;
         }
 
-        unsafe {
-            if servo_set_active_servos(active_servos) != 0 {
-                return Err(eyre::eyre!("Failed to set active servos"));
-            }
-        }
+        self.bus.set_active_servos(&active_servos)?;
         Ok(())
     }
 
+    /// Adds a servo that answers on `id`. A servo that does not answer is
+    /// remembered as missing and added by discovery once it appears.
     pub async fn add_servo(&mut self, id: u8, actuator_type: FeetechActuatorType) -> Result<()> {
-        let mut servos = self.servos.write().await;
-        let mut actuator = match actuator_type {
-            FeetechActuatorType::Sts3215 => Sts3215::new(id),
-            FeetechActuatorType::Sts3250 => todo!(),
-        };
-        let mut success = false;
-        for _ in 0..10 {
-            if actuator.check_id().is_ok() {
-                success = true;
-                break;
-            }
-        }
-
-        if success {
-            servos.insert(id, Box::new(actuator));
-            drop(servos);
-            self.update_active_servos().await?;
-        } else {
+        if !self.try_add_servo(id, actuator_type, 10).await? {
             warn!(
                 "Failed to add servo {:?} not responding after 10 attempts",
                 id
             );
+            self.missing.lock().unwrap().insert(id, Some(actuator_type));
+        }
+        Ok(())
+    }
+
+    async fn try_add_servo(&mut self, id: u8, actuator_type: FeetechActuatorType, attempts: u32) -> Result<bool> {
+        let actuator: Box<dyn FeetechActuator> = match actuator_type {
+            FeetechActuatorType::Sts3215 => Box::new(StsServo::new(self.bus.clone(), id, STS3215)),
+            FeetechActuatorType::Sts3250 => Box::new(StsServo::new(self.bus.clone(), id, STS3250)),
+        };
+        let (actuator, success) = tokio::task::spawn_blocking(move || {
+            let success = (0..attempts).any(|_| actuator.check_id().is_ok());
+            (actuator, success)
+        })
+        .await?;
+        if success {
+            self.servos.write().await.insert(id, actuator);
+            self.missing.lock().unwrap().remove(&id);
+            self.update_active_servos().await?;
+        }
+        Ok(success)
+    }
+
+    /// Adds a servo using the driver for the model it reports.
+    pub async fn discover_servo(&mut self, id: u8) -> Result<()> {
+        let bus = self.bus.clone();
+        let actuator_type = match tokio::task::spawn_blocking(move || read_model(&*bus, id)).await? {
+            Ok(actuator_type) => actuator_type,
+            Err(e) if e.downcast_ref::<UnknownServoModel>().is_some() => return Err(e),
+            Err(e) => {
+                warn!("Failed to add servo {:?}, could not read model: {}", id, e);
+                self.missing.lock().unwrap().insert(id, None);
+                return Ok(());
+            }
+        };
+        debug!("Servo {} reports model {:?}", id, actuator_type);
+        self.add_servo(id, actuator_type).await
+    }
+
+    /// Servos that were added but have not answered yet.
+    pub fn missing_servos(&self) -> Vec<u8> {
+        self.missing.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Servos added by discovery or renamed, tracked servos coming back
+    /// online, protection trips and new status faults.
+    pub fn subscribe_servo_events(&self) -> broadcast::Receiver<ServoEvent> {
+        self.servo_events.subscribe()
+    }
+
+    /// Pings the missing servos every `interval` and adds those that answer.
+    pub fn spawn_discovery(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let mut supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let missing: Vec<_> = supervisor.missing.lock().unwrap().clone().into_iter().collect();
+                for (id, actuator_type) in missing {
+                    if let Err(e) = supervisor.discover_missing(id, actuator_type).await {
+                        error!("Failed to add discovered servo {}: {}", id, e);
+                    }
+                }
+            }
+        })
+    }
+
+    async fn discover_missing(&mut self, id: u8, actuator_type: Option<FeetechActuatorType>) -> Result<()> {
+        let bus = self.bus.clone();
+        let model = tokio::task::spawn_blocking(move || read_model(&*bus, id)).await?;
+        let actuator_type = match (model, actuator_type) {
+            (Ok(reported), configured) => configured.unwrap_or(reported),
+            // The configured driver takes precedence over the model register.
+            (Err(e), Some(configured)) if e.downcast_ref::<UnknownServoModel>().is_some() => configured,
+            (Err(e), None) if e.downcast_ref::<UnknownServoModel>().is_some() => {
+                self.missing.lock().unwrap().remove(&id);
+                return Err(e);
+            }
+            (Err(e), _) => {
+                trace!("Servo {} still missing: {}", id, e);
+                return Ok(());
+            }
+        };
+        if self.try_add_servo(id, actuator_type, 1).await? {
+            info!("Servo {} ({:?}) appeared on the bus", id, actuator_type);
+            let _ = self.servo_events.send(ServoEvent::Added { id, actuator_type });
         }
         Ok(())
     }
@@ -297,6 +760,10 @@ impl FeetechSupervisor {
         let mut servos = self.servos.write().await;
         servos.remove(&id);
         drop(servos);
+        self.protection.lock().unwrap().remove(id);
+        self.stats.lock().unwrap().remove_servo(id);
+        self.liveness.lock().unwrap().remove(id);
+        self.missing.lock().unwrap().remove(&id);
         self.update_active_servos().await?;
         Ok(())
     }
@@ -326,20 +793,89 @@ impl FeetechSupervisor {
             let mut servos = self.servos.write().await;
             let servo = servos
                 .get_mut(&id)
-                .ok_or_else(|| eyre::eyre!("Servo with id {} not found", id))?;
+                .ok_or(ZBotError::ServoNotFound(id))?;
             servo.disable_torque()?;
         } // servos lock is dropped here
         self.broadcast_command().await?;
         Ok(())
     }
 
+    /// Turns torque off on every servo with one broadcast frame, then confirms
+    /// each servo individually so the cached torque state is correct. Fails
+    /// if any servo did not confirm, listing each of them.
+    pub async fn disable_all_torque(&mut self) -> Result<()> {
+        const SERVO_ADDR_TORQUE_SWITCH: u8 = 0x28;
+        let mut command = BroadcastCommand {
+            data_length: 0,
+            data: [0; MAX_SHMEM_DATA],
+        };
+        command.data[0] = SERVO_ADDR_TORQUE_SWITCH;
+        command.data[1] = 1;
+        let mut index = 2;
+
+        self.actuator_desired_positions.clear();
+        self.actuator_desired_velocities.clear();
+
+        // The broadcast and the confirmations block on the bus, which must
+        // not stall the runtime during an e-stop.
+        let servos = self.servos.clone();
+        let bus = self.bus.clone();
+        let (broadcast, failed) = tokio::task::spawn_blocking(move || {
+            let mut servos = servos.blocking_write();
+            for id in servos.keys() {
+                command.data[index] = *id;
+                command.data[index + 1] = 0x00;
+                index += 2;
+            }
+            command.data_length = index as c_uint;
+            let broadcast = bus.broadcast_command(&command);
+
+            let mut failed = BTreeMap::new();
+            for (id, servo) in servos.iter_mut() {
+                if let Err(e) = servo.disable_torque() {
+                    warn!("Failed to confirm torque off on servo {}: {}", id, e);
+                    failed.insert(*id, e);
+                }
+            }
+            (broadcast, failed)
+        })
+        .await?;
+
+        if failed.is_empty() {
+            if let Err(e) = broadcast {
+                warn!("Torque off broadcast failed, but every servo confirmed: {}", e);
+            }
+            return Ok(());
+        }
+        let mut message = format!(
+            "Torque off not confirmed on servos {:?}: {}",
+            failed.keys().collect::<Vec<_>>(),
+            failed
+                .iter()
+                .map(|(id, e)| format!("servo {}: {}", id, e))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if let Err(e) = broadcast {
+            message.push_str(&format!(" (the broadcast failed too: {})", e));
+        }
+        Err(ZBotError::Hardware(message).into())
+    }
+
     pub async fn enable_torque(&mut self, id: u8) -> Result<()> {
+        if self.protection.lock().unwrap().level(id) == ProtectionLevel::Tripped {
+            return Err(ZBotError::ProtectionTripped {
+                id,
+                faults: self.protection.lock().unwrap().faults(id).join(", "),
+            }
+            .into());
+        }
         {
             // New scope to ensure servos lock is dropped
             let mut servos = self.servos.write().await;
             let servo = servos
                 .get_mut(&id)
-                .ok_or_else(|| eyre::eyre!("Servo with id {} not found", id))?;
+                .ok_or(ZBotError::ServoNotFound(id))?;
             servo.enable_torque()?;
             self.actuator_desired_positions.remove(&id);
             //self.actuator_desired_time.remove(&id);
@@ -369,7 +905,8 @@ impl FeetechSupervisor {
         for (id, position) in &self.actuator_desired_positions {
             if let Some(servo) = servos.get(id) {
                 if servo.info().torque_enabled {
-                    let position_raw = servo.degrees_to_raw(*position, 180.0);
+                    // Out-of-range values would otherwise saturate or wrap in the u16 cast.
+                    let position_raw = servo.degrees_to_raw(position.clamp(-180.0, 180.0), 180.0).min(4095);
                     /*let time = self.actuator_desired_time.get(id).copied().unwrap_or(0.0);
                     let time_raw: u16 = (time * 1000.0).clamp(0.0, u16::MAX as f32) as u16;
                     let velocity = if time > 0.0 {
@@ -379,7 +916,7 @@ impl FeetechSupervisor {
                     };*/
 
                     let velocity = self.actuator_desired_velocities.get(id).copied().unwrap_or(0.0); // 1000 larger than max
-                    let velocity_raw = servo.degrees_to_raw(velocity, 0.0);
+                    let velocity_raw = servo.degrees_to_raw(velocity.max(0.0), 0.0).min(0x7FFF);
                     
                     // Pack data
                     command.data[index] = *id;
@@ -406,37 +943,128 @@ impl FeetechSupervisor {
     
         command.data_length = index as c_uint;
     
-        unsafe {
-            if servo_broadcast_command(command) != 0 {
-                return Err(eyre::eyre!("Failed to broadcast command"));
+        let started = Instant::now();
+        let result = self.bus.broadcast_command(&command);
+        self.stats.lock().unwrap().record_broadcast(started.elapsed(), result.is_ok());
+        result
+    }
+
+    /// Moves the servo on `id` to `new_id`. Fails without touching the servo
+    /// if `new_id` is tracked or answers on the bus. The id is read back on
+    /// the new id; if the change fails halfway the old id is restored. A
+    /// tracked servo keeps its state under the new id.
+    pub async fn change_id(&mut self, id: u8, new_id: u8) -> Result<()> {
+        if new_id == id {
+            return Err(ZBotError::InvalidArgument(format!("Servo {} already has id {}", id, new_id)).into());
+        }
+        if !(1..=MAX_SERVO_ID).contains(&new_id) {
+            return Err(ZBotError::OutOfRange(format!("Servo id {} is not in 1..={}", new_id, MAX_SERVO_ID)).into());
+        }
+        if self.servos.read().await.contains_key(&new_id) {
+            return Err(ZBotError::InvalidArgument(format!("Id {} is already used by a tracked servo", new_id)).into());
+        }
+        let bus = self.bus.clone();
+        if tokio::task::spawn_blocking(move || answers_on(&*bus, new_id, 3)).await? {
+            return Err(ZBotError::InvalidArgument(format!("Id {} is already used by a servo on the bus", new_id)).into());
+        }
+
+        // Stop polling the servo while its id changes.
+        let tracked = self.servos.write().await.remove(&id);
+        let was_tracked = tracked.is_some();
+        if was_tracked {
+            self.update_active_servos().await?;
+        }
+        let mut servo = tracked.unwrap_or_else(|| Box::new(StsServo::new(self.bus.clone(), id, STS3215)));
+
+        let (servo, result) = tokio::task::spawn_blocking(move || {
+            let result = servo.change_id(new_id);
+            if result.is_err() && servo.id() == new_id {
+                // Moved, but the EEPROM could not be locked again.
+                if let Err(e) = servo.change_id(id) {
+                    error!("Failed to roll servo {} back to id {}: {}", new_id, id, e);
+                }
+            }
+            (servo, result)
+        })
+        .await?;
+
+        let final_id = servo.id();
+        if was_tracked {
+            self.servos.write().await.insert(final_id, servo);
+            if final_id != id {
+                self.protection.lock().unwrap().rename(id, final_id);
+                self.stats.lock().unwrap().rename_servo(id, final_id);
+                self.liveness.lock().unwrap().rename(id, final_id);
+                self.actuator_desired_positions.remove(&id);
+                self.actuator_desired_velocities.remove(&id);
             }
+            self.update_active_servos().await?;
+        }
+        if final_id != id {
+            self.missing.lock().unwrap().remove(&final_id);
+        }
+        result?;
+
+        info!("Servo {} now has id {}", id, new_id);
+        if was_tracked {
+            let _ = self.servo_events.send(ServoEvent::Renamed { from: id, to: new_id });
         }
-    
         Ok(())
     }
 
-    pub async fn change_id(&mut self, id: u8, new_id: u8) -> Result<()> {
-        let mut servos = self.servos.write().await;
-        if let Some(servo) = servos.get_mut(&id) {
-            servo.change_id(new_id)?;
-            Ok(())
-        } else {
-            let mut new_servo = Box::new(Sts3215::new(id));
-            new_servo.change_id(new_id)?;
-            Ok(())
+    /// Waits until a servo answers on `id`, probing every `interval`.
+    pub async fn wait_for_servo(&self, id: u8, interval: Duration) -> Result<()> {
+        loop {
+            let bus = self.bus.clone();
+            if tokio::task::spawn_blocking(move || answers_on(&*bus, id, 1)).await? {
+                return Ok(());
+            }
+            tokio::time::sleep(interval).await;
         }
     }
-}
 
-impl Drop for FeetechSupervisor {
-    fn drop(&mut self) {
-        unsafe {
-            servo_deinit();
+    /// Assembly mode: gives each new servo plugged in on `factory_id` the
+    /// next id of `ids`, one at a time. Only one servo may answer on
+    /// `factory_id` at once. `on_step` is called before waiting for each
+    /// servo and after it has been moved.
+    pub async fn assign_ids(
+        &mut self,
+        factory_id: u8,
+        ids: &[u8],
+        mut on_step: impl FnMut(AssemblyStep),
+    ) -> Result<()> {
+        for &id in ids {
+            on_step(AssemblyStep::Waiting { id });
+            self.wait_for_servo(factory_id, Duration::from_millis(200)).await?;
+            self.change_id(factory_id, id).await?;
+            on_step(AssemblyStep::Assigned { id });
         }
+        Ok(())
     }
 }
 
-pub fn feetech_write(id: u8, address: u8, data: &[u8]) -> Result<()> {
+/// Progress of `FeetechSupervisor::assign_ids`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssemblyStep {
+    /// Waiting for the servo that will get `id` to be plugged in.
+    Waiting { id: u8 },
+    Assigned { id: u8 },
+}
+
+/// Reads the model number register of `id` and returns the matching
+/// actuator type. Unknown models are reported as `UnknownServoModel`.
+pub fn read_model(bus: &dyn FeetechBus, id: u8) -> Result<FeetechActuatorType> {
+    let model_id = feetech_read(bus, id, MODEL_NUMBER_ADDRESS, 2)?;
+    FeetechActuatorType::from_model_id(&model_id).ok_or_else(|| {
+        UnknownServoModel {
+            id,
+            model_id: [model_id[0], model_id[1]],
+        }
+        .into()
+    })
+}
+
+pub fn feetech_write(bus: &dyn FeetechBus, id: u8, address: u8, data: &[u8]) -> Result<()> {
     trace!(
         "feetech_write: id: {}, address: {}, data: {:?}",
         id,
@@ -447,28 +1075,27 @@ pub fn feetech_write(id: u8, address: u8, data: &[u8]) -> Result<()> {
     const MAX_ATTEMPTS: u8 = 15;
 
     loop {
-        let result = unsafe { servo_write(id, address, data.as_ptr(), data.len() as u8) };
+        let result = bus.write(id, address, data);
 
-        if result == 0 {
-            break;
-        }
+        let err = match result {
+            Ok(()) => break,
+            Err(e) => e,
+        };
 
         attempts += 1;
         trace!(
-            "Write failed (attempt {}/{}): servo id: {}, address: {}, result: {}",
+            "Write failed (attempt {}/{}): servo id: {}, address: {}, error: {}",
             attempts,
             MAX_ATTEMPTS,
             id,
             address,
-            result
+            err
         );
 
         if attempts >= MAX_ATTEMPTS {
-            return Err(eyre::eyre!(
-                "Failed to write to servo after {} attempts, last result: {}",
-                MAX_ATTEMPTS,
-                result
-            ));
+            // Keep the last error in the chain so it decides the error code.
+            let message = format!("Failed to write to servo {} after {} attempts, last error: {}", id, MAX_ATTEMPTS, err);
+            return Err(err.wrap_err(message));
         }
 
         std::thread::sleep(std::time::Duration::from_nanos(200));
@@ -476,7 +1103,7 @@ pub fn feetech_write(id: u8, address: u8, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
-pub fn feetech_read(id: u8, address: u8, length: u8) -> Result<Vec<u8>> {
+pub fn feetech_read(bus: &dyn FeetechBus, id: u8, address: u8, length: u8) -> Result<Vec<u8>> {
     let mut data = vec![0u8; length as usize];
     let mut attempts = 0;
     const MAX_ATTEMPTS: u8 = 15;
@@ -489,28 +1116,26 @@ pub fn feetech_read(id: u8, address: u8, length: u8) -> Result<Vec<u8>> {
     );
 
     loop {
-        let result = unsafe { servo_read(id, address, data.as_mut_ptr(), length) };
+        let result = bus.read(id, address, &mut data);
 
-        if result == 0 {
-            break;
-        }
+        let err = match result {
+            Ok(()) => break,
+            Err(e) => e,
+        };
 
         attempts += 1;
         trace!(
-            "Read failed (attempt {}/{}): servo id: {}, address: {}, result: {}",
+            "Read failed (attempt {}/{}): servo id: {}, address: {}, error: {}",
             attempts,
             MAX_ATTEMPTS,
             id,
             address,
-            result
+            err
         );
 
         if attempts >= MAX_ATTEMPTS {
-            return Err(eyre::eyre!(
-                "Failed to read from servo after {} attempts, last result: {}",
-                MAX_ATTEMPTS,
-                result
-            ));
+            let message = format!("Failed to read from servo {} after {} attempts, last error: {}", id, MAX_ATTEMPTS, err);
+            return Err(err.wrap_err(message));
         }
 
         std::thread::sleep(std::time::Duration::from_nanos(200));
@@ -518,18 +1143,42 @@ pub fn feetech_read(id: u8, address: u8, length: u8) -> Result<Vec<u8>> {
     Ok(data)
 }
 
-pub fn feetech_init() -> Result<()> {
-    unsafe {
-        if servo_init() != 0 {
-            return Err(eyre::eyre!("Failed to initialize servo system"));
-        }
-    }
+pub fn feetech_init(bus: &dyn FeetechBus) -> Result<()> {
+    bus.init()
+}
+
+pub fn feetech_deinit(bus: &dyn FeetechBus) -> Result<()> {
+    bus.deinit();
     Ok(())
 }
 
-pub fn feetech_deinit() -> Result<()> {
-    unsafe {
-        servo_deinit();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::feetech_sim::SimulatedBus;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn change_id_moves_the_servo_and_persists() {
+        let bus = Arc::new(SimulatedBus::with_servos(&[1, 2]));
+        let mut supervisor = FeetechSupervisor::new(bus.clone()).unwrap();
+        supervisor.add_servo(1, FeetechActuatorType::Sts3215).await.unwrap();
+        supervisor.add_servo(2, FeetechActuatorType::Sts3215).await.unwrap();
+
+        assert!(
+            supervisor.change_id(1, 2).await.is_err(),
+            "moved servo 1 onto the id of servo 2"
+        );
+
+        supervisor.change_id(1, 7).await.unwrap();
+        assert!(supervisor.servos.read().await.contains_key(&7));
+        assert!(bus.with_servo(1, |_| ()).is_none());
+        assert_eq!(
+            bus.with_servo(7, |servo| {
+                servo.power_cycle();
+                servo.id()
+            }),
+            Some(7)
+        );
+        supervisor.shutdown().await;
     }
-    Ok(())
 }
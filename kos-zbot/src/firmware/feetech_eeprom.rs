@@ -0,0 +1,304 @@
+use super::feetech::{
+    feetech_read, feetech_write, FeetechActuatorType, FeetechBus, UnknownServoModel, MODEL_NUMBER_ADDRESS,
+};
+use crate::error::ZBotError;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+/// Size of the EEPROM area of the STS register map, 0x00..0x28.
+pub const EEPROM_SIZE: u8 = 0x28;
+const REGISTER_LOCK_MARK: u8 = 0x37;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EepromAccess {
+    ReadOnly,
+    /// Writable, but never restored: a wrong id or baud rate takes the servo
+    /// off the bus.
+    Identity,
+    ReadWrite,
+}
+
+/// One register of the EEPROM table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EepromRegister {
+    pub name: &'static str,
+    pub address: u8,
+    /// 1 or 2 bytes, little endian.
+    pub size: u8,
+    pub access: EepromAccess,
+}
+
+impl EepromRegister {
+    pub fn find(name: &str) -> Option<&'static EepromRegister> {
+        EEPROM_REGISTERS.iter().find(|register| register.name == name)
+    }
+
+    fn decode(&self, data: &[u8]) -> u16 {
+        let start = self.address as usize;
+        match self.size {
+            1 => data[start] as u16,
+            _ => u16::from_le_bytes([data[start], data[start + 1]]),
+        }
+    }
+
+    fn encode(&self, value: u16) -> Vec<u8> {
+        value.to_le_bytes()[..self.size as usize].to_vec()
+    }
+}
+
+macro_rules! eeprom_image {
+    ($($(#[$doc:meta])* $field:ident: $ty:ty = $address:literal, $access:ident;)*) => {
+        /// The EEPROM of an STS3215 or STS3250, which share a register map.
+        /// Values are raw register contents.
+        #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+        pub struct EepromImage {
+            pub model: FeetechActuatorType,
+            $($(#[$doc])* pub $field: $ty,)*
+        }
+
+        /// Every register of `EepromImage`, in address order.
+        pub const EEPROM_REGISTERS: &[EepromRegister] = &[$(EepromRegister {
+            name: stringify!($field),
+            address: $address,
+            size: std::mem::size_of::<$ty>() as u8,
+            access: EepromAccess::$access,
+        },)*];
+
+        impl EepromImage {
+            fn from_bytes(model: FeetechActuatorType, data: &[u8]) -> Self {
+                let mut registers = EEPROM_REGISTERS.iter();
+                Self {
+                    model,
+                    $($field: registers.next().unwrap().decode(data) as $ty,)*
+                }
+            }
+
+            /// Register values, in the order of `EEPROM_REGISTERS`.
+            pub fn values(&self) -> Vec<u16> {
+                vec![$(self.$field as u16,)*]
+            }
+        }
+    };
+}
+
+eeprom_image! {
+    firmware_major: u8 = 0x00, ReadOnly;
+    firmware_minor: u8 = 0x01, ReadOnly;
+    id: u8 = 0x05, Identity;
+    /// 0 is 1 Mbps.
+    baud_rate: u8 = 0x06, Identity;
+    /// Response delay, in 2 µs.
+    return_delay: u8 = 0x07, ReadWrite;
+    /// 0 answers only reads and pings, 1 answers every instruction.
+    response_level: u8 = 0x08, ReadWrite;
+    min_angle: u16 = 0x09, ReadWrite;
+    max_angle: u16 = 0x0B, ReadWrite;
+    /// °C
+    max_temperature_limit: u8 = 0x0D, ReadWrite;
+    /// 0.1 V
+    max_voltage_limit: u8 = 0x0E, ReadWrite;
+    /// 0.1 V
+    min_voltage_limit: u8 = 0x0F, ReadWrite;
+    /// 0.1 %
+    max_torque: u16 = 0x10, ReadWrite;
+    phase: u8 = 0x12, ReadWrite;
+    /// Status bits that disable torque.
+    unloading_condition: u8 = 0x13, ReadWrite;
+    /// Status bits that flash the LED.
+    led_alarm_condition: u8 = 0x14, ReadWrite;
+    p_coefficient: u8 = 0x15, ReadWrite;
+    d_coefficient: u8 = 0x16, ReadWrite;
+    i_coefficient: u8 = 0x17, ReadWrite;
+    /// 0.1 %
+    min_startup_force: u16 = 0x18, ReadWrite;
+    cw_dead_zone: u8 = 0x1A, ReadWrite;
+    ccw_dead_zone: u8 = 0x1B, ReadWrite;
+    /// 6.5 mA
+    protection_current: u16 = 0x1C, ReadWrite;
+    angular_resolution: u8 = 0x1E, ReadWrite;
+    /// Sign-magnitude, sign in bit 11.
+    offset: u16 = 0x1F, ReadWrite;
+    mode: u8 = 0x21, ReadWrite;
+    /// %
+    protective_torque: u8 = 0x22, ReadWrite;
+    /// 10 ms
+    protection_time: u8 = 0x23, ReadWrite;
+    /// %
+    overload_torque: u8 = 0x24, ReadWrite;
+    speed_p_coefficient: u8 = 0x25, ReadWrite;
+    /// 10 ms
+    overcurrent_protection_time: u8 = 0x26, ReadWrite;
+    speed_i_coefficient: u8 = 0x27, ReadWrite;
+}
+
+/// A register whose value differs from the expected one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EepromDifference {
+    pub register: String,
+    pub address: u8,
+    pub expected: u16,
+    pub actual: u16,
+}
+
+/// Expected values for some or all EEPROM registers, by register name. A
+/// saved `EepromImage` is a complete profile; a golden profile lists only
+/// the registers that must match, e.g. PID gains and limits. `id` is never
+/// compared or written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EepromProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<FeetechActuatorType>,
+    #[serde(flatten)]
+    pub registers: BTreeMap<String, u16>,
+}
+
+impl EepromProfile {
+    pub fn from_image(image: &EepromImage) -> Self {
+        Self {
+            model: Some(image.model),
+            registers: EEPROM_REGISTERS
+                .iter()
+                .zip(image.values())
+                .map(|(register, value)| (register.name.to_string(), value))
+                .collect(),
+        }
+    }
+
+    /// The servo a saved image was read from.
+    pub fn id(&self) -> Option<u8> {
+        self.registers.get("id").and_then(|&id| u8::try_from(id).ok())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, &value) in &self.registers {
+            let register = EepromRegister::find(name)
+                .ok_or_else(|| ZBotError::InvalidArgument(format!("Unknown EEPROM register '{}'", name)))?;
+            if register.size == 1 && value > u8::MAX as u16 {
+                return Err(ZBotError::OutOfRange(format!("{} is a byte register, {} does not fit", name, value)).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers of `image` that differ from this profile.
+    pub fn diff(&self, image: &EepromImage) -> Result<Vec<EepromDifference>> {
+        self.validate()?;
+        if let Some(model) = self.model.filter(|&model| model != image.model) {
+            return Err(ZBotError::InvalidArgument(format!(
+                "Servo {} is {:?}, the profile is for {:?}",
+                image.id, image.model, model
+            ))
+            .into());
+        }
+        Ok(EEPROM_REGISTERS
+            .iter()
+            .zip(image.values())
+            .filter(|(register, _)| register.name != "id")
+            .filter_map(|(register, actual)| {
+                let expected = *self.registers.get(register.name)?;
+                (expected != actual).then(|| EepromDifference {
+                    register: register.name.to_string(),
+                    address: register.address,
+                    expected,
+                    actual,
+                })
+            })
+            .collect())
+    }
+}
+
+impl EepromImage {
+    /// Registers of this image that differ from `expected`.
+    pub fn diff(&self, expected: &EepromImage) -> Result<Vec<EepromDifference>> {
+        EepromProfile::from_image(expected).diff(self)
+    }
+}
+
+/// Reads the whole EEPROM of servo `id`.
+pub fn read_eeprom(bus: &dyn FeetechBus, id: u8) -> Result<EepromImage> {
+    let data = feetech_read(bus, id, 0x00, EEPROM_SIZE)?;
+    let model_id = [data[MODEL_NUMBER_ADDRESS as usize], data[MODEL_NUMBER_ADDRESS as usize + 1]];
+    let model = FeetechActuatorType::from_model_id(&model_id).ok_or(UnknownServoModel { id, model_id })?;
+    Ok(EepromImage::from_bytes(model, &data))
+}
+
+/// Writes the read-write registers of `profile` that differ on servo `id`,
+/// re-locking the EEPROM even if a write fails, and reads them back.
+/// Returns the registers that were changed, with their previous values.
+pub fn write_eeprom(bus: &dyn FeetechBus, id: u8, profile: &EepromProfile) -> Result<Vec<EepromDifference>> {
+    let writable = |difference: &EepromDifference| {
+        EepromRegister::find(&difference.register).is_some_and(|register| register.access == EepromAccess::ReadWrite)
+    };
+    let changes: Vec<EepromDifference> = profile.diff(&read_eeprom(bus, id)?)?.into_iter().filter(writable).collect();
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    feetech_write(bus, id, REGISTER_LOCK_MARK, &[0x00])?;
+    let result = changes.iter().try_for_each(|change| {
+        let register = EepromRegister::find(&change.register).unwrap();
+        feetech_write(bus, id, register.address, &register.encode(change.expected))
+    });
+    let locked = feetech_write(bus, id, REGISTER_LOCK_MARK, &[0x01]);
+    result.and(locked)?;
+
+    let remaining: Vec<EepromDifference> = profile.diff(&read_eeprom(bus, id)?)?.into_iter().filter(writable).collect();
+    if let Some(difference) = remaining.first() {
+        return Err(ZBotError::EepromLocked {
+            id,
+            register: difference.register.clone(),
+            written: difference.expected,
+            read: difference.actual,
+        }
+        .into());
+    }
+    info!("Servo {}: wrote {} EEPROM registers", id, changes.len());
+    Ok(changes)
+}
+
+fn is_yaml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+/// Saves images as YAML or JSON, by extension.
+pub fn save_eeprom_images<P: AsRef<Path>>(path: P, images: &[EepromImage]) -> Result<()> {
+    let path = path.as_ref();
+    let contents = if is_yaml(path) {
+        serde_yaml::to_string(images)?
+    } else {
+        serde_json::to_string_pretty(images)?
+    };
+    std::fs::write(path, contents)
+        .map_err(|e| ZBotError::Io(format!("Failed to write {}: {}", path.display(), e)).into())
+}
+
+/// Loads one profile or a list of them (such as saved images) from YAML or
+/// JSON, by extension.
+pub fn load_eeprom_profiles<P: AsRef<Path>>(path: P) -> Result<Vec<EepromProfile>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Profiles {
+        Many(Vec<EepromProfile>),
+        One(EepromProfile),
+    }
+
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ZBotError::Io(format!("Failed to read {}: {}", path.display(), e)))?;
+    let profiles = if is_yaml(path) {
+        serde_yaml::from_str(&contents)
+            .map_err(|e| ZBotError::InvalidArgument(format!("Failed to parse {}: {}", path.display(), e)))?
+    } else {
+        serde_json::from_str(&contents)
+            .map_err(|e| ZBotError::InvalidArgument(format!("Failed to parse {}: {}", path.display(), e)))?
+    };
+    let profiles = match profiles {
+        Profiles::Many(profiles) => profiles,
+        Profiles::One(profile) => vec![profile],
+    };
+    profiles.iter().try_for_each(EepromProfile::validate)?;
+    Ok(profiles)
+}
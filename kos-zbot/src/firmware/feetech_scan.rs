@@ -0,0 +1,196 @@
+use super::feetech::{feetech_read, feetech_write, FeetechActuatorType, FeetechBus, MODEL_NUMBER_ADDRESS};
+use super::feetech_servo::decode_status;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn};
+
+const REGISTER_ID: u8 = 0x05;
+const REGISTER_MIN_ANGLE: u8 = 0x09;
+const REGISTER_MAX_ANGLE: u8 = 0x0B;
+const REGISTER_MAX_TEMPERATURE: u8 = 0x0D;
+const REGISTER_MAX_VOLTAGE: u8 = 0x0E;
+const REGISTER_MIN_VOLTAGE: u8 = 0x0F;
+const REGISTER_MODE: u8 = 0x21;
+const REGISTER_LOCK_MARK: u8 = 0x37;
+const REGISTER_VOLTAGE: u8 = 0x3E;
+const REGISTER_TEMPERATURE: u8 = 0x3F;
+const REGISTER_STATUS: u8 = 0x41;
+
+/// Written over a zero maximum temperature limit when repairing.
+const DEFAULT_MAX_TEMPERATURE_C: u8 = 70;
+/// Written over a zero maximum voltage limit when repairing, in 0.1 V.
+const DEFAULT_MAX_VOLTAGE: u8 = 140;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanOptions {
+    /// Ids to probe, 1..=253 by default.
+    #[serde(default = "default_scan_ids")]
+    pub ids: Vec<u8>,
+    /// Probes of each id before it counts as absent.
+    #[serde(default = "default_probe_attempts")]
+    pub attempts: u32,
+    /// Write defaults over EEPROM limits that read zero. Off by default;
+    /// every write is reported in `ScannedServo::repairs`.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            ids: default_scan_ids(),
+            attempts: default_probe_attempts(),
+            repair: false,
+        }
+    }
+}
+
+fn default_scan_ids() -> Vec<u8> {
+    (1..=253).collect()
+}
+
+fn default_probe_attempts() -> u32 {
+    2
+}
+
+/// An EEPROM register rewritten by a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EepromRepair {
+    pub register: String,
+    pub address: u8,
+    pub from: u8,
+    pub to: u8,
+}
+
+/// What a scan read from one servo.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScannedServo {
+    pub id: u8,
+    pub model_id: u16,
+    /// `None` for models without a driver.
+    pub model: Option<FeetechActuatorType>,
+    pub min_angle: u16,
+    pub max_angle: u16,
+    pub mode: u8,
+    pub status: u8,
+    pub faults: Vec<String>,
+    pub voltage_v: f32,
+    pub temperature_c: f32,
+    pub max_temperature_limit_c: f32,
+    pub max_voltage_limit_v: f32,
+    pub min_voltage_limit_v: f32,
+    #[serde(default)]
+    pub repairs: Vec<EepromRepair>,
+    /// Set when the servo answered but a later read or repair failed.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Probes `id` and reads its identity, limits and state. Returns `None` if
+/// nothing answers on `id`.
+pub fn scan_servo(bus: &dyn FeetechBus, id: u8, options: &ScanOptions) -> Option<ScannedServo> {
+    let mut data = [0u8; 1];
+    let answered = (0..options.attempts.max(1)).any(|_| bus.read(id, REGISTER_ID, &mut data).is_ok() && data[0] == id);
+    if !answered {
+        return None;
+    }
+
+    let mut servo = ScannedServo {
+        id,
+        ..Default::default()
+    };
+    if let Err(e) = read_servo(bus, &mut servo) {
+        warn!("Servo {} answered but could not be read: {}", id, e);
+        servo.error = Some(e.to_string());
+        return Some(servo);
+    }
+    if options.repair {
+        if let Err(e) = repair_limits(bus, &mut servo) {
+            warn!("Failed to repair EEPROM limits of servo {}: {}", id, e);
+            servo.error = Some(e.to_string());
+        }
+    }
+    Some(servo)
+}
+
+fn read_u8(bus: &dyn FeetechBus, id: u8, address: u8) -> Result<u8> {
+    Ok(feetech_read(bus, id, address, 1)?[0])
+}
+
+fn read_u16(bus: &dyn FeetechBus, id: u8, address: u8) -> Result<u16> {
+    let data = feetech_read(bus, id, address, 2)?;
+    Ok(u16::from_le_bytes([data[0], data[1]]))
+}
+
+fn read_servo(bus: &dyn FeetechBus, servo: &mut ScannedServo) -> Result<()> {
+    let id = servo.id;
+    let model = feetech_read(bus, id, MODEL_NUMBER_ADDRESS, 2)?;
+    servo.model_id = u16::from_le_bytes([model[0], model[1]]);
+    servo.model = FeetechActuatorType::from_model_id(&model);
+    servo.min_angle = read_u16(bus, id, REGISTER_MIN_ANGLE)?;
+    servo.max_angle = read_u16(bus, id, REGISTER_MAX_ANGLE)?;
+    servo.mode = read_u8(bus, id, REGISTER_MODE)?;
+    servo.status = read_u8(bus, id, REGISTER_STATUS)?;
+    servo.faults = decode_status(servo.status);
+    servo.voltage_v = read_u8(bus, id, REGISTER_VOLTAGE)? as f32 / 10.0;
+    servo.temperature_c = read_u8(bus, id, REGISTER_TEMPERATURE)? as f32;
+    servo.max_temperature_limit_c = read_u8(bus, id, REGISTER_MAX_TEMPERATURE)? as f32;
+    servo.max_voltage_limit_v = read_u8(bus, id, REGISTER_MAX_VOLTAGE)? as f32 / 10.0;
+    servo.min_voltage_limit_v = read_u8(bus, id, REGISTER_MIN_VOLTAGE)? as f32 / 10.0;
+    Ok(())
+}
+
+/// A zero temperature or voltage limit leaves the servo unprotected; those
+/// are set to the factory defaults.
+fn repair_limits(bus: &dyn FeetechBus, servo: &mut ScannedServo) -> Result<()> {
+    let mut repairs = Vec::new();
+    if servo.max_temperature_limit_c == 0.0 {
+        repairs.push(("max_temperature_limit", REGISTER_MAX_TEMPERATURE, DEFAULT_MAX_TEMPERATURE_C));
+    }
+    if servo.max_voltage_limit_v == 0.0 {
+        repairs.push(("max_voltage_limit", REGISTER_MAX_VOLTAGE, DEFAULT_MAX_VOLTAGE));
+    }
+    if repairs.is_empty() {
+        return Ok(());
+    }
+
+    feetech_write(bus, servo.id, REGISTER_LOCK_MARK, &[0x00])?;
+    let result = repairs.iter().try_for_each(|&(register, address, value)| {
+        feetech_write(bus, servo.id, address, &[value])?;
+        info!("Servo {}: repaired {} (0 -> {})", servo.id, register, value);
+        servo.repairs.push(EepromRepair {
+            register: register.to_string(),
+            address,
+            from: 0,
+            to: value,
+        });
+        Ok(())
+    });
+    let locked = feetech_write(bus, servo.id, REGISTER_LOCK_MARK, &[0x01]);
+    result.and(locked)?;
+
+    servo.max_temperature_limit_c = read_u8(bus, servo.id, REGISTER_MAX_TEMPERATURE)? as f32;
+    servo.max_voltage_limit_v = read_u8(bus, servo.id, REGISTER_MAX_VOLTAGE)? as f32 / 10.0;
+    Ok(())
+}
+
+/// Scans `options.ids` in order, calling `on_probe` after each id with the
+/// servo found there, if any. Stops early once `cancel` is set.
+pub fn scan_bus(
+    bus: &dyn FeetechBus,
+    options: &ScanOptions,
+    cancel: &AtomicBool,
+    mut on_probe: impl FnMut(u8, Option<&ScannedServo>),
+) -> Vec<ScannedServo> {
+    let mut found = Vec::new();
+    for &id in &options.ids {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let servo = scan_servo(bus, id, options);
+        on_probe(id, servo.as_ref());
+        found.extend(servo);
+    }
+    found
+}
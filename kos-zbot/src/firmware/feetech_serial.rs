@@ -0,0 +1,532 @@
+use super::feetech::{
+    ActiveServoList, BroadcastCommand, FeetechBus, ServoInfo, ServoInfoBuffer, MAX_SERVOS,
+};
+use crate::error::{prefixed, ZBotError};
+use eyre::Result;
+use serialport::{ClearBuffer, SerialPort};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+pub const BROADCAST_ID: u8 = 0xFE;
+pub const DEFAULT_BAUD_RATE: u32 = 1_000_000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(20);
+
+const HEADER: [u8; 2] = [0xFF, 0xFF];
+/// Most parameter bytes a packet can carry: the length byte also counts the
+/// instruction and the checksum.
+pub const MAX_PARAMS: usize = u8::MAX as usize - 2;
+
+/// First and last register of the block polled by `get_info`, matching the
+/// fields of `ServoInfo`.
+const INFO_START: u8 = 0x28;
+const INFO_END: u8 = 0x46;
+const INFO_LENGTH: u8 = INFO_END - INFO_START + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Instruction {
+    Ping = 0x01,
+    Read = 0x02,
+    Write = 0x03,
+    RegWrite = 0x04,
+    Action = 0x05,
+    SyncRead = 0x82,
+    SyncWrite = 0x83,
+}
+
+/// Builds an instruction packet:
+/// `0xFF 0xFF ID LEN INSTR PARAM... CHECKSUM`.
+pub fn encode_packet(id: u8, instruction: Instruction, params: &[u8]) -> Result<Vec<u8>> {
+    if params.len() > MAX_PARAMS {
+        return Err(ZBotError::OutOfRange(format!(
+            "{:?} packet has {} parameter bytes, at most {} fit",
+            instruction,
+            params.len(),
+            MAX_PARAMS
+        ))
+        .into());
+    }
+    let length = params.len() as u8 + 2;
+    let mut packet = Vec::with_capacity(params.len() + 6);
+    packet.extend_from_slice(&HEADER);
+    packet.push(id);
+    packet.push(length);
+    packet.push(instruction as u8);
+    packet.extend_from_slice(params);
+    packet.push(checksum(&packet[2..]));
+    Ok(packet)
+}
+
+/// Inverted low byte of the sum of everything after the header.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+/// Status packet returned by a servo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusPacket {
+    pub id: u8,
+    /// Servo status bits (voltage, sensor, temperature, ...).
+    pub error: u8,
+    pub params: Vec<u8>,
+}
+
+/// Feetech/SCS protocol driver over a serial port, e.g. a USB-to-TTL adapter
+/// on `/dev/ttyUSB0`.
+pub struct SerialBus {
+    port: Mutex<Box<dyn SerialPort>>,
+    path: String,
+    active_servos: Mutex<Vec<u8>>,
+    last_info: Mutex<HashMap<u8, ServoInfo>>,
+    started: Instant,
+    /// The adapter receives its own transmissions, as half-duplex adapters
+    /// that tie TX to RX do.
+    local_echo: bool,
+}
+
+impl std::fmt::Debug for SerialBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialBus").field("path", &self.path).finish()
+    }
+}
+
+impl SerialBus {
+    pub fn open(path: &str, baud_rate: u32) -> Result<Self> {
+        Self::open_with_timeout(path, baud_rate, DEFAULT_TIMEOUT)
+    }
+
+    pub fn open_with_timeout(path: &str, baud_rate: u32, timeout: Duration) -> Result<Self> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(timeout)
+            .open()
+            .map_err(|e| ZBotError::Hardware(format!("Failed to open serial port {}: {}", path, e)))?;
+        debug!("Opened Feetech serial bus on {} at {} baud", path, baud_rate);
+        Ok(Self::from_port(port, path))
+    }
+
+    /// Wraps an already opened port, e.g. one end of a pseudo-terminal pair.
+    pub fn from_port(port: Box<dyn SerialPort>, path: &str) -> Self {
+        Self {
+            port: Mutex::new(port),
+            path: path.to_string(),
+            active_servos: Mutex::new(Vec::new()),
+            last_info: Mutex::new(HashMap::new()),
+            started: Instant::now(),
+            local_echo: false,
+        }
+    }
+
+    /// Discards the echo of every packet sent before reading replies.
+    pub fn with_local_echo(mut self, local_echo: bool) -> Self {
+        self.local_echo = local_echo;
+        self
+    }
+
+    pub fn ping(&self, id: u8) -> Result<StatusPacket> {
+        self.transact(id, Instruction::Ping, &[])
+    }
+
+    pub fn read_registers(&self, id: u8, address: u8, length: u8) -> Result<Vec<u8>> {
+        let status = self.transact(id, Instruction::Read, &[address, length])?;
+        if status.params.len() != length as usize {
+            return Err(ZBotError::BadReply(format!(
+                "Short read from servo {}: expected {} bytes, got {}",
+                id,
+                length,
+                status.params.len()
+            ))
+            .into());
+        }
+        Ok(status.params)
+    }
+
+    pub fn write_registers(&self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        self.write_instruction(id, Instruction::Write, address, data)
+    }
+
+    /// Buffers a write on the servo until an `action` is sent.
+    pub fn reg_write(&self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        self.write_instruction(id, Instruction::RegWrite, address, data)
+    }
+
+    /// Triggers all buffered `reg_write`s. Broadcast actions are unacknowledged.
+    pub fn action(&self, id: u8) -> Result<()> {
+        if id == BROADCAST_ID {
+            return self.send(id, Instruction::Action, &[]);
+        }
+        self.transact(id, Instruction::Action, &[]).map(|_| ())
+    }
+
+    /// Reads the same register block from several servos. Replies are matched
+    /// to servos by their id, and read until every servo has answered or one
+    /// port timeout per servo has passed. Servos that do not answer yield
+    /// `None`.
+    pub fn sync_read(&self, ids: &[u8], address: u8, length: u8) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut params = Vec::with_capacity(ids.len() + 2);
+        params.push(address);
+        params.push(length);
+        params.extend_from_slice(ids);
+
+        let mut port = self.port.lock().unwrap();
+        port.clear(ClearBuffer::Input)?;
+        self.write_packet(&mut **port, &encode_packet(BROADCAST_ID, Instruction::SyncRead, &params)?)?;
+        let deadline = Instant::now() + port.timeout() * ids.len() as u32;
+
+        let mut replies: HashMap<u8, Vec<u8>> = HashMap::with_capacity(ids.len());
+        while replies.len() < ids.len() && Instant::now() < deadline {
+            match read_status(&mut **port) {
+                Ok(status) if ids.contains(&status.id) && status.params.len() == length as usize => {
+                    replies.insert(status.id, status.params);
+                }
+                Ok(status) => {
+                    trace!("sync_read: ignoring reply from servo {}", status.id);
+                }
+                Err(e) => {
+                    trace!("sync_read: {}", e);
+                }
+            }
+        }
+        Ok(ids.iter().map(|id| replies.remove(id)).collect())
+    }
+
+    /// Writes `entry length` bytes at `address` on several servos with a single
+    /// broadcast packet. `entries` holds `(id, data)` pairs of equal length.
+    pub fn sync_write(&self, address: u8, entries: &[(u8, &[u8])]) -> Result<()> {
+        let entry_len = entries.first().map_or(0, |(_, data)| data.len());
+        let mut params = Vec::with_capacity(2 + entries.len() * (entry_len + 1));
+        params.push(address);
+        params.push(entry_len as u8);
+        for (id, data) in entries {
+            if data.len() != entry_len {
+                return Err(ZBotError::InvalidArgument(format!("sync_write entries must all be {} bytes", entry_len)).into());
+            }
+            params.push(*id);
+            params.extend_from_slice(data);
+        }
+        self.send(BROADCAST_ID, Instruction::SyncWrite, &params)
+    }
+
+    fn write_instruction(&self, id: u8, instruction: Instruction, address: u8, data: &[u8]) -> Result<()> {
+        let mut params = Vec::with_capacity(data.len() + 1);
+        params.push(address);
+        params.extend_from_slice(data);
+        if id == BROADCAST_ID {
+            return self.send(id, instruction, &params);
+        }
+        self.transact(id, instruction, &params).map(|_| ())
+    }
+
+    fn send(&self, id: u8, instruction: Instruction, params: &[u8]) -> Result<()> {
+        let mut port = self.port.lock().unwrap();
+        self.write_packet(&mut **port, &encode_packet(id, instruction, params)?)
+    }
+
+    fn transact(&self, id: u8, instruction: Instruction, params: &[u8]) -> Result<StatusPacket> {
+        let mut port = self.port.lock().unwrap();
+        port.clear(ClearBuffer::Input)?;
+        self.write_packet(&mut **port, &encode_packet(id, instruction, params)?)?;
+
+        let status = read_status(&mut **port)?;
+        if status.id != id {
+            return Err(ZBotError::BadReply(format!("Reply from servo {} while waiting for {}", status.id, id)).into());
+        }
+        if status.error != 0 {
+            trace!("servo {} reported status 0x{:02X}", id, status.error);
+        }
+        Ok(status)
+    }
+
+    /// Sends `packet`, then reads back and checks its echo if the adapter
+    /// echoes, so the echo is never parsed as a reply.
+    fn write_packet(&self, port: &mut dyn SerialPort, packet: &[u8]) -> Result<()> {
+        port.write_all(packet)?;
+        port.flush()?;
+        if !self.local_echo {
+            return Ok(());
+        }
+        let mut echo = vec![0u8; packet.len()];
+        port.read_exact(&mut echo).map_err(|e| {
+            ZBotError::Hardware(format!("No local echo on {} ({}); is local_echo set on a full-duplex adapter?", self.path, e))
+        })?;
+        if echo != packet {
+            return Err(ZBotError::BadReply(format!("Local echo {:02X?} does not match the packet sent", echo)).into());
+        }
+        Ok(())
+    }
+}
+
+/// Reads one status packet: `0xFF 0xFF ID LEN ERR PARAM... CHECKSUM`.
+pub fn read_status(port: &mut dyn Read) -> Result<StatusPacket> {
+    let mut byte = [0u8; 1];
+    let mut previous = 0u8;
+    // Skip noise until a header.
+    loop {
+        port.read_exact(&mut byte).map_err(status_read_error)?;
+        if previous == HEADER[0] && byte[0] == HEADER[1] {
+            break;
+        }
+        previous = byte[0];
+    }
+
+    let mut head = [0u8; 2];
+    port.read_exact(&mut head).map_err(status_read_error)?;
+    let [id, length] = head;
+    if id == HEADER[0] || length < 2 {
+        return Err(ZBotError::BadReply(format!("Malformed status packet header: {:02X?}", head)).into());
+    }
+
+    let mut body = vec![0u8; length as usize];
+    port.read_exact(&mut body).map_err(status_read_error)?;
+    let expected = checksum(&[&head[..], &body[..body.len() - 1]].concat());
+    let received = body[body.len() - 1];
+    if expected != received {
+        return Err(ZBotError::Checksum { id, expected, received }.into());
+    }
+
+    Ok(StatusPacket {
+        id,
+        error: body[0],
+        params: body[1..body.len() - 1].to_vec(),
+    })
+}
+
+/// A servo that stops answering mid-packet is a bus timeout like one that
+/// never answers.
+fn status_read_error(e: std::io::Error) -> eyre::Report {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => ZBotError::BusTimeout.into(),
+        _ => prefixed("Failed to read status packet")(e),
+    }
+}
+
+fn parse_info(id: u8, last_read_ms: u32, block: &[u8]) -> ServoInfo {
+    let u8_at = |address: u8| block[(address - INFO_START) as usize];
+    let u16_at = |address: u8| u16::from_le_bytes([u8_at(address), u8_at(address + 1)]);
+    ServoInfo {
+        id,
+        last_read_ms,
+        torque_switch: u8_at(0x28),
+        acceleration: u8_at(0x29),
+        target_location: u16_at(0x2A) as i16,
+        running_time: u16_at(0x2C),
+        running_speed: u16_at(0x2E),
+        torque_limit: u16_at(0x30),
+        reserved1: [0; 6],
+        lock_mark: u8_at(0x37),
+        current_location: u16_at(0x38) as i16,
+        current_speed: u16_at(0x3A) as i16,
+        current_load: u16_at(0x3C) as i16,
+        current_voltage: u8_at(0x3E),
+        current_temperature: u8_at(0x3F),
+        async_write_flag: u8_at(0x40),
+        servo_status: u8_at(0x41),
+        mobile_sign: u8_at(0x42),
+        reserved2: [0; 2],
+        current_current: u16_at(0x45),
+    }
+}
+
+impl FeetechBus for SerialBus {
+    fn init(&self) -> Result<()> {
+        self.port.lock().unwrap().clear(ClearBuffer::All)?;
+        Ok(())
+    }
+
+    fn deinit(&self) {}
+
+    fn read(&self, id: u8, address: u8, data: &mut [u8]) -> Result<()> {
+        let bytes = self.read_registers(id, address, data.len() as u8)?;
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn write(&self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        self.write_registers(id, address, data)
+    }
+
+    fn set_active_servos(&self, active_servos: &ActiveServoList) -> Result<()> {
+        let len = (active_servos.len as usize).min(MAX_SERVOS);
+        *self.active_servos.lock().unwrap() = active_servos.servo_id[..len].to_vec();
+        Ok(())
+    }
+
+    fn get_info(&self, info_buffer: &mut ServoInfoBuffer) -> Result<()> {
+        let ids = self.active_servos.lock().unwrap().clone();
+        let results = self.sync_read(&ids, INFO_START, INFO_LENGTH)?;
+        let now_ms = self.started.elapsed().as_millis() as u32;
+
+        let mut last_info = self.last_info.lock().unwrap();
+        let mut read_count = 0;
+        let mut fault_count = 0;
+        for (slot, (id, block)) in ids.iter().zip(results).enumerate() {
+            match block {
+                Some(block) => {
+                    let info = parse_info(*id, now_ms, &block);
+                    last_info.insert(*id, info);
+                    info_buffer.servos[slot] = info;
+                    read_count += 1;
+                }
+                None => {
+                    // Repeat the previous reading so last_read_ms goes stale.
+                    if let Some(info) = last_info.get(id) {
+                        info_buffer.servos[slot] = *info;
+                    }
+                    fault_count += 1;
+                }
+            }
+        }
+
+        info_buffer.read_count = read_count;
+        info_buffer.fault_count = fault_count;
+        info_buffer.retry_count = 0;
+        info_buffer.loop_count = 1;
+        info_buffer.last_read_ms = now_ms;
+        Ok(())
+    }
+
+    fn broadcast_command(&self, command: &BroadcastCommand) -> Result<()> {
+        let length = command.data_length as usize;
+        if length < 2 {
+            return Ok(());
+        }
+        // The broadcast frame is already laid out as SYNC_WRITE parameters:
+        // address, entry length, then (id, data...) per servo.
+        self.send(BROADCAST_ID, Instruction::SyncWrite, &command.data[..length])
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// Answers instruction packets on the master end of a pty like a chain of
+    /// servos with 256 bytes of registers each.
+    struct FakeServos {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl FakeServos {
+        fn spawn(ids: &[u8], corrupt_checksum: bool) -> (SerialBus, Self) {
+            let (mut master, mut slave) = TTYPort::pair().expect("pty pair");
+            slave.set_timeout(TIMEOUT).unwrap();
+            master.set_timeout(Duration::from_millis(10)).unwrap();
+
+            let mut registers: HashMap<u8, Vec<u8>> =
+                ids.iter().map(|id| (*id, (0..=255).map(|b: u8| b ^ id).collect())).collect();
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = std::thread::spawn({
+                let stop = stop.clone();
+                move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        // Instruction packets share the status layout, with
+                        // the instruction in place of the error byte.
+                        let Ok(packet) = read_status(&mut master) else {
+                            continue;
+                        };
+                        for (id, params) in answer(&mut registers, &packet) {
+                            let mut reply = vec![0xFF, 0xFF, id, params.len() as u8 + 2, 0];
+                            reply.extend_from_slice(&params);
+                            let sum = checksum(&reply[2..]);
+                            reply.push(if corrupt_checksum { sum.wrapping_add(1) } else { sum });
+                            master.write_all(&reply).unwrap();
+                        }
+                    }
+                }
+            });
+            let bus = SerialBus::from_port(Box::new(slave), "pty");
+            (bus, Self { stop, thread: Some(thread) })
+        }
+    }
+
+    impl Drop for FakeServos {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// Replies `(id, params)` to one instruction packet.
+    fn answer(registers: &mut HashMap<u8, Vec<u8>>, packet: &StatusPacket) -> Vec<(u8, Vec<u8>)> {
+        let params = &packet.params;
+        match packet.error {
+            op if op == Instruction::Ping as u8 && registers.contains_key(&packet.id) => vec![(packet.id, vec![])],
+            op if op == Instruction::Read as u8 => registers
+                .get(&packet.id)
+                .map(|regs| (packet.id, regs[params[0] as usize..][..params[1] as usize].to_vec()))
+                .into_iter()
+                .collect(),
+            op if op == Instruction::Write as u8 => match registers.get_mut(&packet.id) {
+                Some(regs) => {
+                    let address = params[0] as usize;
+                    regs[address..address + params.len() - 1].copy_from_slice(&params[1..]);
+                    vec![(packet.id, vec![])]
+                }
+                None => vec![],
+            },
+            op if op == Instruction::SyncRead as u8 => {
+                let (address, length) = (params[0] as usize, params[1] as usize);
+                params[2..]
+                    .iter()
+                    .filter_map(|id| registers.get(id).map(|regs| (*id, regs[address..][..length].to_vec())))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn zbot_error(report: &eyre::Report) -> &ZBotError {
+        report.downcast_ref::<ZBotError>().expect("a ZBotError")
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let (bus, _servos) = FakeServos::spawn(&[1, 2], false);
+        assert_eq!(bus.ping(2).unwrap().id, 2);
+        assert_eq!(bus.read_registers(1, 0x38, 2).unwrap(), vec![0x38 ^ 1, 0x39 ^ 1]);
+        bus.write_registers(2, 0x2A, &[0x34, 0x12]).unwrap();
+        assert_eq!(bus.read_registers(2, 0x2A, 2).unwrap(), vec![0x34, 0x12]);
+    }
+
+    #[test]
+    fn sync_read_leaves_missing_servos_empty() {
+        let (bus, _servos) = FakeServos::spawn(&[1, 3], false);
+        let replies = bus.sync_read(&[3, 2, 1], 0x38, 2).unwrap();
+        assert_eq!(replies, vec![Some(vec![0x38 ^ 3, 0x39 ^ 3]), None, Some(vec![0x38 ^ 1, 0x39 ^ 1])]);
+    }
+
+    #[test]
+    fn rejects_a_corrupt_checksum() {
+        let (bus, _servos) = FakeServos::spawn(&[1], true);
+        let err = bus.read_registers(1, 0x38, 2).unwrap_err();
+        assert!(matches!(zbot_error(&err), ZBotError::Checksum { id: 1, .. }), "{}", err);
+    }
+
+    #[test]
+    fn times_out_on_a_silent_servo() {
+        let (bus, _servos) = FakeServos::spawn(&[1], false);
+        let started = Instant::now();
+        let err = bus.read_registers(7, 0x38, 2).unwrap_err();
+        assert!(matches!(zbot_error(&err), ZBotError::BusTimeout), "{}", err);
+        assert!(started.elapsed() >= TIMEOUT);
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        assert!(encode_packet(1, Instruction::Write, &[0; MAX_PARAMS]).is_ok());
+        let err = encode_packet(1, Instruction::Write, &[0; MAX_PARAMS + 1]).unwrap_err();
+        assert!(matches!(zbot_error(&err), ZBotError::OutOfRange(_)), "{}", err);
+    }
+}
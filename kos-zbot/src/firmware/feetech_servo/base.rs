@@ -0,0 +1,457 @@
+use crate::error::{prefixed, ZBotError};
+use crate::firmware::feetech::{
+    feetech_read, feetech_write, CalibrationRegisters, FeetechActuator, FeetechActuatorInfo, FeetechBus,
+    FeetechOperationMode, ServoInfo, MAX_SERVO_ID,
+};
+use crate::firmware::liveness::Liveness;
+use eyre::Result;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, trace};
+
+/// Registers shared by every servo in the STS family.
+const REGISTER_ID: u8 = 0x05;
+const REGISTER_MIN_ANGLE: u8 = 0x09;
+const REGISTER_MAX_ANGLE: u8 = 0x0B;
+const REGISTER_P_COEFFICIENT: u8 = 0x15;
+const REGISTER_D_COEFFICIENT: u8 = 0x16;
+const REGISTER_I_COEFFICIENT: u8 = 0x17;
+const REGISTER_OFFSET: u8 = 0x1F;
+const REGISTER_OPERATION_MODE: u8 = 0x21;
+const REGISTER_TORQUE_SWITCH: u8 = 0x28;
+const REGISTER_ACCELERATION: u8 = 0x29;
+const REGISTER_TARGET_LOCATION: u8 = 0x2A;
+const REGISTER_RUNNING_SPEED: u8 = 0x2E;
+const REGISTER_TORQUE_LIMIT: u8 = 0x30;
+const REGISTER_LOCK_MARK: u8 = 0x37;
+const REGISTER_PRESENT_CURRENT: u8 = 0x45;
+
+/// What differs between servo models of the STS family; the register map
+/// and encoding are shared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServoModel {
+    pub name: &'static str,
+    /// Stall torque at the rated voltage.
+    pub stall_torque_nm: f32,
+    /// Present current register unit in mA.
+    pub current_unit_ma: f32,
+}
+
+/// Bus handle, id and model of a servo, with the register handling shared
+/// by every STS model.
+#[derive(Debug, Clone)]
+pub struct FeetechServoBase {
+    pub id: u8,
+    pub bus: Arc<dyn FeetechBus>,
+    pub model: ServoModel,
+}
+
+impl FeetechServoBase {
+    pub fn new(bus: Arc<dyn FeetechBus>, id: u8, model: ServoModel) -> Self {
+        Self { id, bus, model }
+    }
+
+    pub fn read(&self, address: u8, length: u8) -> Result<Vec<u8>> {
+        feetech_read(&*self.bus, self.id, address, length)
+    }
+
+    pub fn write(&self, address: u8, data: &[u8]) -> Result<()> {
+        feetech_write(&*self.bus, self.id, address, data)
+    }
+
+    pub fn unlock_eeprom(&self) -> Result<()> {
+        self.write(REGISTER_LOCK_MARK, &[0x00])
+            .map_err(prefixed("Failed to unlock EEPROM"))
+    }
+
+    pub fn lock_eeprom(&self) -> Result<()> {
+        self.write(REGISTER_LOCK_MARK, &[0x01])
+            .map_err(prefixed("Failed to lock EEPROM"))
+    }
+
+    /// Runs `f` with the EEPROM unlocked, re-locking it even if `f` fails.
+    /// An error from `f` is returned over one from re-locking.
+    pub fn with_eeprom_unlocked<T>(&self, f: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        self.unlock_eeprom()?;
+        let result = f(self);
+        let locked = self.lock_eeprom();
+        let value = result?;
+        locked?;
+        Ok(value)
+    }
+
+    pub fn read_calibration(&self) -> Result<CalibrationRegisters> {
+        let read_u16 = |address| -> Result<u16> {
+            let data = self.read(address, 2)?;
+            Ok(u16::from_le_bytes([data[0], data[1]]))
+        };
+        Ok(CalibrationRegisters {
+            min_angle: read_u16(REGISTER_MIN_ANGLE)?,
+            max_angle: read_u16(REGISTER_MAX_ANGLE)?,
+            offset: read_u16(REGISTER_OFFSET)?,
+        })
+    }
+
+    pub fn write_calibration(&self, registers: &CalibrationRegisters) -> Result<()> {
+        self.with_eeprom_unlocked(|base| {
+            base.write(REGISTER_MIN_ANGLE, &registers.min_angle.to_le_bytes())?;
+            base.write(REGISTER_MAX_ANGLE, &registers.max_angle.to_le_bytes())?;
+            base.write(REGISTER_OFFSET, &registers.offset.to_le_bytes())
+        })
+    }
+
+    /// Encoder steps per revolution.
+    pub const RESOLUTION: f32 = 4096.0;
+
+    pub fn degrees_to_raw(degrees: f32, offset: f32) -> u16 {
+        ((degrees + offset) / 360.0 * Self::RESOLUTION) as u16
+    }
+
+    pub fn raw_to_degrees(raw: u16, offset: f32) -> f32 {
+        raw as f32 / Self::RESOLUTION * 360.0 - offset
+    }
+
+    /// Decodes a sign-magnitude register with the sign in bit 15.
+    fn signed_raw_to_degrees(raw: u16) -> f32 {
+        let magnitude = Self::raw_to_degrees(raw & 0x7FFF, 0.0);
+        if raw & 0x8000 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Fills `info` from a telemetry reading, scaled by the model.
+    pub fn update_info(&self, info: &mut FeetechActuatorInfo, reading: &ServoInfo) {
+        info.id = self.id;
+        info.position_deg = Self::raw_to_degrees(reading.current_location as u16, 180.0);
+        info.speed_deg_per_s = Self::signed_raw_to_degrees(reading.current_speed as u16);
+        info.load_percent = reading.current_load as f32 / 100.0;
+        info.torque_nm = load_fraction(reading.current_load as u16) * self.model.stall_torque_nm;
+        info.voltage_v = reading.current_voltage as f32 / 10.0;
+        info.current_ma = reading.current_current as f32 * self.model.current_unit_ma;
+        info.temperature_c = reading.current_temperature as f32;
+
+        info.last_read_ms = reading.last_read_ms;
+        info.faults = decode_status(reading.servo_status);
+    }
+
+    pub fn set_position(&self, position_deg: f32) -> Result<()> {
+        let raw = Self::degrees_to_raw(position_deg, 180.0);
+        self.write(REGISTER_TARGET_LOCATION, &raw.to_le_bytes())
+            .map_err(prefixed("Failed to set position"))
+    }
+
+    /// Sets the running speed, sign-magnitude with the sign in bit 15.
+    pub fn set_speed(&self, speed_deg_per_s: f32) -> Result<()> {
+        let sign: u16 = if speed_deg_per_s < 0.0 { 0x8000 } else { 0x0000 };
+        let speed_raw = Self::degrees_to_raw(speed_deg_per_s.abs(), 0.0) | sign;
+        debug!("Setting speed: {} -> {}", speed_deg_per_s, speed_raw);
+        self.write(REGISTER_RUNNING_SPEED, &speed_raw.to_le_bytes())
+            .map_err(prefixed("Failed to set speed"))
+    }
+
+    pub fn set_operation_mode(&self, mode: FeetechOperationMode) -> Result<()> {
+        let mode_raw = match mode {
+            FeetechOperationMode::PositionControl => 0x00,
+            FeetechOperationMode::SpeedControl => 0x01,
+            FeetechOperationMode::TorqueControl => {
+                return Err(ZBotError::Unsupported(format!(
+                    "Torque control is not supported for {}",
+                    self.model.name
+                ))
+                .into())
+            }
+        };
+        self.write(REGISTER_OPERATION_MODE, &[mode_raw])
+            .map_err(prefixed("Failed to set operation mode"))
+    }
+
+    pub fn set_torque_enabled(&self, enabled: bool) -> Result<()> {
+        if enabled {
+            self.write(REGISTER_TORQUE_SWITCH, &[0x01])
+                .map_err(prefixed("Failed to enable torque"))?;
+            debug!("Torque Enabled [id]: {}", self.id);
+        } else {
+            self.write(REGISTER_TORQUE_SWITCH, &[0x00])
+                .map_err(prefixed("Failed to disable torque"))?;
+            debug!("Torque Disabled [id]: {}", self.id);
+        }
+        Ok(())
+    }
+
+    pub fn set_pid(&self, p: Option<f32>, i: Option<f32>, d: Option<f32>) -> Result<()> {
+        self.with_eeprom_unlocked(|base| {
+            if let Some(p) = p {
+                base.write(REGISTER_P_COEFFICIENT, &[p as u8])?;
+            }
+            if let Some(i) = i {
+                base.write(REGISTER_I_COEFFICIENT, &[i as u8])?;
+            }
+            if let Some(d) = d {
+                base.write(REGISTER_D_COEFFICIENT, &[d as u8])?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Present current in mA.
+    pub fn get_current(&self) -> Result<f32> {
+        let data = self.read(REGISTER_PRESENT_CURRENT, 2)?;
+        let current_raw = u16::from_le_bytes([data[0], data[1]]);
+        let current = current_raw as f32 * self.model.current_unit_ma;
+        trace!("Current: {}, raw: {}", current, current_raw);
+        Ok(current)
+    }
+
+    pub fn set_torque_limit(&self, fraction: f32) -> Result<()> {
+        // 0.1 % of maximum torque per unit.
+        let limit_raw = (fraction.clamp(0.0, 1.0) * 1000.0) as u16;
+        self.write(REGISTER_TORQUE_LIMIT, &limit_raw.to_le_bytes())
+            .map_err(prefixed("Failed to set torque limit"))
+    }
+
+    pub fn set_acceleration(&self, accel_deg_per_s2: f32) -> Result<()> {
+        // One acceleration unit is 100 steps/s².
+        let accel_raw = (Self::degrees_to_raw(accel_deg_per_s2, 0.0) as f32 / 100.0).clamp(0.0, 254.0) as u8;
+        debug!("Setting acceleration: {} deg/s² -> {} register units", accel_deg_per_s2, accel_raw);
+        self.write(REGISTER_ACCELERATION, &[accel_raw])
+            .map_err(prefixed("Failed to set acceleration"))
+    }
+
+    /// Centers the range between the end stops `min_angle` and `max_angle`
+    /// (degrees) on the middle of the encoder, shifted by `offset` degrees,
+    /// and writes the resulting limits and offset to EEPROM.
+    pub fn write_calibration_data(&self, min_angle: f32, max_angle: f32, offset: f32) -> Result<()> {
+        let min_raw = Self::degrees_to_raw(min_angle, 180.0) as i32;
+        let mut max_raw = Self::degrees_to_raw(max_angle, 180.0) as i32;
+
+        if max_raw < min_raw {
+            max_raw += 4096;
+        }
+
+        let servo_offset = min_raw + (max_raw - min_raw) / 2 - 2048;
+        let servo_offset = if servo_offset < 0 {
+            servo_offset.unsigned_abs() as u16 | 0x800
+        } else {
+            servo_offset as u16
+        };
+        let servo_offset = servo_offset + Self::degrees_to_raw(offset, 0.0);
+
+        let half_range = (max_raw - min_raw) / 2;
+        let min_raw = std::cmp::max(0, 2048 - half_range) as u16;
+        let max_raw = std::cmp::min(4095, 2048 + half_range) as u16;
+
+        trace!(
+            "Writing calibration, offset: {}, min_angle: {}, max_angle: {}",
+            servo_offset,
+            min_raw,
+            max_raw
+        );
+
+        self.with_eeprom_unlocked(|base| {
+            base.write(REGISTER_MIN_ANGLE, &min_raw.to_le_bytes())?;
+            base.write(REGISTER_MAX_ANGLE, &max_raw.to_le_bytes())?;
+            base.write(REGISTER_OFFSET, &servo_offset.to_le_bytes())?;
+            base.write(REGISTER_OPERATION_MODE, &[0x00])?;
+            Ok(())
+        })
+    }
+
+    /// Opens the angle limits to the full turn and makes the present position
+    /// the middle of the range.
+    pub fn set_zero_position(&self) -> Result<()> {
+        self.with_eeprom_unlocked(|base| {
+            base.write(REGISTER_MIN_ANGLE, &0u16.to_le_bytes())?;
+            thread::sleep(Duration::from_millis(10));
+            base.write(REGISTER_MAX_ANGLE, &4095u16.to_le_bytes())?;
+            thread::sleep(Duration::from_millis(10));
+            base.write(REGISTER_OPERATION_MODE, &[0x00])?;
+            thread::sleep(Duration::from_millis(10));
+            base.write(REGISTER_TORQUE_SWITCH, &[0x80])?;
+            thread::sleep(Duration::from_millis(10));
+            Ok(())
+        })
+    }
+
+    pub fn check_id(&self) -> Result<()> {
+        let id = self.read(REGISTER_ID, 1)?[0];
+        if id != self.id {
+            return Err(ZBotError::BadReply(format!("Servo ID mismatch: expected {}, got {}", self.id, id)).into());
+        }
+        Ok(())
+    }
+
+    /// Moves the servo to `id` and confirms it answers there. The caller
+    /// must make sure nothing else answers on `id`. If the reply to the id
+    /// write is lost, the servo is probed on both ids to find where it is.
+    pub fn change_id(&mut self, id: u8) -> Result<()> {
+        if id == self.id {
+            return Ok(());
+        }
+        if !(1..=MAX_SERVO_ID).contains(&id) {
+            return Err(ZBotError::OutOfRange(format!("Servo id {} is not in 1..={}", id, MAX_SERVO_ID)).into());
+        }
+        self.check_id()?;
+
+        self.unlock_eeprom()?;
+        let written = self.write(REGISTER_ID, &[id]);
+        if !answers_on(&*self.bus, id, ID_VERIFY_ATTEMPTS) {
+            let _ = self.lock_eeprom();
+            return Err(match written {
+                Ok(()) => ZBotError::Hardware(format!("Servo {} did not answer on id {} after the change", self.id, id)).into(),
+                Err(e) => prefixed("Failed to change ID")(e),
+            });
+        }
+        // The servo answers on the new id from here on.
+        self.id = id;
+        self.lock_eeprom()?;
+        Ok(())
+    }
+}
+
+/// A servo of the STS family. The model only changes the scaling of the
+/// telemetry.
+#[derive(Debug, Clone)]
+pub struct StsServo {
+    pub base: FeetechServoBase,
+    pub info: FeetechActuatorInfo,
+}
+
+impl StsServo {
+    pub fn new(bus: Arc<dyn FeetechBus>, id: u8, model: ServoModel) -> Self {
+        Self {
+            base: FeetechServoBase::new(bus, id, model),
+            info: FeetechActuatorInfo::default(),
+        }
+    }
+}
+
+impl FeetechActuator for StsServo {
+    fn id(&self) -> u8 {
+        self.base.id
+    }
+
+    fn check_id(&self) -> Result<()> {
+        self.base.check_id()
+    }
+
+    fn info(&self) -> FeetechActuatorInfo {
+        self.info.clone()
+    }
+
+    fn set_position(&mut self, position_deg: f32) -> Result<()> {
+        self.base.set_position(position_deg)
+    }
+
+    fn set_speed(&mut self, speed_deg_per_s: f32) -> Result<()> {
+        self.base.set_speed(speed_deg_per_s)
+    }
+
+    fn set_acceleration(&mut self, accel_deg_per_s2: f32) -> Result<()> {
+        self.base.set_acceleration(accel_deg_per_s2)
+    }
+
+    fn set_torque_limit(&mut self, fraction: f32) -> Result<()> {
+        self.base.set_torque_limit(fraction)
+    }
+
+    fn enable_torque(&mut self) -> Result<()> {
+        self.info.torque_enabled = true;
+        self.base.set_torque_enabled(true)
+    }
+
+    fn disable_torque(&mut self) -> Result<()> {
+        self.info.torque_enabled = false;
+        self.base.set_torque_enabled(false)
+    }
+
+    fn change_id(&mut self, id: u8) -> Result<()> {
+        self.base.change_id(id)
+    }
+
+    fn update_info(&mut self, info: &ServoInfo) {
+        self.base.update_info(&mut self.info, info);
+    }
+
+    fn update_liveness(&mut self, liveness: &Liveness) {
+        self.info.online = liveness.online;
+        self.info.last_reading_at = liveness.last_reading_at;
+    }
+
+    fn degrees_to_raw(&self, degrees: f32, offset: f32) -> u16 {
+        FeetechServoBase::degrees_to_raw(degrees, offset)
+    }
+
+    fn raw_to_degrees(&self, raw: u16, offset: f32) -> f32 {
+        FeetechServoBase::raw_to_degrees(raw, offset)
+    }
+
+    fn set_pid(&mut self, p: Option<f32>, i: Option<f32>, d: Option<f32>) -> Result<()> {
+        self.base.set_pid(p, i, d)
+    }
+
+    fn set_operation_mode(&mut self, mode: FeetechOperationMode) -> Result<()> {
+        self.base.set_operation_mode(mode)
+    }
+
+    fn get_current(&self) -> Result<f32> {
+        self.base.get_current()
+    }
+
+    fn write_calibration_data(&mut self, min_angle: f32, max_angle: f32, offset: f32) -> Result<()> {
+        self.base.write_calibration_data(min_angle, max_angle, offset)
+    }
+
+    fn read_calibration_registers(&self) -> Result<CalibrationRegisters> {
+        self.base.read_calibration()
+    }
+
+    fn write_calibration_registers(&mut self, registers: &CalibrationRegisters) -> Result<()> {
+        self.base.write_calibration(registers)
+    }
+
+    fn set_zero_position(&mut self) -> Result<()> {
+        self.base.set_zero_position()
+    }
+}
+
+/// Attempts at reading back the id register after an id change.
+const ID_VERIFY_ATTEMPTS: u32 = 5;
+
+/// Whether a servo answers on `id` with a matching id register, trying
+/// `attempts` times 10 ms apart.
+pub fn answers_on(bus: &dyn FeetechBus, id: u8, attempts: u32) -> bool {
+    let mut data = [0u8; 1];
+    (0..attempts).any(|attempt| {
+        if attempt > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        bus.read(id, REGISTER_ID, &mut data).is_ok() && data[0] == id
+    })
+}
+
+/// Names of the status register fault bits, lowest bit first.
+pub const STATUS_FAULTS: [&str; 6] = ["Voltage", "Sensor", "Temperature", "Current", "Angle", "Overload"];
+
+/// Decodes the servo status register (0x41) into fault names.
+pub fn decode_status(status: u8) -> Vec<String> {
+    STATUS_FAULTS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| status & (1 << bit) != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// Converts the present load register (0.1 % of stall torque, sign in bit 10)
+/// into a signed fraction of stall torque.
+pub fn load_fraction(raw: u16) -> f32 {
+    let magnitude = (raw & 0x3FF) as f32 / 1000.0;
+    if raw & 0x400 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
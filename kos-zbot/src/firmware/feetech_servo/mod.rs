@@ -1,3 +1,7 @@
+mod base;
 mod sts3215;
+mod sts3250;
 
+pub use base::*;
 pub use sts3215::*;
+pub use sts3250::*;
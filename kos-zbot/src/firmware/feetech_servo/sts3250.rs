@@ -0,0 +1,8 @@
+use super::base::ServoModel;
+
+/// 50 kg·cm, 12 V servo used on the knees of the larger leg variant.
+pub const STS3250: ServoModel = ServoModel {
+    name: "Sts3250",
+    stall_torque_nm: 4.9,
+    current_unit_ma: 6.5,
+};
@@ -0,0 +1,519 @@
+use super::feetech::{
+    ActiveServoList, BroadcastCommand, FeetechActuatorType, FeetechBus, ServoInfo,
+    ServoInfoBuffer, MAX_SERVOS,
+};
+use crate::error::ZBotError;
+use eyre::{Report, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+const REGISTER_COUNT: usize = 0x50;
+
+const ADDR_MODEL: usize = 0x03;
+const ADDR_ID: usize = 0x05;
+const ADDR_MIN_ANGLE: usize = 0x09;
+const ADDR_MAX_ANGLE: usize = 0x0B;
+const ADDR_MAX_TEMPERATURE: usize = 0x0D;
+const ADDR_MAX_VOLTAGE: usize = 0x0E;
+const ADDR_MIN_VOLTAGE: usize = 0x0F;
+const ADDR_P_COEFF: usize = 0x15;
+const ADDR_D_COEFF: usize = 0x16;
+const ADDR_I_COEFF: usize = 0x17;
+const ADDR_OFFSET: usize = 0x1F;
+const ADDR_OPERATION_MODE: usize = 0x21;
+const ADDR_TORQUE_SWITCH: usize = 0x28;
+const ADDR_ACCELERATION: usize = 0x29;
+const ADDR_TARGET_LOCATION: usize = 0x2A;
+const ADDR_RUNNING_TIME: usize = 0x2C;
+const ADDR_RUNNING_SPEED: usize = 0x2E;
+const ADDR_TORQUE_LIMIT: usize = 0x30;
+const ADDR_LOCK_MARK: usize = 0x37;
+const ADDR_CURRENT_LOCATION: usize = 0x38;
+const ADDR_CURRENT_SPEED: usize = 0x3A;
+const ADDR_CURRENT_LOAD: usize = 0x3C;
+const ADDR_CURRENT_VOLTAGE: usize = 0x3E;
+const ADDR_CURRENT_TEMPERATURE: usize = 0x3F;
+const ADDR_SERVO_STATUS: usize = 0x41;
+const ADDR_MOBILE_SIGN: usize = 0x42;
+const ADDR_CURRENT_CURRENT: usize = 0x45;
+
+/// Registers below this address live in EEPROM. Writes take effect either
+/// way, but only survive a power cycle while the lock mark is cleared.
+const EEPROM_END: usize = ADDR_TORQUE_SWITCH;
+
+/// Raw speed used in position mode when the goal speed register is zero.
+const MAX_SPEED_RAW: f32 = 3400.0;
+/// Current reported while driving into an end stop, in raw units (x6.5 mA).
+const STALL_CURRENT_RAW: u16 = 200;
+
+/// Register-level model of a single Feetech servo.
+#[derive(Debug, Clone)]
+pub struct SimServo {
+    pub model: FeetechActuatorType,
+    pub registers: [u8; REGISTER_COUNT],
+    /// Mechanical position in raw encoder units, before the offset register is
+    /// applied.
+    pub position_raw: f32,
+    pub velocity_raw: f32,
+    /// Mechanical end stops in raw encoder units, if the joint has any.
+    pub end_stops: Option<(f32, f32)>,
+    /// Time constant of the first-order position response.
+    pub time_constant: Duration,
+    pub supply_voltage_v: f32,
+    pub ambient_temperature_c: f32,
+    temperature_c: f32,
+    stalled: bool,
+    /// EEPROM contents restored by `power_cycle`.
+    eeprom: [u8; EEPROM_END],
+}
+
+impl SimServo {
+    pub fn new(id: u8, model: FeetechActuatorType) -> Self {
+        let mut servo = Self {
+            model,
+            registers: [0; REGISTER_COUNT],
+            position_raw: 2048.0,
+            velocity_raw: 0.0,
+            end_stops: None,
+            time_constant: Duration::from_millis(40),
+            supply_voltage_v: 12.0,
+            ambient_temperature_c: 25.0,
+            temperature_c: 25.0,
+            stalled: false,
+            eeprom: [0; EEPROM_END],
+        };
+
+        servo.registers[ADDR_MODEL..ADDR_MODEL + 2].copy_from_slice(&model.model_id());
+        servo.registers[ADDR_ID] = id;
+        servo.write_u16(ADDR_MIN_ANGLE, 0);
+        servo.write_u16(ADDR_MAX_ANGLE, 4095);
+        servo.registers[ADDR_MAX_TEMPERATURE] = 70;
+        servo.registers[ADDR_MAX_VOLTAGE] = 140;
+        servo.registers[ADDR_MIN_VOLTAGE] = 40;
+        servo.registers[ADDR_P_COEFF] = 32;
+        servo.registers[ADDR_D_COEFF] = 32;
+        servo.registers[ADDR_I_COEFF] = 0;
+        servo.write_u16(ADDR_TORQUE_LIMIT, 1000);
+        servo.registers[ADDR_LOCK_MARK] = 1;
+        servo.write_u16(ADDR_TARGET_LOCATION, 2048);
+        servo.eeprom.copy_from_slice(&servo.registers[..EEPROM_END]);
+        servo.refresh_present();
+        servo
+    }
+
+    /// Reloads EEPROM, dropping unpersisted writes, and resets RAM state.
+    pub fn power_cycle(&mut self) {
+        self.registers[..EEPROM_END].copy_from_slice(&self.eeprom);
+        self.registers[ADDR_TORQUE_SWITCH] = 0;
+        self.registers[ADDR_LOCK_MARK] = 1;
+        self.velocity_raw = 0.0;
+        self.refresh_present();
+    }
+
+    pub fn id(&self) -> u8 {
+        self.registers[ADDR_ID]
+    }
+
+    pub fn with_end_stops(mut self, min_deg: f32, max_deg: f32) -> Self {
+        self.end_stops = Some((deg_to_raw(min_deg), deg_to_raw(max_deg)));
+        self
+    }
+
+    pub fn torque_enabled(&self) -> bool {
+        self.registers[ADDR_TORQUE_SWITCH] == 1
+    }
+
+    pub fn temperature_c(&self) -> f32 {
+        self.temperature_c
+    }
+
+    pub fn set_temperature_c(&mut self, temperature_c: f32) {
+        self.temperature_c = temperature_c;
+        self.refresh_present();
+    }
+
+    fn read_u16(&self, address: usize) -> u16 {
+        u16::from_le_bytes([self.registers[address], self.registers[address + 1]])
+    }
+
+    fn write_u16(&mut self, address: usize, value: u16) {
+        self.registers[address..address + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn offset_raw(&self) -> f32 {
+        sign_magnitude(self.read_u16(ADDR_OFFSET), 11)
+    }
+
+    /// Goal position in mechanical raw units, clamped to the angle limits.
+    fn goal_raw(&self) -> f32 {
+        let goal = sign_magnitude(self.read_u16(ADDR_TARGET_LOCATION), 15);
+        let min = self.read_u16(ADDR_MIN_ANGLE) as f32;
+        let max = self.read_u16(ADDR_MAX_ANGLE) as f32;
+        let goal = if min < max { goal.clamp(min, max) } else { goal };
+        goal + self.offset_raw()
+    }
+
+    pub fn read(&self, address: u8, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let end = start + data.len();
+        if end > REGISTER_COUNT {
+            return Err(ZBotError::OutOfRange(format!(
+                "Read past end of register map: 0x{:02X}+{}",
+                address,
+                data.len()
+            ))
+            .into());
+        }
+        data.copy_from_slice(&self.registers[start..end]);
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u8, data: &[u8]) -> Result<()> {
+        let start = address as usize;
+        let end = start + data.len();
+        if end > REGISTER_COUNT {
+            return Err(ZBotError::OutOfRange(format!(
+                "Write past end of register map: 0x{:02X}+{}",
+                address,
+                data.len()
+            ))
+            .into());
+        }
+
+        for (i, &value) in data.iter().enumerate() {
+            let register = start + i;
+            let unlocked = self.registers[ADDR_LOCK_MARK] == 0;
+            if register < EEPROM_END {
+                if unlocked {
+                    self.eeprom[register] = value;
+                } else {
+                    trace!(
+                        "sim servo {}: write to locked EEPROM register 0x{:02X} will not persist",
+                        self.id(),
+                        register
+                    );
+                }
+            }
+            if register == ADDR_TORQUE_SWITCH && value == 0x80 {
+                // Calibrate the current position as the middle of the range.
+                let offset = self.position_raw.round() as i32 - 2048;
+                let offset = if offset < 0 {
+                    offset.unsigned_abs() as u16 | 0x800
+                } else {
+                    offset as u16
+                };
+                self.write_u16(ADDR_OFFSET, offset);
+                if unlocked {
+                    self.eeprom[ADDR_OFFSET..ADDR_OFFSET + 2]
+                        .copy_from_slice(&self.registers[ADDR_OFFSET..ADDR_OFFSET + 2]);
+                }
+                continue;
+            }
+            self.registers[register] = value;
+        }
+
+        if start <= ADDR_TORQUE_SWITCH && ADDR_TORQUE_SWITCH < end && !self.torque_enabled() {
+            self.velocity_raw = 0.0;
+        }
+        self.refresh_present();
+        Ok(())
+    }
+
+    /// Advance the servo dynamics by `dt`.
+    pub fn step(&mut self, dt: Duration) {
+        let dt_s = dt.as_secs_f32();
+        if dt_s <= 0.0 {
+            return;
+        }
+
+        let previous = self.position_raw;
+        let mut current_raw = 0.0;
+
+        if self.torque_enabled() {
+            match self.registers[ADDR_OPERATION_MODE] {
+                0 => {
+                    let goal_speed = self.read_u16(ADDR_RUNNING_SPEED) & 0x7FFF;
+                    let max_step = if goal_speed == 0 {
+                        MAX_SPEED_RAW
+                    } else {
+                        goal_speed as f32
+                    } * dt_s;
+                    let tau = self.time_constant.as_secs_f32().max(1e-3);
+                    let error = self.goal_raw() - self.position_raw;
+                    let step = (error * (1.0 - (-dt_s / tau).exp())).clamp(-max_step, max_step);
+                    self.position_raw += step;
+                    current_raw = (error.abs() * 0.5).min(STALL_CURRENT_RAW as f32);
+                }
+                1 => {
+                    let speed = sign_magnitude(self.read_u16(ADDR_RUNNING_SPEED), 15);
+                    self.position_raw += speed * dt_s;
+                    current_raw = 10.0 + speed.abs() * 0.05;
+                }
+                _ => {}
+            }
+        }
+
+        self.stalled = false;
+        if let Some((min, max)) = self.end_stops {
+            if self.position_raw < min || self.position_raw > max {
+                self.position_raw = self.position_raw.clamp(min, max);
+                self.stalled = self.torque_enabled();
+            }
+        }
+        if self.stalled {
+            current_raw = STALL_CURRENT_RAW as f32;
+        }
+
+        self.velocity_raw = (self.position_raw - previous) / dt_s;
+
+        // Crude thermal model: heat with current, cool towards ambient.
+        let heating = current_raw * 6.5 / 1000.0 * 0.5;
+        let cooling = (self.temperature_c - self.ambient_temperature_c) * 0.01;
+        self.temperature_c += (heating - cooling) * dt_s;
+
+        self.write_u16(ADDR_CURRENT_CURRENT, current_raw as u16);
+        self.refresh_present();
+    }
+
+    fn refresh_present(&mut self) {
+        let present = (self.position_raw - self.offset_raw()).rem_euclid(4096.0);
+        self.write_u16(ADDR_CURRENT_LOCATION, present as u16);
+        self.write_u16(ADDR_CURRENT_SPEED, to_sign_magnitude(self.velocity_raw, 15));
+        let load = (self.read_u16(ADDR_CURRENT_CURRENT) as f32 * 5.0).min(1000.0);
+        self.write_u16(ADDR_CURRENT_LOAD, load as u16);
+        self.registers[ADDR_CURRENT_VOLTAGE] = (self.supply_voltage_v * 10.0) as u8;
+        self.registers[ADDR_CURRENT_TEMPERATURE] = self.temperature_c.clamp(0.0, 255.0) as u8;
+        self.registers[ADDR_MOBILE_SIGN] = (self.velocity_raw.abs() > 1.0) as u8;
+
+        let mut status = 0u8;
+        let voltage = self.registers[ADDR_CURRENT_VOLTAGE];
+        if voltage > self.registers[ADDR_MAX_VOLTAGE] || voltage < self.registers[ADDR_MIN_VOLTAGE] {
+            status |= 1 << 0;
+        }
+        if self.registers[ADDR_CURRENT_TEMPERATURE] > self.registers[ADDR_MAX_TEMPERATURE] {
+            status |= 1 << 2;
+        }
+        if self.stalled {
+            status |= 1 << 5;
+        }
+        self.registers[ADDR_SERVO_STATUS] = status;
+    }
+
+    fn snapshot(&self, last_read_ms: u32) -> ServoInfo {
+        ServoInfo {
+            id: self.id(),
+            last_read_ms,
+            torque_switch: self.registers[ADDR_TORQUE_SWITCH],
+            acceleration: self.registers[ADDR_ACCELERATION],
+            target_location: self.read_u16(ADDR_TARGET_LOCATION) as i16,
+            running_time: self.read_u16(ADDR_RUNNING_TIME),
+            running_speed: self.read_u16(ADDR_RUNNING_SPEED),
+            torque_limit: self.read_u16(ADDR_TORQUE_LIMIT),
+            reserved1: [0; 6],
+            lock_mark: self.registers[ADDR_LOCK_MARK],
+            current_location: self.read_u16(ADDR_CURRENT_LOCATION) as i16,
+            current_speed: self.read_u16(ADDR_CURRENT_SPEED) as i16,
+            current_load: self.read_u16(ADDR_CURRENT_LOAD) as i16,
+            current_voltage: self.registers[ADDR_CURRENT_VOLTAGE],
+            current_temperature: self.registers[ADDR_CURRENT_TEMPERATURE],
+            async_write_flag: 0,
+            servo_status: self.registers[ADDR_SERVO_STATUS],
+            mobile_sign: self.registers[ADDR_MOBILE_SIGN],
+            reserved2: [0; 2],
+            current_current: self.read_u16(ADDR_CURRENT_CURRENT),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SimState {
+    // Servos can change their id, so they are looked up by register value.
+    servos: Vec<SimServo>,
+    active_servos: Vec<u8>,
+    last_info: HashMap<u8, ServoInfo>,
+    last_step: Instant,
+    loop_count: u32,
+    read_count: u32,
+}
+
+impl SimState {
+    fn advance(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_step);
+        self.last_step = now;
+        for servo in &mut self.servos {
+            servo.step(dt);
+        }
+        self.loop_count += 1;
+    }
+
+    fn servo_mut(&mut self, id: u8) -> Result<&mut SimServo> {
+        self.servos
+            .iter_mut()
+            .find(|servo| servo.id() == id)
+            .ok_or_else(|| Report::new(ZBotError::BusTimeout).wrap_err(format!("No response from servo {}", id)))
+    }
+}
+
+/// In-process bus that simulates a chain of Feetech servos at the register
+/// level. Dynamics advance with wall-clock time on every bus access.
+#[derive(Debug)]
+pub struct SimulatedBus {
+    state: Mutex<SimState>,
+    started: Instant,
+}
+
+impl SimulatedBus {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(SimState {
+                servos: Vec::new(),
+                active_servos: Vec::new(),
+                last_info: HashMap::new(),
+                last_step: now,
+                loop_count: 0,
+                read_count: 0,
+            }),
+            started: now,
+        }
+    }
+
+    /// Creates a bus with one STS3215 per id.
+    pub fn with_servos(ids: &[u8]) -> Self {
+        let bus = Self::new();
+        for &id in ids {
+            bus.insert_servo(SimServo::new(id, FeetechActuatorType::Sts3215));
+        }
+        bus
+    }
+
+    /// Connects a servo to the bus, replacing any servo with the same id.
+    pub fn insert_servo(&self, servo: SimServo) {
+        let mut state = self.state.lock().unwrap();
+        state.servos.retain(|s| s.id() != servo.id());
+        state.servos.push(servo);
+    }
+
+    /// Disconnects the servo currently answering to `id`.
+    pub fn remove_servo(&self, id: u8) -> Option<SimServo> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.servos.iter().position(|servo| servo.id() == id)?;
+        Some(state.servos.remove(index))
+    }
+
+    /// Runs `f` against the servo answering to `id`, e.g. to inject faults.
+    pub fn with_servo<R>(&self, id: u8, f: impl FnOnce(&mut SimServo) -> R) -> Option<R> {
+        let mut state = self.state.lock().unwrap();
+        state.servo_mut(id).ok().map(f)
+    }
+
+    fn elapsed_ms(&self) -> u32 {
+        self.started.elapsed().as_millis() as u32
+    }
+}
+
+impl Default for SimulatedBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeetechBus for SimulatedBus {
+    fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn deinit(&self) {}
+
+    fn read(&self, id: u8, address: u8, data: &mut [u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state.servo_mut(id)?.read(address, data)
+    }
+
+    fn write(&self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state.servo_mut(id)?.write(address, data)
+    }
+
+    fn set_active_servos(&self, active_servos: &ActiveServoList) -> Result<()> {
+        let len = (active_servos.len as usize).min(MAX_SERVOS);
+        let mut state = self.state.lock().unwrap();
+        state.active_servos = active_servos.servo_id[..len].to_vec();
+        Ok(())
+    }
+
+    fn get_info(&self, info_buffer: &mut ServoInfoBuffer) -> Result<()> {
+        let now_ms = self.elapsed_ms();
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+
+        let active_servos = state.active_servos.clone();
+        let mut read_count = 0;
+        for (slot, id) in active_servos.into_iter().enumerate() {
+            if let Ok(servo) = state.servo_mut(id) {
+                let info = servo.snapshot(now_ms);
+                state.last_info.insert(id, info);
+                info_buffer.servos[slot] = info;
+                read_count += 1;
+            } else if let Some(info) = state.last_info.get(&id) {
+                // A disconnected servo repeats its last reading, like libfeetech.
+                info_buffer.servos[slot] = *info;
+            }
+        }
+
+        state.read_count += read_count;
+        info_buffer.loop_count = state.loop_count;
+        info_buffer.read_count = read_count;
+        info_buffer.retry_count = 0;
+        info_buffer.fault_count = 0;
+        info_buffer.last_read_ms = now_ms;
+        Ok(())
+    }
+
+    fn broadcast_command(&self, command: &BroadcastCommand) -> Result<()> {
+        let length = command.data_length as usize;
+        if length < 2 {
+            return Ok(());
+        }
+
+        let address = command.data[0];
+        let entry_len = command.data[1] as usize;
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+
+        for entry in command.data[2..length].chunks_exact(entry_len + 1) {
+            // Broadcast writes are unacknowledged, so missing servos are ignored.
+            if let Ok(servo) = state.servo_mut(entry[0]) {
+                servo.write(address, &entry[1..])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn deg_to_raw(degrees: f32) -> f32 {
+    (degrees + 180.0) / 360.0 * 4096.0
+}
+
+fn sign_magnitude(raw: u16, sign_bit: u8) -> f32 {
+    let magnitude = (raw & ((1 << sign_bit) - 1)) as f32;
+    if raw & (1 << sign_bit) != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+fn to_sign_magnitude(value: f32, sign_bit: u8) -> u16 {
+    let magnitude = (value.abs() as u16).min((1 << sign_bit) - 1);
+    if value < 0.0 {
+        magnitude | (1 << sign_bit)
+    } else {
+        magnitude
+    }
+}
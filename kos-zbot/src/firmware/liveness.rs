@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// When a servo counts as responding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LivenessConfig {
+    /// Age of the last new reading, in ms, after which a servo goes offline.
+    #[serde(default = "default_stale_after_ms")]
+    pub stale_after_ms: u64,
+    /// Consecutive polls with a new reading needed to come back online.
+    #[serde(default = "default_online_after_reads")]
+    pub online_after_reads: u32,
+    /// Interval, in ms, at which configured servos missing from the bus are
+    /// pinged. 0 disables discovery.
+    #[serde(default = "default_discovery_interval_ms")]
+    pub discovery_interval_ms: u64,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            stale_after_ms: default_stale_after_ms(),
+            online_after_reads: default_online_after_reads(),
+            discovery_interval_ms: default_discovery_interval_ms(),
+        }
+    }
+}
+
+fn default_stale_after_ms() -> u64 {
+    100
+}
+
+fn default_online_after_reads() -> u32 {
+    2
+}
+
+fn default_discovery_interval_ms() -> u64 {
+    1000
+}
+
+/// Whether a servo is responding, as last decided by the supervisor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Liveness {
+    pub online: bool,
+    /// When the bus last brought a new reading, on the monotonic clock.
+    pub last_reading_at: Option<Instant>,
+}
+
+impl Liveness {
+    pub fn age(&self, now: Instant) -> Option<Duration> {
+        self.last_reading_at.map(|at| now.saturating_duration_since(at))
+    }
+}
+
+/// Liveness of one actuator with the age of its last reading, as served by
+/// `GET /readings`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReadingAge {
+    pub actuator_id: u32,
+    pub online: bool,
+    /// `None` if the actuator has not been read yet.
+    pub age_ms: Option<u64>,
+}
+
+/// A servo going online or offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LivenessEvent {
+    pub id: u8,
+    pub online: bool,
+    pub at: Instant,
+    /// Age of the last reading when the servo went offline.
+    pub age: Option<Duration>,
+    /// The servo came back online after having been offline.
+    pub reconnected: bool,
+}
+
+#[derive(Debug, Default)]
+struct ServoLiveness {
+    liveness: Liveness,
+    last_stamp: Option<u32>,
+    fresh_streak: u32,
+    has_been_online: bool,
+}
+
+/// Per-servo liveness, fed from the supervisor's poll loop. Bus read stamps
+/// are only compared for change; ages come from `Instant`, so wrapping bus
+/// clocks and wall clock steps do not matter.
+#[derive(Debug, Default)]
+pub struct LivenessMonitor {
+    config: LivenessConfig,
+    servos: HashMap<u8, ServoLiveness>,
+}
+
+impl LivenessMonitor {
+    pub fn config(&self) -> &LivenessConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: LivenessConfig) {
+        self.config = config;
+    }
+
+    pub fn get(&self, id: u8) -> Liveness {
+        self.servos.get(&id).map(|servo| servo.liveness).unwrap_or_default()
+    }
+
+    pub fn remove(&mut self, id: u8) {
+        self.servos.remove(&id);
+    }
+
+    pub fn rename(&mut self, id: u8, new_id: u8) {
+        if let Some(servo) = self.servos.remove(&id) {
+            self.servos.insert(new_id, servo);
+        }
+    }
+
+    /// Records one poll of servo `id`. `stamp` is the read timestamp from the
+    /// bus, or `None` if the servo was missing from the poll. Returns an
+    /// event when the servo went online or offline.
+    pub fn update(&mut self, id: u8, stamp: Option<u32>, now: Instant) -> Option<LivenessEvent> {
+        let stale_after = Duration::from_millis(self.config.stale_after_ms);
+        let online_after = self.config.online_after_reads.max(1);
+        let servo = self.servos.entry(id).or_default();
+
+        if stamp.is_some() && stamp != servo.last_stamp {
+            servo.last_stamp = stamp;
+            servo.liveness.last_reading_at = Some(now);
+            servo.fresh_streak = servo.fresh_streak.saturating_add(1);
+            if !servo.liveness.online && servo.fresh_streak >= online_after {
+                servo.liveness.online = true;
+                let reconnected = std::mem::replace(&mut servo.has_been_online, true);
+                return Some(LivenessEvent {
+                    id,
+                    online: true,
+                    at: now,
+                    age: None,
+                    reconnected,
+                });
+            }
+            return None;
+        }
+
+        let age = servo.liveness.age(now);
+        if age.is_none_or(|age| age > stale_after) {
+            servo.fresh_streak = 0;
+            if servo.liveness.online {
+                servo.liveness.online = false;
+                return Some(LivenessEvent {
+                    id,
+                    online: false,
+                    at: now,
+                    age,
+                    reconnected: false,
+                });
+            }
+        }
+        None
+    }
+}
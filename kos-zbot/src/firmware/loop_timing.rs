@@ -0,0 +1,128 @@
+use super::stats::Histogram;
+use crate::error::ZBotError;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Timing of a periodic loop since it started or last changed rate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoopTiming {
+    pub rate_hz: f64,
+    pub ticks: u64,
+    /// Ticks skipped because the loop fell a whole period behind.
+    pub missed_ticks: u64,
+    /// Iterations whose work took longer than one period.
+    pub overruns: u64,
+    pub last_period_us: u64,
+    /// Deviation of the tick-to-tick period from the target.
+    pub mean_jitter_us: f64,
+    pub max_jitter_us: u64,
+    pub jitter: Histogram,
+    pub max_work_us: u64,
+}
+
+/// Rate limits accepted for the command and poll loops.
+pub const MIN_LOOP_RATE_HZ: f64 = 1.0;
+pub const MAX_LOOP_RATE_HZ: f64 = 1000.0;
+
+pub fn loop_period(rate_hz: f64) -> Result<Duration> {
+    if !(MIN_LOOP_RATE_HZ..=MAX_LOOP_RATE_HZ).contains(&rate_hz) {
+        return Err(ZBotError::OutOfRange(format!(
+            "Loop rate {} Hz is not in {}..={} Hz",
+            rate_hz, MIN_LOOP_RATE_HZ, MAX_LOOP_RATE_HZ
+        ))
+        .into());
+    }
+    Ok(Duration::from_secs_f64(1.0 / rate_hz))
+}
+
+/// A `tokio::time::Interval` that skips missed ticks and records its timing
+/// in a shared `LoopTiming`.
+#[derive(Debug)]
+pub struct LoopTimer {
+    interval: Interval,
+    period: Duration,
+    last_tick: Option<Instant>,
+    work_started: Option<Instant>,
+    jitter_sum_us: f64,
+    timing: Arc<Mutex<LoopTiming>>,
+}
+
+impl LoopTimer {
+    pub fn new(period: Duration, timing: Arc<Mutex<LoopTiming>>) -> Self {
+        let mut timer = Self {
+            interval: Self::interval(period),
+            period,
+            last_tick: None,
+            work_started: None,
+            jitter_sum_us: 0.0,
+            timing,
+        };
+        timer.reset();
+        timer
+    }
+
+    fn interval(period: Duration) -> Interval {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        interval
+    }
+
+    fn reset(&mut self) {
+        self.last_tick = None;
+        self.work_started = None;
+        self.jitter_sum_us = 0.0;
+        *self.timing.lock().unwrap() = LoopTiming {
+            rate_hz: 1.0 / self.period.as_secs_f64(),
+            ..Default::default()
+        };
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Switches to a new period, restarting the statistics.
+    pub fn set_period(&mut self, period: Duration) {
+        if period != self.period {
+            self.period = period;
+            self.interval = Self::interval(period);
+            self.reset();
+        }
+    }
+
+    /// Waits for the next tick. The time since the previous tick returned is
+    /// counted as that iteration's work. Cancel safe, so it can be raced in
+    /// `tokio::select!`.
+    pub async fn tick(&mut self) {
+        if let Some(started) = self.work_started.take() {
+            let work = started.elapsed();
+            let mut timing = self.timing.lock().unwrap();
+            timing.max_work_us = timing.max_work_us.max(work.as_micros() as u64);
+            if work > self.period {
+                timing.overruns += 1;
+            }
+        }
+        self.interval.tick().await;
+        let tick = Instant::now();
+
+        let mut timing = self.timing.lock().unwrap();
+        timing.ticks += 1;
+        if let Some(last) = self.last_tick {
+            let actual = tick.saturating_duration_since(last);
+            let periods = (actual.as_secs_f64() / self.period.as_secs_f64()).round() as u64;
+            timing.missed_ticks += periods.saturating_sub(1);
+            let jitter = actual.abs_diff(self.period * periods.max(1) as u32);
+            timing.jitter.record(jitter);
+            let jitter_us = jitter.as_micros() as u64;
+            self.jitter_sum_us += jitter_us as f64;
+            timing.last_period_us = actual.as_micros() as u64;
+            timing.max_jitter_us = timing.max_jitter_us.max(jitter_us);
+            timing.mean_jitter_us = self.jitter_sum_us / (timing.ticks - 1) as f64;
+        }
+        self.last_tick = Some(tick);
+        self.work_started = Some(tick);
+    }
+}
@@ -1,5 +1,13 @@
 pub mod feetech;
+pub mod feetech_eeprom;
+pub mod feetech_scan;
+pub mod feetech_serial;
+pub mod feetech_sim;
 pub mod feetech_servo;
+pub mod liveness;
+pub mod loop_timing;
+pub mod protection;
+pub mod stats;
 
 mod cvitek;
 
@@ -0,0 +1,222 @@
+use super::feetech::FeetechActuatorInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Temperature drop below the derate threshold needed to leave derating.
+const TEMPERATURE_HYSTERESIS_C: f32 = 5.0;
+/// Longest gap integrated into the I²t estimate, so a stalled poll loop does
+/// not count as one long overcurrent.
+const MAX_INTEGRATION_STEP: Duration = Duration::from_millis(100);
+
+/// Thermal, overload and supply thresholds for one servo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProtectionLimits {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_derate_temperature_c")]
+    pub derate_temperature_c: f32,
+    #[serde(default = "default_trip_temperature_c")]
+    pub trip_temperature_c: f32,
+    /// Current the servo can carry indefinitely.
+    #[serde(default = "default_continuous_current_ma")]
+    pub continuous_current_ma: f32,
+    /// Accumulated I²t above the continuous current, in A²·s, at which the
+    /// servo trips. Derating starts at half of it.
+    #[serde(default = "default_i2t_limit_a2s")]
+    pub i2t_limit_a2s: f32,
+    /// Supply voltage below which commands are derated.
+    #[serde(default)]
+    pub sag_voltage_v: Option<f32>,
+    /// Supply voltage below which the servo trips.
+    #[serde(default)]
+    pub min_voltage_v: Option<f32>,
+    /// Torque limit, as a fraction of the maximum, while derated.
+    #[serde(default = "default_derate_torque_fraction")]
+    pub derate_torque_fraction: f32,
+    /// How long a trip condition must persist before torque is disabled.
+    #[serde(default = "default_trip_after_ms")]
+    pub trip_after_ms: u64,
+}
+
+impl Default for ProtectionLimits {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            derate_temperature_c: default_derate_temperature_c(),
+            trip_temperature_c: default_trip_temperature_c(),
+            continuous_current_ma: default_continuous_current_ma(),
+            i2t_limit_a2s: default_i2t_limit_a2s(),
+            sag_voltage_v: None,
+            min_voltage_v: None,
+            derate_torque_fraction: default_derate_torque_fraction(),
+            trip_after_ms: default_trip_after_ms(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_derate_temperature_c() -> f32 {
+    60.0
+}
+
+fn default_trip_temperature_c() -> f32 {
+    70.0
+}
+
+fn default_continuous_current_ma() -> f32 {
+    1500.0
+}
+
+fn default_i2t_limit_a2s() -> f32 {
+    20.0
+}
+
+fn default_derate_torque_fraction() -> f32 {
+    0.5
+}
+
+fn default_trip_after_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtectionLevel {
+    #[default]
+    Normal,
+    /// Torque limit lowered to `derate_torque_fraction`.
+    Derated,
+    /// Torque disabled. Cleared once every condition has recovered.
+    Tripped,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ServoProtection {
+    limits: ProtectionLimits,
+    level: ProtectionLevel,
+    i2t_a2s: f32,
+    last_update: Option<Instant>,
+    violation_since: Option<Instant>,
+    faults: Vec<String>,
+}
+
+/// Per-servo protection state, fed from the supervisor's telemetry loop.
+#[derive(Debug, Default)]
+pub struct ProtectionMonitor {
+    servos: HashMap<u8, ServoProtection>,
+}
+
+impl ProtectionMonitor {
+    pub fn set_limits(&mut self, id: u8, limits: ProtectionLimits) {
+        self.servos.entry(id).or_default().limits = limits;
+    }
+
+    pub fn remove(&mut self, id: u8) {
+        self.servos.remove(&id);
+    }
+
+    pub fn rename(&mut self, id: u8, new_id: u8) {
+        if let Some(state) = self.servos.remove(&id) {
+            self.servos.insert(new_id, state);
+        }
+    }
+
+    pub fn level(&self, id: u8) -> ProtectionLevel {
+        self.servos.get(&id).map(|state| state.level).unwrap_or_default()
+    }
+
+    pub fn limits(&self, id: u8) -> Option<&ProtectionLimits> {
+        self.servos.get(&id).map(|state| &state.limits)
+    }
+
+    /// Active protection conditions, in the same form as servo status faults.
+    pub fn faults(&self, id: u8) -> Vec<String> {
+        self.servos.get(&id).map(|state| state.faults.clone()).unwrap_or_default()
+    }
+
+    /// Evaluates fresh telemetry. Returns the new level when it changed.
+    pub fn update(&mut self, id: u8, info: &FeetechActuatorInfo, now: Instant) -> Option<ProtectionLevel> {
+        let state = self.servos.get_mut(&id)?;
+        if !state.limits.enabled || !info.online {
+            state.last_update = None;
+            return None;
+        }
+        let limits = &state.limits;
+
+        let dt = state
+            .last_update
+            .map_or(Duration::ZERO, |last| now.duration_since(last).min(MAX_INTEGRATION_STEP));
+        state.last_update = Some(now);
+        let current_a = info.current_ma / 1000.0;
+        let continuous_a = limits.continuous_current_ma / 1000.0;
+        state.i2t_a2s = (state.i2t_a2s + (current_a * current_a - continuous_a * continuous_a) * dt.as_secs_f32())
+            .max(0.0);
+
+        let recovering = state.level != ProtectionLevel::Normal;
+        let derate_temperature = if recovering {
+            limits.derate_temperature_c - TEMPERATURE_HYSTERESIS_C
+        } else {
+            limits.derate_temperature_c
+        };
+
+        let mut faults = Vec::new();
+        let mut worst = ProtectionLevel::Normal;
+        let mut flag = |level: ProtectionLevel, fault: String| {
+            worst = worst.max(level);
+            faults.push(fault);
+        };
+
+        if info.temperature_c >= limits.trip_temperature_c {
+            flag(ProtectionLevel::Tripped, format!("Overtemperature {:.0}°C", info.temperature_c));
+        } else if info.temperature_c >= derate_temperature {
+            flag(ProtectionLevel::Derated, format!("High temperature {:.0}°C", info.temperature_c));
+        }
+
+        if state.i2t_a2s >= limits.i2t_limit_a2s {
+            flag(ProtectionLevel::Tripped, format!("Overload I²t {:.1} A²s", state.i2t_a2s));
+        } else if state.i2t_a2s >= limits.i2t_limit_a2s / 2.0 {
+            flag(ProtectionLevel::Derated, format!("High load I²t {:.1} A²s", state.i2t_a2s));
+        }
+
+        if limits.min_voltage_v.is_some_and(|min| info.voltage_v < min) {
+            flag(ProtectionLevel::Tripped, format!("Undervoltage {:.1}V", info.voltage_v));
+        } else if limits.sag_voltage_v.is_some_and(|sag| info.voltage_v < sag) {
+            flag(ProtectionLevel::Derated, format!("Voltage sag {:.1}V", info.voltage_v));
+        }
+
+        let trip_after = Duration::from_millis(limits.trip_after_ms);
+        let level = match worst {
+            ProtectionLevel::Tripped => {
+                let since = *state.violation_since.get_or_insert(now);
+                if now.duration_since(since) >= trip_after {
+                    ProtectionLevel::Tripped
+                } else {
+                    state.level.max(ProtectionLevel::Derated)
+                }
+            }
+            // A tripped servo stays tripped until every condition has cleared.
+            ProtectionLevel::Derated => {
+                state.violation_since = None;
+                state.level.max(ProtectionLevel::Derated)
+            }
+            ProtectionLevel::Normal => {
+                state.violation_since = None;
+                ProtectionLevel::Normal
+            }
+        };
+
+        if level == ProtectionLevel::Tripped {
+            faults.push("Protection tripped, torque disabled".to_string());
+        }
+        state.faults = faults;
+
+        if level == state.level {
+            return None;
+        }
+        state.level = level;
+        Some(level)
+    }
+}
@@ -0,0 +1,387 @@
+use super::loop_timing::LoopTiming;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Upper bucket bounds, in µs, for latency and jitter histograms.
+pub const HISTOGRAM_BOUNDS_US: [u64; 10] = [50, 100, 250, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000];
+
+/// Fixed-bucket histogram of durations. `counts` has one more entry than
+/// `HISTOGRAM_BOUNDS_US`, for values above the last bound.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub counts: Vec<u64>,
+    pub sum_us: u64,
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; HISTOGRAM_BOUNDS_US.len() + 1],
+            sum_us: 0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        let value_us = value.as_micros() as u64;
+        let bucket = HISTOGRAM_BOUNDS_US
+            .iter()
+            .position(|&bound| value_us <= bound)
+            .unwrap_or(HISTOGRAM_BOUNDS_US.len());
+        self.counts[bucket] += 1;
+        self.sum_us += value_us;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServoStats {
+    /// Polls that brought a new reading from the servo.
+    pub fresh_reads: u64,
+    /// Polls in which the servo's reading had not changed.
+    pub stale_polls: u64,
+    /// Fresh readings as a fraction of all polls.
+    pub read_success_rate: f64,
+    pub last_reading_age_ms: Option<u64>,
+}
+
+/// Counters of the servo bus and the supervisor's poll loop since startup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SupervisorStats {
+    /// Totals reported by the bus driver.
+    pub bus_retries: u64,
+    pub bus_reads: u64,
+    pub bus_loops: u64,
+    pub bus_faults: u64,
+    /// Polls in which reading the bus failed outright.
+    pub poll_errors: u64,
+    pub broadcasts: u64,
+    pub broadcast_errors: u64,
+    pub broadcast_latency: Histogram,
+    pub poll: LoopTiming,
+    pub servos: BTreeMap<u8, ServoStats>,
+}
+
+#[derive(Debug, Default)]
+struct ServoReads {
+    last_stamp: Option<u32>,
+    last_fresh: Option<Instant>,
+    fresh_reads: u64,
+    stale_polls: u64,
+}
+
+/// Accumulates `SupervisorStats` from the poll loop and broadcasts.
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    stats: SupervisorStats,
+    servos: HashMap<u8, ServoReads>,
+}
+
+impl StatsCollector {
+    pub fn record_bus(&mut self, retries: u32, reads: u32, loops: u32, faults: u32) {
+        self.stats.bus_retries += retries as u64;
+        self.stats.bus_reads += reads as u64;
+        self.stats.bus_loops += loops as u64;
+        self.stats.bus_faults += faults as u64;
+    }
+
+    pub fn record_poll_error(&mut self) {
+        self.stats.poll_errors += 1;
+    }
+
+    /// Records one poll of servo `id`. `stamp` is the read timestamp from the
+    /// bus, or `None` if the servo was missing from the poll.
+    pub fn record_servo(&mut self, id: u8, stamp: Option<u32>, now: Instant) {
+        let servo = self.servos.entry(id).or_default();
+        if stamp.is_some() && stamp != servo.last_stamp {
+            servo.fresh_reads += 1;
+            servo.last_fresh = Some(now);
+            servo.last_stamp = stamp;
+        } else {
+            servo.stale_polls += 1;
+        }
+    }
+
+    pub fn record_broadcast(&mut self, latency: Duration, ok: bool) {
+        self.stats.broadcasts += 1;
+        if !ok {
+            self.stats.broadcast_errors += 1;
+        }
+        self.stats.broadcast_latency.record(latency);
+    }
+
+    pub fn remove_servo(&mut self, id: u8) {
+        self.servos.remove(&id);
+    }
+
+    pub fn rename_servo(&mut self, id: u8, new_id: u8) {
+        if let Some(reads) = self.servos.remove(&id) {
+            self.servos.insert(new_id, reads);
+        }
+    }
+
+    pub fn snapshot(&self, poll: LoopTiming, now: Instant) -> SupervisorStats {
+        let servos = self
+            .servos
+            .iter()
+            .map(|(&id, reads)| {
+                let polls = reads.fresh_reads + reads.stale_polls;
+                let stats = ServoStats {
+                    fresh_reads: reads.fresh_reads,
+                    stale_polls: reads.stale_polls,
+                    read_success_rate: if polls > 0 {
+                        reads.fresh_reads as f64 / polls as f64
+                    } else {
+                        0.0
+                    },
+                    last_reading_age_ms: reads
+                        .last_fresh
+                        .map(|at| now.saturating_duration_since(at).as_millis() as u64),
+                };
+                (id, stats)
+            })
+            .collect();
+        SupervisorStats {
+            poll,
+            servos,
+            ..self.stats.clone()
+        }
+    }
+}
+
+/// Writes metrics in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct PrometheusWriter {
+    out: String,
+}
+
+impl PrometheusWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// One sample with a single label.
+    fn labelled(
+        &mut self,
+        name: &str,
+        label: &str,
+        label_value: impl std::fmt::Display,
+        value: impl std::fmt::Display,
+    ) {
+        let label_value = escape_label_value(&label_value.to_string());
+        let _ = writeln!(self.out, "{}{{{}=\"{}\"}} {}", name, label, label_value, value);
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, "counter", help);
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.header(name, "gauge", help);
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    /// A metric with one sample per servo.
+    pub fn per_servo<T: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        values: impl IntoIterator<Item = (u8, T)>,
+    ) {
+        self.header(name, kind, help);
+        for (id, value) in values {
+            self.labelled(name, "servo", id, value);
+        }
+    }
+
+    /// A histogram, exported in seconds.
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.header(name, "histogram", help);
+        let mut cumulative = 0;
+        for (bound, count) in HISTOGRAM_BOUNDS_US.iter().zip(&histogram.counts) {
+            cumulative += count;
+            self.labelled(&format!("{}_bucket", name), "le", *bound as f64 / 1e6, cumulative);
+        }
+        self.labelled(&format!("{}_bucket", name), "le", "+Inf", histogram.count);
+        let _ = writeln!(self.out, "{}_sum {}", name, histogram.sum_us as f64 / 1e6);
+        let _ = writeln!(self.out, "{}_count {}", name, histogram.count);
+    }
+
+    /// Tick, overrun and jitter metrics of a loop, named `zbot_<prefix>_*`.
+    pub fn loop_timing(&mut self, prefix: &str, what: &str, timing: &LoopTiming) {
+        self.gauge(
+            &format!("zbot_{}_rate_hz", prefix),
+            &format!("Target rate of the {}", what),
+            timing.rate_hz,
+        );
+        self.counter(
+            &format!("zbot_{}_ticks_total", prefix),
+            &format!("Ticks of the {} since its rate was last set", what),
+            timing.ticks,
+        );
+        self.counter(
+            &format!("zbot_{}_missed_ticks_total", prefix),
+            &format!("Ticks of the {} skipped because it fell behind", what),
+            timing.missed_ticks,
+        );
+        self.counter(
+            &format!("zbot_{}_overruns_total", prefix),
+            &format!("Iterations of the {} that took longer than a period", what),
+            timing.overruns,
+        );
+        self.histogram(
+            &format!("zbot_{}_jitter_seconds", prefix),
+            &format!("Deviation of the {} period from its target", what),
+            &timing.jitter,
+        );
+    }
+
+    pub fn supervisor(&mut self, stats: &SupervisorStats) {
+        self.counter("zbot_bus_retries_total", "Servo read retries reported by the bus", stats.bus_retries);
+        self.counter("zbot_bus_reads_total", "Servo reads reported by the bus", stats.bus_reads);
+        self.counter("zbot_bus_loops_total", "Read loops reported by the bus", stats.bus_loops);
+        self.counter("zbot_bus_faults_total", "Faults reported by the bus", stats.bus_faults);
+        self.counter("zbot_poll_errors_total", "Polls in which reading the bus failed", stats.poll_errors);
+        self.counter("zbot_broadcasts_total", "Command broadcasts sent", stats.broadcasts);
+        self.counter("zbot_broadcast_errors_total", "Command broadcasts that failed", stats.broadcast_errors);
+        self.histogram(
+            "zbot_broadcast_latency_seconds",
+            "Time to hand a command broadcast to the bus",
+            &stats.broadcast_latency,
+        );
+        self.loop_timing("poll", "telemetry poll loop", &stats.poll);
+        self.per_servo(
+            "zbot_servo_read_success_ratio",
+            "gauge",
+            "Fraction of polls that brought a new reading",
+            stats.servos.iter().map(|(&id, servo)| (id, servo.read_success_rate)),
+        );
+        self.per_servo(
+            "zbot_servo_fresh_reads_total",
+            "counter",
+            "Polls that brought a new reading",
+            stats.servos.iter().map(|(&id, servo)| (id, servo.fresh_reads)),
+        );
+        self.per_servo(
+            "zbot_servo_stale_polls_total",
+            "counter",
+            "Polls in which the reading had not changed",
+            stats.servos.iter().map(|(&id, servo)| (id, servo.stale_polls)),
+        );
+        self.per_servo(
+            "zbot_servo_reading_age_seconds",
+            "gauge",
+            "Time since the last new reading",
+            stats.servos.iter().filter_map(|(&id, servo)| {
+                servo.last_reading_age_ms.map(|age| (id, age as f64 / 1000.0))
+            }),
+        );
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> SupervisorStats {
+        let mut latency = Histogram::default();
+        for us in [40, 80, 80, 3_000, 60_000] {
+            latency.record(Duration::from_micros(us));
+        }
+        let servos = BTreeMap::from([
+            (
+                3,
+                ServoStats {
+                    fresh_reads: 9,
+                    stale_polls: 1,
+                    read_success_rate: 0.9,
+                    last_reading_age_ms: Some(250),
+                },
+            ),
+            (7, ServoStats::default()),
+        ]);
+        SupervisorStats {
+            broadcasts: 5,
+            broadcast_latency: latency,
+            servos,
+            ..Default::default()
+        }
+    }
+
+    fn render(stats: &SupervisorStats) -> String {
+        let mut writer = PrometheusWriter::new();
+        writer.supervisor(stats);
+        writer.finish()
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = render(&stats());
+        let buckets: Vec<(&str, u64)> = metrics
+            .lines()
+            .filter_map(|line| line.strip_prefix("zbot_broadcast_latency_seconds_bucket{le=\""))
+            .map(|line| {
+                let (bound, count) = line.split_once("\"} ").unwrap();
+                (bound, count.parse().unwrap())
+            })
+            .collect();
+
+        assert_eq!(buckets.len(), HISTOGRAM_BOUNDS_US.len() + 1);
+        assert_eq!(buckets[0], ("0.00005", 1));
+        assert_eq!(buckets[1], ("0.0001", 3));
+        assert_eq!(buckets[6], ("0.005", 4));
+        assert_eq!(buckets[9], ("0.05", 4));
+        assert!(buckets.windows(2).all(|pair| pair[0].1 <= pair[1].1), "{:?}", buckets);
+        // The value above the last bound only shows up in +Inf.
+        assert_eq!(buckets[10], ("+Inf", 5));
+        assert!(metrics.contains("zbot_broadcast_latency_seconds_count 5\n"));
+        assert!(metrics.contains("zbot_broadcast_latency_seconds_sum 0.0632\n"));
+    }
+
+    #[test]
+    fn writes_one_sample_per_servo() {
+        let metrics = render(&stats());
+        assert!(metrics.contains("# TYPE zbot_servo_fresh_reads_total counter\n"));
+        assert!(metrics.contains("zbot_servo_fresh_reads_total{servo=\"3\"} 9\n"));
+        assert!(metrics.contains("zbot_servo_fresh_reads_total{servo=\"7\"} 0\n"));
+        assert!(metrics.contains("zbot_servo_read_success_ratio{servo=\"3\"} 0.9\n"));
+        // Servo 7 has never been read, so it has no age.
+        assert!(metrics.contains("zbot_servo_reading_age_seconds{servo=\"3\"} 0.25\n"));
+        assert!(!metrics.contains("zbot_servo_reading_age_seconds{servo=\"7\"}"));
+    }
+
+    #[test]
+    fn escapes_label_values_and_help() {
+        let mut writer = PrometheusWriter::new();
+        writer.gauge("zbot_test", "Two\nlines with a \\", 1.0);
+        writer.labelled("zbot_test", "joint", "left \"hip\"\\\n", 2);
+        assert_eq!(
+            writer.finish(),
+            "# HELP zbot_test Two\\nlines with a \\\\\n\
+             # TYPE zbot_test gauge\n\
+             zbot_test 1\n\
+             zbot_test{joint=\"left \\\"hip\\\"\\\\\\n\"} 2\n"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::error::prefixed;
+use eyre::Result;
+use serde::Serialize;
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Large enough for a trajectory of a few thousand waypoints.
+const MAX_REQUEST_LEN: usize = 1 << 20;
+
+/// A request to one of the runtime's plain HTTP endpoints.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: body.into(),
+        }
+    }
+
+    pub fn json<T: Serialize + ?Sized>(status: &'static str, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::text("500 Internal Server Error", format!("Failed to encode response: {}\n", e)),
+        }
+    }
+}
+
+/// Binds `listen` and answers every request with `handler`. Returns once the
+/// socket is bound; connections are handled in the background, one request
+/// each.
+pub(crate) async fn serve<F, Fut>(listen: &str, what: &str, handler: F) -> Result<()>
+where
+    F: Fn(Request) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(prefixed(format!("Failed to bind {} {}", what, listen)))?;
+    let what = what.to_string();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    debug!("{} accept failed: {}", what, e);
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            let what = what.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, handler)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => debug!("{} request from {} failed: {}", what, peer, e),
+                    Err(_) => debug!("{} request from {} timed out", what, peer),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle<F, Fut>(mut stream: TcpStream, handler: F) -> Result<()>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let response = match read_request(&mut stream).await? {
+        Some(request) => handler(request).await,
+        None => Response::text("400 Bad Request", "Malformed request\n"),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads the request line, headers and a `Content-Length` body. `None` if
+/// the request is malformed or too long.
+async fn read_request(stream: &mut TcpStream) -> Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let header_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() > MAX_REQUEST_LEN {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map_or(Ok(0), |(_, value)| value.trim().parse::<usize>());
+    let Ok(content_length) = content_length else {
+        return Ok(None);
+    };
+    if header_end + content_length > MAX_REQUEST_LEN {
+        return Ok(None);
+    }
+
+    let mut body = request.split_off(header_end);
+    let received = body.len();
+    if received < content_length {
+        body.resize(content_length, 0);
+        stream.read_exact(&mut body[received..]).await?;
+    }
+    body.truncate(content_length);
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or(target).to_string(),
+        body,
+    }))
+}
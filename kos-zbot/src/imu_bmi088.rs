@@ -1,3 +1,4 @@
+use crate::error::{i2c_error, prefixed, to_kos_error, ZBotError};
 use async_trait::async_trait;
 use eyre::Result;
 use imu::bmi088::Bmi088Reader;
@@ -6,7 +7,7 @@ use kos::{
         EulerAnglesResponse, ImuAdvancedValuesResponse, ImuValuesResponse, Operation,
         QuaternionResponse, IMU,
     },
-    kos_proto::common::{ActionResponse, Error, ErrorCode},
+    kos_proto::common::ActionResponse,
 };
 use nalgebra::{Matrix3, Rotation3, UnitQuaternion, Vector3};
 use std::sync::{Arc, Mutex};
@@ -15,6 +16,7 @@ use tracing::{debug, error, info, warn};
 
 pub struct ZBotBMI088 {
     imu: Bmi088Reader,
+    bus: String,
     /// Do fusion / correction logic in f32
     axis_correction: Rotation3<f32>,
     /// Complementary filter for orientation estimation.
@@ -28,12 +30,21 @@ impl ZBotBMI088 {
     /// If the accelerometer reading is zero after 0.1 seconds, the sensor is reinitialized.
     /// This process is repeated until the sensor is successfully initialized or 5 seconds have elapsed.
     pub fn new(i2c_bus: &str) -> Result<Self> {
+        Self::with_axis_correction(i2c_bus, None)
+    }
+
+    /// Like `new`, but maps sensor axes to robot axes with `axis_correction`
+    /// instead of the stock Zeroth-01 mounting.
+    pub fn with_axis_correction(
+        i2c_bus: &str,
+        axis_correction: Option<Rotation3<f32>>,
+    ) -> Result<Self> {
         info!("Initializing BMI088 on bus: {}", i2c_bus);
         let overall_start = Instant::now();
         loop {
-            let imu = Bmi088Reader::new(i2c_bus)?;
+            let imu = Bmi088Reader::new(i2c_bus).map_err(|e| i2c_error(i2c_bus, e))?;
             std::thread::sleep(Duration::from_millis(100));
-            let sample = imu.get_data()?;
+            let sample = imu.get_data().map_err(|e| i2c_error(i2c_bus, e))?;
             if sample.accelerometer.x != 0.0
                 || sample.accelerometer.y != 0.0
                 || sample.accelerometer.z != 0.0
@@ -52,15 +63,18 @@ impl ZBotBMI088 {
                 //   Robot Z = - (Sensor X)
                 //
                 // The corresponding rotation matrix is defined below.
-                let axis_correction = Rotation3::from_matrix(&Matrix3::new(
-                    0.0_f32, -1.0_f32, 0.0_f32, 0.0_f32, 0.0_f32, 1.0_f32, -1.0_f32, 0.0_f32,
-                    0.0_f32,
-                ));
+                let axis_correction = axis_correction.unwrap_or_else(|| {
+                    Rotation3::from_matrix(&Matrix3::new(
+                        0.0_f32, -1.0_f32, 0.0_f32, 0.0_f32, 0.0_f32, 1.0_f32, -1.0_f32, 0.0_f32,
+                        0.0_f32,
+                    ))
+                });
                 debug!("Using fixed axis correction: {:?}", axis_correction);
                 // Initialize the complementary filter
                 let comp_filter = ComplementaryFilter::new(0.90);
                 return Ok(Self {
                     imu,
+                    bus: i2c_bus.to_string(),
                     axis_correction,
                     comp_filter: Arc::new(Mutex::new(comp_filter)),
                 });
@@ -69,9 +83,10 @@ impl ZBotBMI088 {
             }
             if overall_start.elapsed() >= Duration::from_secs(5) {
                 warn!("BMI088 failed to initialize properly after 5 seconds; giving up");
-                return Err(eyre::eyre!(
-                    "BMI088 failed to initialize within 5 seconds; no non-zero acceleration reading"
-                ));
+                return Err(ZBotError::Hardware(
+                    "BMI088 failed to initialize within 5 seconds; no non-zero acceleration reading".to_string(),
+                )
+                .into());
             }
         }
     }
@@ -79,7 +94,7 @@ impl ZBotBMI088 {
     /// Helper function that reads sensor data, applies the fixed axis correction,
     /// and updates the complementary filter. Returns the current orientation quaternion.
     fn update_orientation(&self) -> Result<UnitQuaternion<f32>> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
 
         // Convert raw accelerometer data from g to m/s².
         let raw_accel = Vector3::new(
@@ -157,7 +172,7 @@ impl ComplementaryFilter {
 #[async_trait]
 impl IMU for ZBotBMI088 {
     async fn get_values(&self) -> Result<ImuValuesResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
 
         // Build raw sensor vectors in f32.
         let raw_accel = Vector3::new(
@@ -193,7 +208,7 @@ impl IMU for ZBotBMI088 {
     }
 
     async fn get_advanced_values(&self) -> Result<ImuAdvancedValuesResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
         let raw_lin_acc = Vector3::new(
             data.linear_acceleration.x * 9.81_f32,
             data.linear_acceleration.y * 9.81_f32,
@@ -245,8 +260,8 @@ impl IMU for ZBotBMI088 {
 
     async fn calibrate(&self) -> Result<Operation> {
         info!("Starting BMI088 calibration");
-        self.imu.reset()?;
-        let cal_status = self.imu.get_data()?.calibration_status;
+        self.imu.reset().map_err(|e| i2c_error(&self.bus, e))?;
+        let cal_status = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?.calibration_status;
         debug!(
             "Calibration Status - Sys: {}, Gyro: {}, Accel: {}, Mag: {}",
             (cal_status >> 6) & 0x03,
@@ -271,17 +286,14 @@ impl IMU for ZBotBMI088 {
         _max_vel: Option<f32>,
         _max_accel: Option<f32>,
     ) -> Result<ActionResponse> {
-        match self.imu.reset() {
+        match self.imu.reset().map_err(|e| i2c_error(&self.bus, e)) {
             Ok(_) => {
                 // BMI088 uses u8 for mode, 0x0C is equivalent to NDOF mode
-                if let Err(e) = self.imu.set_mode(0x0C) {
+                if let Err(e) = self.imu.set_mode(0x0C).map_err(|e| i2c_error(&self.bus, e)) {
                     error!("Failed to set BMI088 mode after reset: {}", e);
                     return Ok(ActionResponse {
                         success: false,
-                        error: Some(Error {
-                            code: ErrorCode::HardwareFailure as i32,
-                            message: format!("Failed to set IMU mode: {}", e),
-                        }),
+                        error: Some(to_kos_error(&prefixed("Failed to set IMU mode")(e))),
                     });
                 }
                 Ok(ActionResponse {
@@ -293,10 +305,7 @@ impl IMU for ZBotBMI088 {
                 error!("Failed to zero BMI088: {}", e);
                 Ok(ActionResponse {
                     success: false,
-                    error: Some(Error {
-                        code: ErrorCode::HardwareFailure as i32,
-                        message: format!("Failed to zero IMU: {}", e),
-                    }),
+                    error: Some(to_kos_error(&prefixed("Failed to zero IMU")(e))),
                 })
             }
         }
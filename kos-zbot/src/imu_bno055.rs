@@ -1,3 +1,4 @@
+use crate::error::{i2c_error, prefixed, to_kos_error};
 use async_trait::async_trait;
 use eyre::Result;
 use imu::bno055::{Bno055Reader, OperationMode};
@@ -6,27 +7,31 @@ use kos::{
         EulerAnglesResponse, ImuAdvancedValuesResponse, ImuValuesResponse, Operation,
         QuaternionResponse, IMU,
     },
-    kos_proto::common::{ActionResponse, Error, ErrorCode},
+    kos_proto::common::ActionResponse,
 };
 use std::time::Duration;
 use tracing::{debug, error, info};
 
 pub struct ZBotBNO055 {
     imu: Bno055Reader,
+    bus: String,
 }
 
 impl ZBotBNO055 {
     pub fn new(i2c_bus: &str) -> Result<Self> {
         info!("Initializing BNO055 on bus: {}", i2c_bus);
-        let imu = Bno055Reader::new(i2c_bus)?;
-        Ok(Self { imu })
+        let imu = Bno055Reader::new(i2c_bus).map_err(|e| i2c_error(i2c_bus, e))?;
+        Ok(Self {
+            imu,
+            bus: i2c_bus.to_string(),
+        })
     }
 }
 
 #[async_trait]
 impl IMU for ZBotBNO055 {
     async fn get_values(&self) -> Result<ImuValuesResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
         Ok(ImuValuesResponse {
             accel_x: data.accelerometer.x as f64,
             accel_y: data.accelerometer.y as f64,
@@ -42,7 +47,7 @@ impl IMU for ZBotBNO055 {
     }
 
     async fn get_advanced_values(&self) -> Result<ImuAdvancedValuesResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
         Ok(ImuAdvancedValuesResponse {
             lin_acc_x: Some(data.linear_acceleration.x as f64),
             lin_acc_y: Some(data.linear_acceleration.y as f64),
@@ -56,7 +61,7 @@ impl IMU for ZBotBNO055 {
     }
 
     async fn get_euler(&self) -> Result<EulerAnglesResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
         Ok(EulerAnglesResponse {
             roll: data.euler.roll as f64,
             pitch: data.euler.pitch as f64,
@@ -66,7 +71,7 @@ impl IMU for ZBotBNO055 {
     }
 
     async fn get_quaternion(&self) -> Result<QuaternionResponse> {
-        let data = self.imu.get_data()?;
+        let data = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?;
         Ok(QuaternionResponse {
             w: data.quaternion.w as f64,
             x: data.quaternion.x as f64,
@@ -78,8 +83,8 @@ impl IMU for ZBotBNO055 {
 
     async fn calibrate(&self) -> Result<Operation> {
         info!("Starting BNO055 calibration");
-        self.imu.reset()?;
-        let cal_status = self.imu.get_data()?.calibration_status;
+        self.imu.reset().map_err(|e| i2c_error(&self.bus, e))?;
+        let cal_status = self.imu.get_data().map_err(|e| i2c_error(&self.bus, e))?.calibration_status;
         debug!(
             "Calibration Status - Sys: {}, Gyro: {}, Accel: {}, Mag: {}",
             (cal_status >> 6) & 0x03,
@@ -104,16 +109,13 @@ impl IMU for ZBotBNO055 {
         _max_vel: Option<f32>,
         _max_accel: Option<f32>,
     ) -> Result<ActionResponse> {
-        match self.imu.reset() {
+        match self.imu.reset().map_err(|e| i2c_error(&self.bus, e)) {
             Ok(_) => {
-                if let Err(e) = self.imu.set_mode(OperationMode::Ndof) {
+                if let Err(e) = self.imu.set_mode(OperationMode::Ndof).map_err(|e| i2c_error(&self.bus, e)) {
                     error!("Failed to set BNO055 mode after reset: {}", e);
                     return Ok(ActionResponse {
                         success: false,
-                        error: Some(Error {
-                            code: ErrorCode::HardwareFailure as i32,
-                            message: format!("Failed to set IMU mode: {}", e),
-                        }),
+                        error: Some(to_kos_error(&prefixed("Failed to set IMU mode")(e))),
                     });
                 }
                 Ok(ActionResponse {
@@ -125,10 +127,7 @@ impl IMU for ZBotBNO055 {
                 error!("Failed to zero BNO055: {}", e);
                 Ok(ActionResponse {
                     success: false,
-                    error: Some(Error {
-                        code: ErrorCode::HardwareFailure as i32,
-                        message: format!("Failed to zero IMU: {}", e),
-                    }),
+                    error: Some(to_kos_error(&prefixed("Failed to zero IMU")(e))),
                 })
             }
         }
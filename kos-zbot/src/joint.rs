@@ -0,0 +1,159 @@
+use crate::config::{JointConfig, JointUnits};
+use serde::{Deserialize, Serialize};
+
+/// Maps between servo space (degrees at the servo horn, 0 at the servo's
+/// calibrated center) and joint space (the unit and zero of the robot model).
+///
+/// `servo = direction * gear_ratio * joint + zero_offset`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointDescriptor {
+    pub name: String,
+    pub servo_id: u8,
+    pub direction: f32,
+    pub zero_offset_deg: f32,
+    pub gear_ratio: f32,
+    pub units: JointUnits,
+    pub limits: JointLimits,
+}
+
+/// Software limits, in servo degrees, applied before commands reach the bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct JointLimits {
+    pub min_position_deg: Option<f32>,
+    pub max_position_deg: Option<f32>,
+    pub max_velocity_deg_per_s: Option<f32>,
+    pub max_step_deg: Option<f32>,
+}
+
+impl JointLimits {
+    pub fn from_config(joint: &JointConfig) -> Self {
+        Self {
+            min_position_deg: joint.min_position_deg,
+            max_position_deg: joint.max_position_deg,
+            max_velocity_deg_per_s: joint.max_velocity_deg_per_s,
+            max_step_deg: joint.max_step_deg,
+        }
+    }
+
+    pub fn clamp_position(&self, position_deg: f32) -> f32 {
+        let position_deg = self.min_position_deg.map_or(position_deg, |min| position_deg.max(min));
+        self.max_position_deg.map_or(position_deg, |max| position_deg.min(max))
+    }
+
+    /// Clamps a speed limit. The servo treats 0 as "no limit", so that maps to
+    /// the configured maximum too.
+    pub fn clamp_speed(&self, speed_deg_per_s: f32) -> f32 {
+        match self.max_velocity_deg_per_s {
+            Some(max) if speed_deg_per_s == 0.0 || speed_deg_per_s > max => max,
+            _ => speed_deg_per_s,
+        }
+    }
+
+    /// Moves from `from_deg` towards `to_deg` by at most `max_step_deg`.
+    pub fn limit_step(&self, from_deg: f32, to_deg: f32) -> f32 {
+        match self.max_step_deg {
+            Some(step) => to_deg.clamp(from_deg - step, from_deg + step),
+            None => to_deg,
+        }
+    }
+}
+
+impl JointDescriptor {
+    pub fn from_config(joint: &JointConfig, units: JointUnits) -> Self {
+        Self {
+            name: joint.name.clone(),
+            servo_id: joint.servo_id,
+            direction: joint.direction,
+            zero_offset_deg: joint.zero_offset_deg,
+            gear_ratio: joint.gear_ratio,
+            units,
+            limits: JointLimits::from_config(joint),
+        }
+    }
+
+    fn units_to_degrees(&self, value: f64) -> f32 {
+        match self.units {
+            JointUnits::Degrees => value as f32,
+            JointUnits::Radians => value.to_degrees() as f32,
+        }
+    }
+
+    fn degrees_to_units(&self, value: f32) -> f64 {
+        match self.units {
+            JointUnits::Degrees => value as f64,
+            JointUnits::Radians => (value as f64).to_radians(),
+        }
+    }
+
+    /// Joint position to servo position in degrees.
+    pub fn to_servo_position(&self, position: f64) -> f32 {
+        self.direction * self.gear_ratio * self.units_to_degrees(position) + self.zero_offset_deg
+    }
+
+    /// Servo position in degrees to joint position.
+    pub fn to_joint_position(&self, position_deg: f32) -> f64 {
+        self.degrees_to_units((position_deg - self.zero_offset_deg) / (self.direction * self.gear_ratio))
+    }
+
+    /// Joint velocity to servo velocity in deg/s.
+    pub fn to_servo_velocity(&self, velocity: f64) -> f32 {
+        self.direction * self.gear_ratio * self.units_to_degrees(velocity)
+    }
+
+    /// Servo velocity in deg/s to joint velocity.
+    pub fn to_joint_velocity(&self, velocity_deg_per_s: f32) -> f64 {
+        self.degrees_to_units(velocity_deg_per_s / (self.direction * self.gear_ratio))
+    }
+
+    /// Servo torque in N·m to joint torque in N·m.
+    pub fn to_joint_load(&self, torque_nm: f32) -> f64 {
+        (self.direction * self.gear_ratio * torque_nm) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> JointLimits {
+        JointLimits {
+            min_position_deg: Some(-90.0),
+            max_position_deg: Some(45.0),
+            max_velocity_deg_per_s: Some(200.0),
+            max_step_deg: Some(5.0),
+        }
+    }
+
+    #[test]
+    fn clamp_position_holds_each_bound() {
+        let limits = limits();
+        assert_eq!(limits.clamp_position(-120.0), -90.0);
+        assert_eq!(limits.clamp_position(10.0), 10.0);
+        assert_eq!(limits.clamp_position(60.0), 45.0);
+        assert_eq!(JointLimits::default().clamp_position(600.0), 600.0);
+        let min_only = JointLimits {
+            max_position_deg: None,
+            ..limits
+        };
+        assert_eq!(min_only.clamp_position(600.0), 600.0);
+        assert_eq!(min_only.clamp_position(-120.0), -90.0);
+    }
+
+    #[test]
+    fn clamp_speed_maps_unlimited_to_the_maximum() {
+        let limits = limits();
+        assert_eq!(limits.clamp_speed(100.0), 100.0);
+        assert_eq!(limits.clamp_speed(300.0), 200.0);
+        assert_eq!(limits.clamp_speed(0.0), 200.0);
+        assert_eq!(JointLimits::default().clamp_speed(0.0), 0.0);
+    }
+
+    #[test]
+    fn limit_step_moves_at_most_one_step() {
+        let limits = limits();
+        assert_eq!(limits.limit_step(0.0, 3.0), 3.0);
+        assert_eq!(limits.limit_step(0.0, 20.0), 5.0);
+        assert_eq!(limits.limit_step(10.0, -20.0), 5.0);
+        assert_eq!(JointLimits::default().limit_step(0.0, 20.0), 20.0);
+    }
+}
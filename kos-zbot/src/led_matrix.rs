@@ -1,25 +1,31 @@
+use crate::error::{i2c_error, prefixed, ZBotError};
 use eyre::Result;
 use i2cdev::core::I2CDevice;
 use i2cdev::linux::LinuxI2CDevice;
 use kos::{
     hal::LEDMatrix,
     kos_proto::{
-        common::{ActionResponse, Error, ErrorCode},
+        common::ActionResponse,
         led_matrix::*,
     },
 };
 use std::sync::Mutex;
 
-const DISPLAY_ADDR: u16 = 0x55;
+/// Default I2C address of the LED matrix controller.
+pub const DISPLAY_ADDR: u16 = 0x55;
 const HEADER: [u8; 2] = [0xA5, 0x5A];
 
 struct DisplayDriver<T: I2CDevice> {
     i2c: T,
+    bus: String,
 }
 
 impl<T: I2CDevice> DisplayDriver<T> {
-    pub fn new(i2c: T) -> Self {
-        Self { i2c }
+    pub fn new(i2c: T, bus: &str) -> Self {
+        Self {
+            i2c,
+            bus: bus.to_string(),
+        }
     }
 
     pub fn write_region(
@@ -29,19 +35,19 @@ impl<T: I2CDevice> DisplayDriver<T> {
         width: u8,
         height: u8,
         data: &[u8],
-    ) -> std::result::Result<(), Box<dyn std::error::Error>>
+    ) -> Result<()>
     where
-        T::Error: 'static,
+        T::Error: Send + Sync + 'static,
     {
         // Validate input parameters
         if x >= 32 || y >= 16 || width == 0 || height == 0 || x + width > 32 || y + height > 16 {
-            return Err("Invalid coordinates or dimensions".into());
+            return Err(ZBotError::OutOfRange("Invalid coordinates or dimensions".to_string()).into());
         }
 
         // Calculate expected data length: (w/2 + w%2) * h
         let expected_len = ((width as usize / 2 + width as usize % 2) * height as usize) as usize;
         if data.len() != expected_len {
-            return Err("Invalid data length".into());
+            return Err(ZBotError::InvalidArgument("Invalid data length".to_string()).into());
         }
 
         // Construct message
@@ -54,7 +60,7 @@ impl<T: I2CDevice> DisplayDriver<T> {
         message.extend_from_slice(data);
 
         // Send data over I2C
-        self.i2c.write(&message)?;
+        self.i2c.write(&message).map_err(|e| i2c_error(&self.bus, e))?;
         Ok(())
     }
 }
@@ -64,11 +70,10 @@ pub struct ZBotLEDMatrix {
 }
 
 impl ZBotLEDMatrix {
-    pub fn new(i2c_path: &str) -> Result<Self> {
-        let i2c = LinuxI2CDevice::new(i2c_path, DISPLAY_ADDR)
-            .map_err(|e| eyre::eyre!("Failed to open I2C device: {}", e))?;
+    pub fn new(i2c_path: &str, address: u16) -> Result<Self> {
+        let i2c = LinuxI2CDevice::new(i2c_path, address).map_err(|e| i2c_error(i2c_path, e))?;
         Ok(Self {
-            display: Mutex::new(DisplayDriver::new(i2c)),
+            display: Mutex::new(DisplayDriver::new(i2c, i2c_path)),
         })
     }
 }
@@ -91,10 +96,9 @@ impl LEDMatrix for ZBotLEDMatrix {
             // 32x16 pixels = 512 bits = 64 bytes
             return Ok(ActionResponse {
                 success: false,
-                error: Some(Error {
-                    code: ErrorCode::InvalidArgument as i32,
-                    message: "Buffer must be exactly 64 bytes (512 bits)".to_string(),
-                }),
+                error: Some(
+                    ZBotError::InvalidArgument("Buffer must be exactly 64 bytes (512 bits)".to_string()).into(),
+                ),
             });
         }
 
@@ -122,10 +126,10 @@ impl LEDMatrix for ZBotLEDMatrix {
         let mut display = self
             .display
             .lock()
-            .map_err(|_| eyre::eyre!("Failed to lock display"))?;
+            .map_err(|_| ZBotError::Hardware("Failed to lock display".to_string()))?;
         display
             .write_region(0, 0, 32, 16, &brightness_data)
-            .map_err(|e| eyre::eyre!("Failed to write to display: {}", e))?;
+            .map_err(prefixed("Failed to write to display"))?;
 
         Ok(ActionResponse {
             success: true,
@@ -143,10 +147,7 @@ impl LEDMatrix for ZBotLEDMatrix {
     ) -> Result<ActionResponse> {
         Ok(ActionResponse {
             success: false,
-            error: Some(Error {
-                code: ErrorCode::NotImplemented as i32,
-                message: "Color buffer not supported".to_string(),
-            }),
+            error: Some(ZBotError::Unsupported("Color buffer not supported".to_string()).into()),
         })
     }
 }
@@ -1,19 +1,44 @@
 mod actuator;
+mod api;
+mod calibration;
+mod calibration_store;
+mod config;
+mod error;
+mod estop;
 mod firmware;
+mod http;
 mod imu_bmi088;
 mod imu_bno055;
+mod joint;
 mod led_matrix;
+mod metrics;
 mod model;
+mod scan;
+mod sysid;
+mod trajectory;
+mod watchdog;
 
 pub use actuator::*;
+pub use api::*;
+pub use calibration::*;
+pub use calibration_store::*;
+pub use config::*;
+pub use error::*;
+pub use estop::*;
 pub use firmware::*;
+pub use joint::*;
 pub use led_matrix::*;
+pub use metrics::*;
 pub use model::*;
+pub use scan::*;
+pub use sysid::*;
+pub use trajectory::*;
+pub use watchdog::*;
 
 use crate::imu_bmi088::ZBotBMI088;
 use crate::imu_bno055::ZBotBNO055;
 use kos::{
-    hal::IMU,
+    hal::{Operation, IMU},
     kos_proto::actuator::actuator_service_server::ActuatorServiceServer,
     kos_proto::imu::imu_service_server::ImuServiceServer,
     kos_proto::inference::inference_service_server::InferenceServiceServer,
@@ -22,17 +47,33 @@ use kos::{
         ActuatorServiceImpl, IMUServiceImpl, InferenceServiceImpl, LEDMatrixServiceImpl,
         OperationsServiceImpl,
     },
+    telemetry::Telemetry,
     Platform, ServiceEnum,
 };
-use std::{future::Future, pin::Pin, sync::Arc};
+use nalgebra::{Matrix3, Rotation3};
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 use tonic::async_trait;
 use tracing::{error, info, warn};
 
-pub struct ZBotPlatform {}
+pub struct ZBotPlatform {
+    config: Option<RobotConfig>,
+    /// Set by `create_services`, so `shutdown` can release the servo bus.
+    actuator: std::sync::Mutex<Option<Arc<ZBotActuator>>>,
+}
 
 impl ZBotPlatform {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            config: None,
+            actuator: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn with_config(config: RobotConfig) -> Self {
+        Self {
+            config: Some(config),
+            actuator: std::sync::Mutex::new(None),
+        }
     }
 }
 
@@ -42,6 +83,18 @@ impl Default for ZBotPlatform {
     }
 }
 
+#[async_trait]
+impl OperationSink for OperationsServiceImpl {
+    async fn publish(&self, operation: Operation) {
+        let name = operation.name.clone();
+        // Names are "operations/<kind>/<uuid>".
+        let kind = name.split('/').nth(1).unwrap_or("calibrate_actuator");
+        if let Err(e) = self.create(name.clone(), operation, kind).await {
+            warn!("Failed to publish operation {}: {}", name, e);
+        }
+    }
+}
+
 #[async_trait]
 impl Platform for ZBotPlatform {
     fn name(&self) -> &'static str {
@@ -49,56 +102,64 @@ impl Platform for ZBotPlatform {
     }
 
     fn serial(&self) -> String {
-        "00000000".to_string()
+        match &self.config {
+            Some(config) => config.serial().to_string(),
+            None => RobotConfig::load().map_or_else(|_| "00000000".to_string(), |config| config.serial().to_string()),
+        }
     }
 
     fn initialize(&mut self, _operations_service: Arc<OperationsServiceImpl>) -> eyre::Result<()> {
+        if self.config.is_none() {
+            self.config = Some(RobotConfig::load()?);
+        }
         Ok(())
     }
 
     fn create_services<'a>(
         &'a self,
-        _operations_service: Arc<OperationsServiceImpl>,
+        operations_service: Arc<OperationsServiceImpl>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<ServiceEnum>>> + Send + 'a>> {
         Box::pin(async move {
-            let actuator_list = [
-                11, 12, 13, 14, 21, 22, 23, 24, 31, 32, 33, 34, 35, 36, 41, 42, 43, 44, 45, 46
-            ];
+            let config = match &self.config {
+                Some(config) => config.clone(),
+                None => RobotConfig::load()?,
+            };
 
-            let actuator = ZBotActuator::new(actuator_list.as_slice()).await?;
+            let bus = config.servo_bus.open(&config.servo_ids())?;
+            let actuator = Arc::new(
+                ZBotActuator::new(bus, &config)
+                    .await?
+                    .with_operations(operations_service),
+            );
+            *self.actuator.lock().unwrap() = Some(actuator.clone());
+
+            if let Some(listen) = &config.metrics.listen {
+                if let Err(e) = serve_metrics(listen, actuator.clone()).await {
+                    error!("{}. Continuing without the metrics endpoint.", e);
+                }
+            }
+            if let Some(listen) = &config.api.listen {
+                if let Err(e) = serve_api(listen, actuator.clone()).await {
+                    error!("{}. Continuing without the control API.", e);
+                }
+            }
+            if config.metrics.telemetry_interval_ms > 0 {
+                let interval = Duration::from_millis(config.metrics.telemetry_interval_ms);
+                tokio::spawn(publish_stats(actuator.clone(), interval));
+            }
 
             let mut services = vec![ServiceEnum::Actuator(ActuatorServiceServer::new(
-                ActuatorServiceImpl::new(Arc::new(actuator)),
+                ActuatorServiceImpl::new(actuator),
             ))];
 
-            // Try BNO055 first, fall back to BMI088.
-            // If both fail, we log the error and continue without the IMU service.
-            let imu_service = match ZBotBNO055::new("/dev/i2c-1") {
-                Ok(bno055) => {
-                    info!("Successfully initialized BNO055");
-                    Some(Arc::new(bno055) as Arc<dyn IMU>)
-                }
-                Err(e) => {
-                    warn!("BNO055 initialization failed ({}), attempting BMI088", e);
-                    match ZBotBMI088::new("/dev/i2c-1") {
-                        Ok(bmi088) => {
-                            info!("Successfully initialized BMI088");
-                            Some(Arc::new(bmi088) as Arc<dyn IMU>)
-                        }
-                        Err(e) => {
-                            error!("Failed to initialize BMI088: {}", e);
-                            None
-                        }
-                    }
-                }
-            };
+            let imu_service = config.imu.as_ref().and_then(create_imu);
 
             if let Some(imu) = imu_service {
                 services.push(ServiceEnum::Imu(ImuServiceServer::new(
                     IMUServiceImpl::new(imu),
                 )));
-            } else {
-                error!("Failed to initialize both BNO055 and BMI088 IMUs. Continuing without IMU sensor.");
+            } else if config.imu.is_some() {
+                error!("Failed to initialize the IMU. Continuing without IMU sensor.");
             }
 
             match ZBotInference::new() {
@@ -112,14 +173,16 @@ impl Platform for ZBotPlatform {
                 }
             }
 
-            match ZBotLEDMatrix::new("/dev/i2c-1") {
-                Ok(led_matrix) => {
-                    services.push(ServiceEnum::LEDMatrix(LedMatrixServiceServer::new(
-                        LEDMatrixServiceImpl::new(Arc::new(led_matrix)),
-                    )));
-                }
-                Err(e) => {
-                    error!("Failed to initialize LEDMatrix: {}", e);
+            if let Some(led_config) = &config.led_matrix {
+                match ZBotLEDMatrix::new(&led_config.bus, led_config.address) {
+                    Ok(led_matrix) => {
+                        services.push(ServiceEnum::LEDMatrix(LedMatrixServiceServer::new(
+                            LEDMatrixServiceImpl::new(Arc::new(led_matrix)),
+                        )));
+                    }
+                    Err(e) => {
+                        error!("Failed to initialize LEDMatrix: {}", e);
+                    }
                 }
             }
 
@@ -128,6 +191,61 @@ impl Platform for ZBotPlatform {
     }
 
     fn shutdown(&mut self) -> eyre::Result<()> {
+        if let Some(actuator) = self.actuator.lock().unwrap().take() {
+            tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(actuator.shutdown()));
+        }
         Ok(())
     }
 }
+
+/// Publishes the supervisor statistics to telemetry every `interval`.
+async fn publish_stats(actuator: Arc<ZBotActuator>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let Some(telemetry) = Telemetry::get().await else {
+            continue;
+        };
+        let stats = actuator.supervisor_stats().await;
+        if let Err(e) = telemetry.publish("actuator/stats", &stats).await {
+            warn!("Failed to publish supervisor stats: {}", e);
+        }
+    }
+}
+
+fn create_imu(config: &ImuConfig) -> Option<Arc<dyn IMU>> {
+    let bmi088 = || {
+        let axis_correction = config
+            .mounting_rotation
+            .map(|m| Rotation3::from_matrix_unchecked(Matrix3::from_fn(|i, j| m[i][j])));
+        match ZBotBMI088::with_axis_correction(&config.bus, axis_correction) {
+            Ok(bmi088) => {
+                info!("Successfully initialized BMI088");
+                Some(Arc::new(bmi088) as Arc<dyn IMU>)
+            }
+            Err(e) => {
+                error!("Failed to initialize BMI088: {}", e);
+                None
+            }
+        }
+    };
+
+    match config.imu_type {
+        ImuType::Bmi088 => bmi088(),
+        ImuType::Bno055 | ImuType::Auto => match ZBotBNO055::new(&config.bus) {
+            Ok(bno055) => {
+                info!("Successfully initialized BNO055");
+                Some(Arc::new(bno055) as Arc<dyn IMU>)
+            }
+            Err(e) if config.imu_type == ImuType::Auto => {
+                // Try BNO055 first, fall back to BMI088.
+                warn!("BNO055 initialization failed ({}), attempting BMI088", e);
+                bmi088()
+            }
+            Err(e) => {
+                error!("Failed to initialize BNO055: {}", e);
+                None
+            }
+        },
+    }
+}
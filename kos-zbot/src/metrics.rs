@@ -0,0 +1,28 @@
+use crate::actuator::ZBotActuator;
+use crate::http::{serve, Response};
+use eyre::Result;
+use std::sync::Arc;
+use tracing::info;
+
+/// Serves `ZBotActuator::prometheus_metrics` at `GET /metrics` on `listen`.
+/// Returns once the socket is bound; connections are handled in the
+/// background.
+pub async fn serve_metrics(listen: &str, actuator: Arc<ZBotActuator>) -> Result<()> {
+    serve(listen, "metrics endpoint", move |request| {
+        let actuator = actuator.clone();
+        async move {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/metrics") => Response {
+                    status: "200 OK",
+                    content_type: "text/plain; version=0.0.4",
+                    body: actuator.prometheus_metrics().await,
+                },
+                ("GET", _) => Response::text("404 Not Found", "Not found\n"),
+                _ => Response::text("405 Method Not Allowed", "Only GET is supported\n"),
+            }
+        }
+    })
+    .await?;
+    info!("Serving metrics on http://{}/metrics", listen);
+    Ok(())
+}
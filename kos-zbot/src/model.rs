@@ -1,8 +1,9 @@
 use crate::Model;
 use crate::Model as CvitekModel;
+use crate::error::{prefixed, to_kos_error, ZBotError};
 use eyre::Result;
 use kos::hal::Inference;
-use kos::kos_proto::common::{ActionResponse, Error, ErrorCode};
+use kos::kos_proto::common::ActionResponse;
 use kos::kos_proto::inference::{
     get_models_info_request::Filter, tensor::Dimension as ProtoDimension, ForwardResponse,
     GetModelsInfoRequest, GetModelsInfoResponse, LoadModelsResponse, ModelInfo, ModelMetadata,
@@ -109,10 +110,11 @@ impl ZBotInference {
                 models_dir.display(),
                 e
             );
-            return Err(eyre::eyre!(
+            return Err(ZBotError::Hardware(format!(
                 "Failed to create models directory: {}. Check permissions and disk space",
                 e
-            ));
+            ))
+            .into());
         }
 
         // Check available disk space (require at least model size + 10MB buffer)
@@ -124,7 +126,7 @@ impl ZBotInference {
                     required_space / (1024 * 1024),
                     space / (1024 * 1024)
                 );
-                return Err(eyre::eyre!("Insufficient disk space for model upload"));
+                return Err(ZBotError::Hardware("Insufficient disk space for model upload".to_string()).into());
             }
         }
 
@@ -139,10 +141,11 @@ impl ZBotInference {
             }
             Err(e) => {
                 error!("Failed to write model to {}: {}", model_path.display(), e);
-                Err(eyre::eyre!(
+                Err(ZBotError::Hardware(format!(
                     "Failed to save model file: {}. Check permissions and disk space",
                     e
                 ))
+                .into())
             }
         }
     }
@@ -151,22 +154,22 @@ impl ZBotInference {
         let models_dir = PathBuf::from(MODELS_DIR);
         if let Err(e) = std::fs::create_dir_all(&models_dir) {
             error!("Failed to create models directory: {}", e);
-            return Err(eyre::eyre!("Failed to create models directory: {}", e));
+            return Err(ZBotError::Hardware(format!("Failed to create models directory: {}", e)).into());
         }
 
         let metadata = self.available_models.read().await;
         let metadata_json = serde_json::to_string_pretty(&*metadata)
-            .map_err(|e| eyre::eyre!("Failed to serialize metadata: {}", e))?;
+            .map_err(|e| ZBotError::Hardware(format!("Failed to serialize metadata: {}", e)))?;
 
         let temp_path = Path::new(METADATA_FILE).with_extension("tmp");
 
         // Write to temporary file first
         fs::write(&temp_path, metadata_json)
-            .map_err(|e| eyre::eyre!("Failed to write metadata file: {}", e))?;
+            .map_err(|e| ZBotError::Hardware(format!("Failed to write metadata file: {}", e)))?;
 
         // Atomically rename temporary file to actual file
         fs::rename(&temp_path, METADATA_FILE)
-            .map_err(|e| eyre::eyre!("Failed to save metadata file: {}", e))?;
+            .map_err(|e| ZBotError::Hardware(format!("Failed to save metadata file: {}", e)))?;
 
         debug!("Successfully saved metadata to {}", METADATA_FILE);
         Ok(())
@@ -179,11 +182,11 @@ impl ZBotInference {
         }
 
         let metadata_str = fs::read_to_string(METADATA_FILE)
-            .map_err(|e| eyre::eyre!("Failed to read metadata file: {}", e))?;
+            .map_err(|e| ZBotError::ModelLoad(format!("Failed to read metadata file: {}", e)))?;
 
         let metadata: HashMap<String, SerializableModelMetadata> =
             serde_json::from_str(&metadata_str)
-                .map_err(|e| eyre::eyre!("Failed to parse metadata file: {}", e))?;
+                .map_err(|e| ZBotError::ModelLoad(format!("Failed to parse metadata file: {}", e)))?;
 
         let mut available = self.available_models.write().await;
         *available = metadata;
@@ -200,7 +203,7 @@ impl ZBotInference {
         let mut models = self.loaded_models.write().await;
         if models.contains_key(uid) {
             drop(model);
-            return Err(eyre::eyre!("Model {} already loaded", uid));
+            return Err(ZBotError::InvalidArgument(format!("Model {} already loaded", uid)).into());
         }
         models.insert(uid.to_string(), model);
         Ok(())
@@ -285,7 +288,7 @@ impl Inference for ZBotInference {
         let model_uid = model_path
             .file_stem()
             .and_then(|s| s.to_str())
-            .ok_or_else(|| eyre::eyre!("Invalid model path"))?
+            .ok_or_else(|| ZBotError::ModelLoad("Invalid model path".to_string()))?
             .to_string();
 
         self.register_model(model_uid.clone(), metadata.unwrap_or_default())
@@ -311,10 +314,7 @@ impl Inference for ZBotInference {
                 models: vec![],
                 result: Some(ActionResponse {
                     success: false,
-                    error: Some(Error {
-                        code: ErrorCode::InvalidArgument as i32,
-                        message: format!("Model {} not registered", uid),
-                    }),
+                    error: Some(ZBotError::InvalidArgument(format!("Model {} not registered", uid)).into()),
                 }),
             });
         }
@@ -370,10 +370,7 @@ impl Inference for ZBotInference {
                 models: vec![],
                 result: Some(ActionResponse {
                     success: false,
-                    error: Some(Error {
-                        code: ErrorCode::InvalidArgument as i32,
-                        message,
-                    }),
+                    error: Some(ZBotError::ModelLoad(message).into()),
                 }),
             });
         }
@@ -392,11 +389,7 @@ impl Inference for ZBotInference {
                 Err(e) => {
                     error!("Failed to fetch input info for {}: {}", uid, e);
                     // Return an error, or skip this model. Here we choose to fail immediately:
-                    return Err(eyre::eyre!(
-                        "Failed to get input info for model {}: {}",
-                        uid,
-                        e
-                    ));
+                    return Err(prefixed(format!("Failed to get input info for model {}", uid))(e));
                 }
             };
 
@@ -405,11 +398,7 @@ impl Inference for ZBotInference {
                 Ok(info) => info,
                 Err(e) => {
                     error!("Failed to fetch output info for {}: {}", uid, e);
-                    return Err(eyre::eyre!(
-                        "Failed to get output info for model {}: {}",
-                        uid,
-                        e
-                    ));
+                    return Err(prefixed(format!("Failed to get output info for model {}", uid))(e));
                 }
             };
 
@@ -498,10 +487,7 @@ impl Inference for ZBotInference {
             } else {
                 return Ok(ActionResponse {
                     success: false,
-                    error: Some(Error {
-                        code: ErrorCode::InvalidArgument as i32,
-                        message: format!("Model {} not found", uid),
-                    }),
+                    error: Some(ZBotError::InvalidArgument(format!("Model {} not found", uid)).into()),
                 });
             }
         }
@@ -525,10 +511,7 @@ impl Inference for ZBotInference {
                 if let Some(missing_uid) = uids.iter().find(|uid| !metadata.contains_key(*uid)) {
                     return Ok(GetModelsInfoResponse {
                         models: vec![],
-                        error: Some(Error {
-                            code: ErrorCode::InvalidArgument as i32,
-                            message: format!("Model {} not found", missing_uid),
-                        }),
+                        error: Some(ZBotError::InvalidArgument(format!("Model {} not found", missing_uid)).into()),
                     });
                 }
 
@@ -569,10 +552,7 @@ impl Inference for ZBotInference {
             None => {
                 return Ok(ForwardResponse {
                     outputs: HashMap::new(),
-                    error: Some(Error {
-                        code: ErrorCode::InvalidArgument as i32,
-                        message: format!("Model {} not found", model_uid),
-                    }),
+                    error: Some(ZBotError::InvalidArgument(format!("Model {} not found", model_uid)).into()),
                 });
             }
         };
@@ -589,10 +569,7 @@ impl Inference for ZBotInference {
                 error!("Inference failed: {:?}", e);
                 return Ok(ForwardResponse {
                     outputs: HashMap::new(),
-                    error: Some(Error {
-                        code: ErrorCode::HardwareFailure as i32,
-                        message: format!("Inference failed: {:?}", e),
-                    }),
+                    error: Some(to_kos_error(&prefixed("Inference failed")(e))),
                 });
             }
         };
@@ -603,10 +580,7 @@ impl Inference for ZBotInference {
                 error!("Failed to retrieve output tensor info: {}", e);
                 return Ok(ForwardResponse {
                     outputs: HashMap::new(),
-                    error: Some(Error {
-                        code: ErrorCode::HardwareFailure as i32,
-                        message: format!("Failed to retrieve output metadata: {}", e),
-                    }),
+                    error: Some(to_kos_error(&prefixed("Failed to retrieve output metadata")(e))),
                 });
             }
         };
@@ -0,0 +1,122 @@
+use crate::calibration::OperationSink;
+use crate::firmware::feetech::FeetechBus;
+use crate::firmware::feetech_scan::{scan_bus, ScanOptions, ScannedServo};
+use kos::hal::Operation;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Published as the metadata of a bus scan operation, JSON encoded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanProgress {
+    pub total: usize,
+    pub scanned: usize,
+    pub found: Vec<ScannedServo>,
+    pub finished: bool,
+    pub cancelled: bool,
+    pub message: Option<String>,
+}
+
+impl ScanProgress {
+    pub fn to_operation(&self, name: &str) -> Operation {
+        Operation {
+            name: name.to_string(),
+            metadata: Some(prost_types::Any {
+                type_url: "type.kscale.dev/kos_zbot.ScanProgress+json".to_string(),
+                value: serde_json::to_vec(self).unwrap_or_default(),
+            }),
+            done: self.finished,
+            result: None,
+        }
+    }
+}
+
+/// A bus scan in flight.
+#[derive(Debug, Clone)]
+pub struct ScanHandle {
+    pub name: String,
+    cancel: Arc<AtomicBool>,
+    progress: watch::Receiver<ScanProgress>,
+}
+
+impl ScanHandle {
+    pub fn progress(&self) -> ScanProgress {
+        self.progress.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ScanProgress> {
+        self.progress.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress.borrow().finished
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Scans the bus in the background, publishing progress to `operations`.
+pub fn start_scan(
+    bus: Arc<dyn FeetechBus>,
+    options: ScanOptions,
+    operations: Option<Arc<dyn OperationSink>>,
+) -> ScanHandle {
+    let name = format!("operations/scan_bus/{}", Uuid::new_v4());
+    let (sender, progress) = watch::channel(ScanProgress {
+        total: options.ids.len(),
+        ..Default::default()
+    });
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    if let Some(operations) = operations {
+        let name = name.clone();
+        let mut progress = progress.clone();
+        tokio::spawn(async move {
+            loop {
+                let operation = progress.borrow_and_update().to_operation(&name);
+                operations.publish(operation).await;
+                if progress.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    info!("Scanning {} servo ids ({})", options.ids.len(), name);
+    let scan_cancel = cancel.clone();
+    tokio::spawn(async move {
+        let progress = sender.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            scan_bus(&*bus, &options, &scan_cancel, |_, servo| {
+                progress.send_modify(|progress| {
+                    progress.scanned += 1;
+                    progress.found.extend(servo.cloned());
+                });
+            });
+            scan_cancel.load(Ordering::Relaxed)
+        })
+        .await;
+        sender.send_modify(|progress| {
+            progress.finished = true;
+            match result {
+                Ok(cancelled) => progress.cancelled = cancelled,
+                Err(e) => {
+                    error!("Bus scan failed: {}", e);
+                    progress.message = Some(e.to_string());
+                }
+            }
+        });
+        info!("Bus scan found {} servos", sender.borrow().found.len());
+    });
+
+    ScanHandle {
+        name,
+        cancel,
+        progress,
+    }
+}
@@ -0,0 +1,516 @@
+use crate::firmware::feetech::FeetechSupervisor;
+use crate::error::ZBotError;
+use crate::joint::JointLimits;
+use eyre::{Report, Result};
+use nalgebra::{Matrix2, Vector2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long the capture waits for a poll before giving up.
+const TELEMETRY_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longest dead time tried by the fits.
+const MAX_DELAY_S: f64 = 0.1;
+
+/// Position signal added to each joint's start position.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Excitation {
+    /// Steps by `amplitude_deg`, holds, then steps back.
+    Step { amplitude_deg: f32, hold_ms: u64 },
+    /// Alternates between `+amplitude_deg` and `-amplitude_deg`.
+    Square { amplitude_deg: f32, period_ms: u64, cycles: u32 },
+    /// Sine sweeping linearly from `start_hz` to `end_hz`.
+    Chirp {
+        amplitude_deg: f32,
+        start_hz: f64,
+        end_hz: f64,
+        duration_ms: u64,
+    },
+}
+
+impl Excitation {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(match *self {
+            Excitation::Step { hold_ms, .. } => 2 * hold_ms,
+            Excitation::Square { period_ms, cycles, .. } => period_ms * cycles as u64,
+            Excitation::Chirp { duration_ms, .. } => duration_ms,
+        })
+    }
+
+    /// Offset from the start position `time_s` into the excitation.
+    pub fn offset_deg(&self, time_s: f64) -> f32 {
+        match *self {
+            Excitation::Step { amplitude_deg, hold_ms } => {
+                if time_s < hold_ms as f64 / 1000.0 {
+                    amplitude_deg
+                } else {
+                    0.0
+                }
+            }
+            Excitation::Square {
+                amplitude_deg, period_ms, ..
+            } => {
+                let period_s = period_ms as f64 / 1000.0;
+                if time_s % period_s < period_s / 2.0 {
+                    amplitude_deg
+                } else {
+                    -amplitude_deg
+                }
+            }
+            Excitation::Chirp {
+                amplitude_deg,
+                start_hz,
+                end_hz,
+                duration_ms,
+            } => {
+                let duration_s = duration_ms as f64 / 1000.0;
+                let phase = 2.0 * PI * (start_hz * time_s + (end_hz - start_hz) * time_s * time_s / (2.0 * duration_s));
+                amplitude_deg * phase.sin() as f32
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SysidConfig {
+    pub excitation: Excitation,
+    /// Hold at the start position before the excitation and after returning
+    /// to it.
+    pub settle_ms: u64,
+    /// Speed sent with every command; 0 is the servo maximum. Capped by the
+    /// joint limits either way.
+    pub speed_deg_per_s: f32,
+}
+
+/// One fresh servo reading and the command in effect when it was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SysidSample {
+    /// Since the start of the excitation.
+    pub time_s: f64,
+    pub actuator_id: u8,
+    pub commanded_deg: f32,
+    pub position_deg: f32,
+    pub velocity_deg_per_s: f32,
+    pub load_percent: f32,
+    pub current_ma: f32,
+}
+
+/// `G(s) = gain * e^(-delay s) / (time_constant s + 1)`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FirstOrderFit {
+    pub gain: f64,
+    pub time_constant_s: f64,
+    pub delay_s: f64,
+    /// Of the simulated response against the measured position.
+    pub rmse_deg: f64,
+}
+
+/// `G(s) = gain * e^(-delay s) * wn² / (s² + 2 ζ wn s + wn²)`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SecondOrderFit {
+    pub gain: f64,
+    pub natural_frequency_hz: f64,
+    pub damping_ratio: f64,
+    pub delay_s: f64,
+    pub rmse_deg: f64,
+}
+
+/// Models fitted to one joint's commanded-to-measured position response.
+/// Fits are `None` when there are too few readings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SysidFit {
+    pub actuator_id: u8,
+    pub samples: usize,
+    pub sample_period_s: f64,
+    pub first_order: Option<FirstOrderFit>,
+    pub second_order: Option<SecondOrderFit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SysidRun {
+    pub config: SysidConfig,
+    pub samples: Vec<SysidSample>,
+    pub fits: Vec<SysidFit>,
+}
+
+impl SysidRun {
+    /// Writes the samples as CSV, one row per servo reading.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let write = || -> std::io::Result<()> {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            writeln!(
+                file,
+                "time_s,actuator_id,commanded_deg,position_deg,velocity_deg_per_s,load_percent,current_ma"
+            )?;
+            for sample in &self.samples {
+                writeln!(
+                    file,
+                    "{:.6},{},{:.3},{:.3},{:.3},{:.1},{:.1}",
+                    sample.time_s,
+                    sample.actuator_id,
+                    sample.commanded_deg,
+                    sample.position_deg,
+                    sample.velocity_deg_per_s,
+                    sample.load_percent,
+                    sample.current_ma
+                )?;
+            }
+            file.flush()
+        };
+        write().map_err(|e| ZBotError::Io(format!("Failed to write {}: {}", path.display(), e)).into())
+    }
+}
+
+/// Runs the excitation on every joint in `limits` at once, recording each
+/// fresh reading at the supervisor's poll rate, then returns the joints to
+/// where they started and restores their torque state.
+pub async fn run_sysid(
+    supervisor: &mut FeetechSupervisor,
+    limits: &BTreeMap<u8, JointLimits>,
+    config: &SysidConfig,
+) -> Result<SysidRun> {
+    let mut start = HashMap::new();
+    let mut torque_was_enabled = HashMap::new();
+    {
+        let servos = supervisor.servos.read().await;
+        for &id in limits.keys() {
+            let info = servos.get(&id).map(|servo| servo.info());
+            match info {
+                Some(info) if info.online => {
+                    start.insert(id, info.position_deg);
+                    torque_was_enabled.insert(id, info.torque_enabled);
+                }
+                _ => {
+                    return Err(Report::new(ZBotError::ServoNotFound(id)).wrap_err(format!("Servo {} is not online", id)))
+                }
+            }
+        }
+    }
+    let speeds: HashMap<u8, f32> = limits
+        .iter()
+        .map(|(&id, limits)| (id, limits.clamp_speed(config.speed_deg_per_s)))
+        .collect();
+    let settle = Duration::from_millis(config.settle_ms);
+
+    for &id in limits.keys() {
+        supervisor.enable_torque(id).await?;
+    }
+    supervisor.move_actuators(&start, &speeds).await?;
+    tokio::time::sleep(settle).await;
+
+    info!(
+        "Running {:?} on servos {:?} for {:.1} s",
+        config.excitation,
+        limits.keys().collect::<Vec<_>>(),
+        config.excitation.duration().as_secs_f64()
+    );
+    let captured = capture(supervisor, limits, config, &start, &speeds).await;
+
+    let returned = async {
+        supervisor.move_actuators(&start, &speeds).await?;
+        tokio::time::sleep(settle).await;
+        for (&id, &enabled) in &torque_was_enabled {
+            if !enabled {
+                supervisor.disable_torque(id).await?;
+            }
+        }
+        Ok::<_, eyre::Report>(())
+    }
+    .await;
+    if let Err(e) = &returned {
+        warn!("Failed to return servos to their start positions: {}", e);
+    }
+    let samples = captured?;
+    returned?;
+
+    let fits = limits.keys().map(|&id| fit_models(&samples, id)).collect();
+    Ok(SysidRun {
+        config: *config,
+        samples,
+        fits,
+    })
+}
+
+async fn capture(
+    supervisor: &mut FeetechSupervisor,
+    limits: &BTreeMap<u8, JointLimits>,
+    config: &SysidConfig,
+    start: &HashMap<u8, f32>,
+    speeds: &HashMap<u8, f32>,
+) -> Result<Vec<SysidSample>> {
+    let notify = supervisor.telemetry_notify();
+    let duration = config.excitation.duration();
+    let mut commanded = start.clone();
+    let mut last_read_ms = HashMap::new();
+    let mut samples = Vec::new();
+    let started = Instant::now();
+    loop {
+        tokio::time::timeout(TELEMETRY_TIMEOUT, notify.notified())
+            .await
+            .map_err(|_| {
+                Report::new(ZBotError::BusTimeout)
+                    .wrap_err(format!("No telemetry for {} ms", TELEMETRY_TIMEOUT.as_millis()))
+            })?;
+        let elapsed = started.elapsed();
+        {
+            let servos = supervisor.servos.read().await;
+            for &id in limits.keys() {
+                let Some(info) = servos.get(&id).map(|servo| servo.info()) else {
+                    return Err(ZBotError::ServoNotFound(id).into());
+                };
+                if !info.online {
+                    return Err(ZBotError::Hardware(format!("Servo {} went offline", id)).into());
+                }
+                // The same reading is applied again when a poll misses it.
+                if last_read_ms.insert(id, info.last_read_ms) == Some(info.last_read_ms) {
+                    continue;
+                }
+                let read_at = info.last_reading_at.map_or(elapsed, |at| at.saturating_duration_since(started));
+                samples.push(SysidSample {
+                    time_s: read_at.as_secs_f64(),
+                    actuator_id: id,
+                    commanded_deg: commanded[&id],
+                    position_deg: info.position_deg,
+                    velocity_deg_per_s: info.speed_deg_per_s,
+                    load_percent: info.load_percent,
+                    current_ma: info.current_ma,
+                });
+            }
+        }
+        if elapsed >= duration {
+            return Ok(samples);
+        }
+        let offset = config.excitation.offset_deg(elapsed.as_secs_f64());
+        for (&id, limits) in limits {
+            commanded.insert(id, limits.clamp_position(start[&id] + offset));
+        }
+        supervisor.move_actuators(&commanded, speeds).await?;
+    }
+}
+
+/// Minimizes `cost` with the Nelder-Mead simplex, starting from `start`
+/// with steps of `step` along each axis.
+fn minimize<const N: usize>(cost: impl Fn(&[f64; N]) -> f64, start: [f64; N], step: f64) -> ([f64; N], f64) {
+    const ITERATIONS: usize = 500;
+    const TOLERANCE: f64 = 1e-9;
+
+    let mut simplex: Vec<([f64; N], f64)> = (0..=N)
+        .map(|axis| {
+            let mut point = start;
+            if axis < N {
+                point[axis] += step;
+            }
+            (point, cost(&point))
+        })
+        .collect();
+    let along = |from: &[f64; N], to: &[f64; N], t: f64| -> [f64; N] {
+        std::array::from_fn(|i| from[i] + t * (to[i] - from[i]))
+    };
+
+    for _ in 0..ITERATIONS {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        if simplex[N].1 - simplex[0].1 < TOLERANCE {
+            break;
+        }
+        let centroid: [f64; N] =
+            std::array::from_fn(|i| simplex[..N].iter().map(|(point, _)| point[i]).sum::<f64>() / N as f64);
+        let worst = simplex[N];
+
+        let reflected = along(&centroid, &worst.0, -1.0);
+        let reflected_cost = cost(&reflected);
+        if reflected_cost < simplex[0].1 {
+            let expanded = along(&centroid, &worst.0, -2.0);
+            let expanded_cost = cost(&expanded);
+            simplex[N] = if expanded_cost < reflected_cost {
+                (expanded, expanded_cost)
+            } else {
+                (reflected, reflected_cost)
+            };
+        } else if reflected_cost < simplex[N - 1].1 {
+            simplex[N] = (reflected, reflected_cost);
+        } else {
+            let contracted = along(&centroid, &worst.0, 0.5);
+            let contracted_cost = cost(&contracted);
+            if contracted_cost < worst.1 {
+                simplex[N] = (contracted, contracted_cost);
+            } else {
+                let best = simplex[0].0;
+                for vertex in &mut simplex[1..] {
+                    vertex.0 = along(&best, &vertex.0, 0.5);
+                    vertex.1 = cost(&vertex.0);
+                }
+            }
+        }
+    }
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    simplex[0]
+}
+
+/// RMSE between `y` and the response of `step_model` to `u` delayed by
+/// `delay` samples, starting from rest. `step_model` advances the state
+/// by one sample and returns the new position.
+fn simulation_error<const S: usize>(
+    u: &[f64],
+    y: &[f64],
+    delay: usize,
+    mut step_model: impl FnMut(&mut [f64; S], f64) -> f64,
+) -> f64 {
+    let mut state = [0.0; S];
+    let mut position = 0.0;
+    let mut sum = 0.0;
+    for k in 0..y.len() {
+        sum += (position - y[k]).powi(2);
+        position = step_model(&mut state, u[k.saturating_sub(delay)]);
+    }
+    let rmse = (sum / y.len() as f64).sqrt();
+    if rmse.is_finite() {
+        rmse
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Fits `G(s) = gain * e^(-delay s) / (tau s + 1)`, discretized with a zero
+/// order hold. Parameters are `[gain, ln tau]`.
+fn fit_first_order(u: &[f64], y: &[f64], dt: f64) -> Option<FirstOrderFit> {
+    let max_delay = (MAX_DELAY_S / dt).round() as usize;
+    (0..=max_delay)
+        .map(|delay| {
+            let cost = |&[gain, log_tau]: &[f64; 2]| {
+                let a = (-dt / log_tau.exp()).exp();
+                simulation_error(u, y, delay, |[x], input| {
+                    *x = a * *x + gain * (1.0 - a) * input;
+                    *x
+                })
+            };
+            let ([gain, log_tau], rmse) = minimize(cost, [1.0, 0.05f64.ln()], 0.5);
+            FirstOrderFit {
+                gain,
+                time_constant_s: log_tau.exp(),
+                delay_s: delay as f64 * dt,
+                rmse_deg: rmse,
+            }
+        })
+        .filter(|fit| fit.rmse_deg.is_finite())
+        .min_by(|a, b| a.rmse_deg.total_cmp(&b.rmse_deg))
+}
+
+/// Fits `G(s) = gain * e^(-delay s) * wn² / (s² + 2 ζ wn s + wn²)`,
+/// discretized with a zero order hold. Parameters are
+/// `[gain, ln wn, ln ζ]`.
+fn fit_second_order(u: &[f64], y: &[f64], dt: f64, first_order: Option<&FirstOrderFit>) -> Option<SecondOrderFit> {
+    // A critically damped start with the first-order bandwidth.
+    let wn = first_order.map_or(20.0, |fit| 1.0 / fit.time_constant_s.max(dt));
+    let max_delay = (MAX_DELAY_S / dt).round() as usize;
+    (0..=max_delay)
+        .filter_map(|delay| {
+            let cost = |&[gain, log_wn, log_zeta]: &[f64; 3]| {
+                let (wn, zeta) = (log_wn.exp(), log_zeta.exp());
+                // Faster poles are not observable; a first-order response
+                // shows up as a large damping ratio instead.
+                if wn > PI / dt {
+                    return f64::INFINITY;
+                }
+                let a = Matrix2::new(0.0, 1.0, -wn * wn, -2.0 * zeta * wn);
+                let b = Vector2::new(0.0, gain * wn * wn);
+                let ad = (a * dt).exp();
+                let Some(bd) = a.try_inverse().map(|inverse| inverse * (ad - Matrix2::identity()) * b) else {
+                    return f64::INFINITY;
+                };
+                simulation_error(u, y, delay, |state, input| {
+                    let next = ad * Vector2::from(*state) + bd * input;
+                    *state = next.into();
+                    next[0]
+                })
+            };
+            let ([gain, log_wn, log_zeta], rmse) = minimize(cost, [1.0, wn.ln(), 0.0], 0.5);
+            rmse.is_finite().then(|| SecondOrderFit {
+                gain,
+                natural_frequency_hz: log_wn.exp() / (2.0 * PI),
+                damping_ratio: log_zeta.exp(),
+                delay_s: delay as f64 * dt,
+                rmse_deg: rmse,
+            })
+        })
+        .min_by(|a, b| a.rmse_deg.total_cmp(&b.rmse_deg))
+}
+
+/// Fits first- and second-order models to the samples of `id` by
+/// minimizing the error of the simulated response, in deviation from where
+/// the joint started. The samples are assumed evenly spaced.
+pub fn fit_models(samples: &[SysidSample], id: u8) -> SysidFit {
+    let samples: Vec<&SysidSample> = samples.iter().filter(|sample| sample.actuator_id == id).collect();
+    let mut fit = SysidFit {
+        actuator_id: id,
+        samples: samples.len(),
+        sample_period_s: 0.0,
+        first_order: None,
+        second_order: None,
+    };
+    if samples.len() < 10 {
+        return fit;
+    }
+    let dt = (samples[samples.len() - 1].time_s - samples[0].time_s) / (samples.len() - 1) as f64;
+    fit.sample_period_s = dt;
+    if dt <= 0.0 {
+        return fit;
+    }
+    let u: Vec<f64> = samples
+        .iter()
+        .map(|sample| (sample.commanded_deg - samples[0].commanded_deg) as f64)
+        .collect();
+    let y: Vec<f64> = samples
+        .iter()
+        .map(|sample| (sample.position_deg - samples[0].position_deg) as f64)
+        .collect();
+
+    fit.first_order = fit_first_order(&u, &y, dt);
+    fit.second_order = fit_second_order(&u, &y, dt, fit.first_order.as_ref());
+    fit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_order_fit_recovers_a_known_plant() {
+        let (gain, time_constant_s, delay, dt) = (0.9, 0.1, 3, 0.01f64);
+        let a = (-dt / time_constant_s).exp();
+        let commanded = |k: usize| if k >= 20 { 30.0 } else { 0.0 };
+        let mut position = 0.0;
+        let samples: Vec<SysidSample> = (0..300)
+            .map(|k| {
+                let sample = SysidSample {
+                    time_s: k as f64 * dt,
+                    actuator_id: 1,
+                    commanded_deg: commanded(k) as f32,
+                    position_deg: position as f32,
+                    velocity_deg_per_s: 0.0,
+                    load_percent: 0.0,
+                    current_ma: 0.0,
+                };
+                position = a * position + gain * (1.0 - a) * commanded(k.saturating_sub(delay));
+                sample
+            })
+            .collect();
+
+        let fit = fit_models(&samples, 1);
+        assert_eq!(fit.samples, 300);
+        assert!((fit.sample_period_s - dt).abs() < 1e-9);
+        let first_order = fit.first_order.expect("first-order fit");
+        assert!((first_order.gain - gain).abs() < 0.01, "{:?}", first_order);
+        assert!((first_order.time_constant_s - time_constant_s).abs() < 0.005, "{:?}", first_order);
+        assert!((first_order.delay_s - delay as f64 * dt).abs() < dt / 2.0, "{:?}", first_order);
+        assert!(first_order.rmse_deg < 0.05, "{:?}", first_order);
+        assert!(fit.second_order.is_some());
+        // Another servo's samples are not fitted.
+        assert_eq!(fit_models(&samples, 2).first_order, None);
+    }
+}
@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Linear,
+    /// Cubic Hermite through every waypoint. Waypoint velocities are used
+    /// where given, otherwise estimated from the neighbouring waypoints.
+    #[default]
+    Cubic,
+    /// Comes to rest at every waypoint.
+    MinimumJerk,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrajectoryMode {
+    /// Preempt whatever is playing, starting from where the joints are now.
+    #[default]
+    Replace,
+    /// Start once everything already queued has played.
+    Append,
+}
+
+/// Joint-space targets at a time relative to the start of the trajectory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub time_from_start_s: f64,
+    /// Positions by actuator id, in the configured joint units.
+    pub positions: HashMap<u32, f64>,
+    /// Optional velocities by actuator id, in joint units per second. Only
+    /// used by cubic interpolation.
+    #[serde(default)]
+    pub velocities: HashMap<u32, f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Trajectory {
+    pub waypoints: Vec<Waypoint>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub mode: TrajectoryMode,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrajectoryStatus {
+    pub active: bool,
+    /// Actuators still moving along the queue.
+    pub actuator_ids: Vec<u32>,
+    /// Until the last queued waypoint is reached.
+    pub remaining_s: f64,
+    pub queued_waypoints: usize,
+    pub accepted: u64,
+    pub completed: u64,
+    pub preempted: u64,
+}
+
+/// A servo-space waypoint of one servo.
+#[derive(Debug, Clone, Copy)]
+struct Knot {
+    t: f64,
+    position: f32,
+    velocity: f32,
+    /// The client gave `velocity`, rather than it being estimated.
+    given: bool,
+    /// How the segment ending at this knot is interpolated.
+    interpolation: Interpolation,
+}
+
+/// Servo-space setpoint to be queued: time from start, position and optional
+/// velocity, all in servo degrees.
+pub type ServoWaypoint = (f64, HashMap<u8, (f32, Option<f32>)>);
+
+/// Queued trajectories in servo space, sampled by the command loop.
+#[derive(Debug)]
+pub struct TrajectoryQueue {
+    origin: Instant,
+    knots: HashMap<u8, VecDeque<Knot>>,
+    accepted: u64,
+    completed: u64,
+    preempted: u64,
+}
+
+impl Default for TrajectoryQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrajectoryQueue {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            knots: HashMap::new(),
+            accepted: 0,
+            completed: 0,
+            preempted: 0,
+        }
+    }
+
+    fn seconds(&self, now: Instant) -> f64 {
+        now.saturating_duration_since(self.origin).as_secs_f64()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.knots.is_empty()
+    }
+
+    /// Queues servo-space waypoints. `start` gives the current position of
+    /// servos that are not already moving along the queue.
+    pub fn submit(
+        &mut self,
+        now: Instant,
+        waypoints: &[ServoWaypoint],
+        interpolation: Interpolation,
+        mode: TrajectoryMode,
+        start: impl Fn(u8) -> Option<f32>,
+    ) {
+        let now_s = self.seconds(now);
+        let ids: BTreeSet<u8> = waypoints.iter().flat_map(|(_, targets)| targets.keys().copied()).collect();
+
+        let t0 = match mode {
+            TrajectoryMode::Replace => {
+                let current: HashMap<u8, (f32, f32)> = self
+                    .knots
+                    .iter()
+                    .map(|(&id, knots)| (id, sample(knots, now_s)))
+                    .collect();
+                if self.is_active() {
+                    self.preempted += 1;
+                }
+                self.knots.clear();
+                for &id in &ids {
+                    let (position, velocity) = match current.get(&id) {
+                        Some(&sampled) => sampled,
+                        None => match start(id) {
+                            Some(position) => (position, 0.0),
+                            None => continue,
+                        },
+                    };
+                    let knot = Knot {
+                        t: now_s,
+                        position,
+                        velocity,
+                        given: true,
+                        interpolation,
+                    };
+                    self.knots.insert(id, VecDeque::from([knot]));
+                }
+                now_s
+            }
+            TrajectoryMode::Append => {
+                let end = self
+                    .knots
+                    .values()
+                    .filter_map(|knots| knots.back().map(|knot| knot.t))
+                    .fold(now_s, f64::max);
+                // Servos that finish early hold until the new part starts.
+                for knots in self.knots.values_mut() {
+                    if let Some(&last) = knots.back() {
+                        if last.t < end {
+                            knots.push_back(Knot {
+                                t: end,
+                                velocity: 0.0,
+                                given: true,
+                                ..last
+                            });
+                        }
+                    }
+                }
+                for &id in &ids {
+                    if self.knots.contains_key(&id) {
+                        continue;
+                    }
+                    if let Some(position) = start(id) {
+                        let knot = Knot {
+                            t: end,
+                            position,
+                            velocity: 0.0,
+                            given: true,
+                            interpolation,
+                        };
+                        self.knots.insert(id, VecDeque::from([knot]));
+                    }
+                }
+                end
+            }
+        };
+
+        for &id in &ids {
+            let Some(knots) = self.knots.get_mut(&id) else {
+                continue;
+            };
+            let first = knots.len();
+            for (time_s, targets) in waypoints {
+                if let Some(&(position, velocity)) = targets.get(&id) {
+                    knots.push_back(Knot {
+                        t: t0 + time_s,
+                        position,
+                        velocity: velocity.unwrap_or(0.0),
+                        given: velocity.is_some(),
+                        interpolation,
+                    });
+                }
+            }
+            estimate_velocities(knots, first);
+        }
+        self.accepted += 1;
+    }
+
+    /// Setpoints for `now`, one per servo on the queue. Servos whose last
+    /// waypoint has been reached get it once more and leave the queue.
+    pub fn advance(&mut self, now: Instant) -> HashMap<u8, f32> {
+        let now_s = self.seconds(now);
+        let was_active = self.is_active();
+        let mut setpoints = HashMap::new();
+        self.knots.retain(|&id, knots| {
+            while knots.len() > 1 && knots[1].t <= now_s {
+                knots.pop_front();
+            }
+            setpoints.insert(id, sample(knots, now_s).0);
+            knots.len() > 1
+        });
+        if was_active && !self.is_active() {
+            self.completed += 1;
+        }
+        setpoints
+    }
+
+    /// Takes a single servo off the queue.
+    pub fn remove(&mut self, id: u8) {
+        if self.knots.remove(&id).is_some() && self.knots.is_empty() {
+            self.preempted += 1;
+        }
+    }
+
+    /// Drops everything queued. Returns true if something was playing.
+    pub fn clear(&mut self) -> bool {
+        let active = self.is_active();
+        if active {
+            self.preempted += 1;
+            self.knots.clear();
+        }
+        active
+    }
+
+    pub fn status(&self, now: Instant) -> TrajectoryStatus {
+        let now_s = self.seconds(now);
+        let mut actuator_ids: Vec<u32> = self.knots.keys().map(|&id| id as u32).collect();
+        actuator_ids.sort_unstable();
+        TrajectoryStatus {
+            active: self.is_active(),
+            actuator_ids,
+            remaining_s: self
+                .knots
+                .values()
+                .filter_map(|knots| knots.back().map(|knot| knot.t - now_s))
+                .fold(0.0, f64::max),
+            queued_waypoints: self
+                .knots
+                .values()
+                .map(|knots| knots.iter().filter(|knot| knot.t > now_s).count())
+                .max()
+                .unwrap_or(0),
+            accepted: self.accepted,
+            completed: self.completed,
+            preempted: self.preempted,
+        }
+    }
+}
+
+/// Estimates the velocities not given by the client from knot `first` on,
+/// and of the knot before it, which used to end the queue: zero at the end of
+/// the queue, the slope between the neighbouring knots elsewhere.
+fn estimate_velocities(knots: &mut VecDeque<Knot>, first: usize) {
+    let last = knots.len() - 1;
+    for i in first.saturating_sub(1).max(1)..=last {
+        if knots[i].given {
+            continue;
+        }
+        knots[i].velocity = if i == last {
+            0.0
+        } else {
+            let (before, after) = (knots[i - 1], knots[i + 1]);
+            let dt = (after.t - before.t) as f32;
+            if dt > 0.0 {
+                (after.position - before.position) / dt
+            } else {
+                0.0
+            }
+        };
+    }
+}
+
+/// Position and velocity at `t`, holding the ends outside the queue.
+fn sample(knots: &VecDeque<Knot>, t: f64) -> (f32, f32) {
+    let Some(i) = (0..knots.len().saturating_sub(1)).find(|&i| knots[i + 1].t > t) else {
+        return knots.back().map_or((0.0, 0.0), |knot| (knot.position, 0.0));
+    };
+    let (k0, k1) = (knots[i], knots[i + 1]);
+    if t <= k0.t {
+        return (k0.position, 0.0);
+    }
+    let h = (k1.t - k0.t) as f32;
+    let tau = ((t - k0.t) as f32 / h).clamp(0.0, 1.0);
+    let delta = k1.position - k0.position;
+
+    match k1.interpolation {
+        Interpolation::Linear => (k0.position + delta * tau, delta / h),
+        Interpolation::MinimumJerk => {
+            let s = tau * tau * tau * (10.0 - 15.0 * tau + 6.0 * tau * tau);
+            let ds = 30.0 * tau * tau * (1.0 - tau) * (1.0 - tau);
+            (k0.position + delta * s, delta * ds / h)
+        }
+        Interpolation::Cubic => {
+            let (t2, t3) = (tau * tau, tau * tau * tau);
+            let (m0, m1) = (k0.velocity * h, k1.velocity * h);
+            let position = (2.0 * t3 - 3.0 * t2 + 1.0) * k0.position
+                + (t3 - 2.0 * t2 + tau) * m0
+                + (-2.0 * t3 + 3.0 * t2) * k1.position
+                + (t3 - t2) * m1;
+            let velocity = ((6.0 * t2 - 6.0 * tau) * k0.position
+                + (3.0 * t2 - 4.0 * tau + 1.0) * m0
+                + (-6.0 * t2 + 6.0 * tau) * k1.position
+                + (3.0 * t2 - 2.0 * tau) * m1)
+                / h;
+            (position, velocity)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joint::JointLimits;
+    use std::time::Duration;
+
+    fn waypoint(time_s: f64, id: u8, position: f32, velocity: Option<f32>) -> ServoWaypoint {
+        (time_s, HashMap::from([(id, (position, velocity))]))
+    }
+
+    #[test]
+    fn cubic_overshoot_is_held_at_the_limit() {
+        let mut queue = TrajectoryQueue::new();
+        let origin = queue.origin;
+        // Reaches the limit on its way back down, so the curve peaks past it.
+        let waypoints = [waypoint(1.0, 1, 90.0, Some(-60.0)), waypoint(2.0, 1, 60.0, None)];
+        queue.submit(origin, &waypoints, Interpolation::Cubic, TrajectoryMode::Replace, |_| Some(80.0));
+
+        let limits = JointLimits {
+            max_position_deg: Some(90.0),
+            ..Default::default()
+        };
+        let sampled = queue.advance(origin + Duration::from_millis(900))[&1];
+        assert!(sampled > 90.0, "{}", sampled);
+        assert_eq!(limits.clamp_position(sampled), 90.0);
+    }
+
+    /// Samples servo 1 at `t` seconds from the queue origin.
+    fn sample_at(queue: &TrajectoryQueue, t: f64) -> (f32, f32) {
+        sample(&queue.knots[&1], t)
+    }
+
+    #[test]
+    fn cubic_passes_through_waypoints_at_their_velocity() {
+        let mut queue = TrajectoryQueue::new();
+        let origin = queue.origin;
+        let waypoints = [waypoint(1.0, 1, 30.0, Some(20.0)), waypoint(2.0, 1, -10.0, None)];
+        queue.submit(origin, &waypoints, Interpolation::Cubic, TrajectoryMode::Replace, |_| Some(0.0));
+
+        assert_eq!(sample_at(&queue, 0.0).0, 0.0);
+        let (position, velocity) = sample_at(&queue, 1.0 - 1e-6);
+        assert!((position - 30.0).abs() < 1e-3, "{}", position);
+        assert!((velocity - 20.0).abs() < 0.1, "{}", velocity);
+        // The last waypoint is reached at rest and held.
+        let (position, velocity) = sample_at(&queue, 2.0 - 1e-6);
+        assert!((position + 10.0).abs() < 1e-3, "{}", position);
+        assert!(velocity.abs() < 0.1, "{}", velocity);
+        assert_eq!(sample_at(&queue, 3.0), (-10.0, 0.0));
+
+        assert_eq!(queue.advance(origin + Duration::from_secs(3)), HashMap::from([(1, -10.0)]));
+        assert!(!queue.is_active());
+    }
+
+    #[test]
+    fn minimum_jerk_comes_to_rest_at_every_waypoint() {
+        let mut queue = TrajectoryQueue::new();
+        let origin = queue.origin;
+        // A given velocity is ignored: the joint stops at each waypoint.
+        let waypoints = [waypoint(1.0, 1, 30.0, Some(20.0)), waypoint(2.0, 1, -10.0, None)];
+        queue.submit(origin, &waypoints, Interpolation::MinimumJerk, TrajectoryMode::Replace, |_| Some(0.0));
+
+        let (position, velocity) = sample_at(&queue, 1e-6);
+        assert!(position.abs() < 1e-3, "{}", position);
+        assert!(velocity.abs() < 1e-3, "{}", velocity);
+        for (t, expected) in [(1.0, 30.0), (2.0, -10.0)] {
+            let (position, velocity) = sample_at(&queue, t - 1e-6);
+            assert!((position - expected).abs() < 1e-3, "{} at {} s", position, t);
+            assert!(velocity.abs() < 1e-3, "{} at {} s", velocity, t);
+        }
+        // Halfway through a segment is halfway between its waypoints.
+        assert!((sample_at(&queue, 0.5).0 - 15.0).abs() < 1e-3);
+        assert_eq!(sample_at(&queue, 3.0), (-10.0, 0.0));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::{JointConfig, WatchdogAction, WatchdogConfig};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogState {
+    /// Commands are arriving in time, or none have been sent yet.
+    Armed,
+    /// The client went silent and `action` was taken.
+    Tripped { action: WatchdogAction, at: Instant },
+}
+
+/// Tracks when the last command arrived and flags the command loop once the
+/// client has been silent for longer than the configured timeout.
+#[derive(Debug)]
+pub struct CommandWatchdog {
+    timeout: Option<Duration>,
+    action: WatchdogAction,
+    safe_pose_speed_deg_per_s: f32,
+    safe_pose: HashMap<u8, f32>,
+    last_command: Mutex<Instant>,
+    state: watch::Sender<WatchdogState>,
+}
+
+impl CommandWatchdog {
+    pub fn new(config: &WatchdogConfig, joints: &[JointConfig]) -> Self {
+        Self {
+            timeout: (config.timeout_ms > 0).then(|| Duration::from_millis(config.timeout_ms)),
+            action: config.action,
+            safe_pose_speed_deg_per_s: config.safe_pose_speed_deg_per_s,
+            safe_pose: joints
+                .iter()
+                .filter_map(|joint| Some((joint.servo_id, joint.safe_position_deg?)))
+                .collect(),
+            last_command: Mutex::new(Instant::now()),
+            state: watch::channel(WatchdogState::Armed).0,
+        }
+    }
+
+    pub fn action(&self) -> WatchdogAction {
+        self.action
+    }
+
+    pub fn safe_pose(&self) -> &HashMap<u8, f32> {
+        &self.safe_pose
+    }
+
+    pub fn safe_pose_speed_deg_per_s(&self) -> f32 {
+        self.safe_pose_speed_deg_per_s
+    }
+
+    pub fn state(&self) -> WatchdogState {
+        *self.state.borrow()
+    }
+
+    /// Receives every state transition.
+    pub fn subscribe(&self) -> watch::Receiver<WatchdogState> {
+        self.state.subscribe()
+    }
+
+    /// Records a command from the client, re-arming a tripped watchdog.
+    pub fn feed(&self) {
+        *self.last_command.lock().unwrap() = Instant::now();
+        if let WatchdogState::Tripped { at, .. } = self.state() {
+            info!("Commands resumed after {:.1?}, watchdog re-armed", at.elapsed());
+            self.state.send_replace(WatchdogState::Armed);
+        }
+    }
+
+    /// Returns true exactly once per silence, when the timeout has just expired.
+    pub fn check(&self) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        if self.state() != WatchdogState::Armed {
+            return false;
+        }
+        let silence = self.last_command.lock().unwrap().elapsed();
+        if silence < timeout {
+            return false;
+        }
+        warn!("No commands for {:.1?}, watchdog tripped: {:?}", silence, self.action);
+        self.state.send_replace(WatchdogState::Tripped {
+            action: self.action,
+            at: Instant::now(),
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog(timeout_ms: u64) -> CommandWatchdog {
+        let config = WatchdogConfig {
+            timeout_ms,
+            action: WatchdogAction::DisableTorque,
+            ..Default::default()
+        };
+        CommandWatchdog::new(&config, &[])
+    }
+
+    #[test]
+    fn trips_once_per_silence_and_rearms_on_feed() {
+        let watchdog = watchdog(20);
+        assert!(!watchdog.check());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(watchdog.check());
+        assert!(!watchdog.check(), "tripped twice in one silence");
+        assert!(matches!(
+            watchdog.state(),
+            WatchdogState::Tripped { action: WatchdogAction::DisableTorque, .. }
+        ));
+
+        watchdog.feed();
+        assert_eq!(watchdog.state(), WatchdogState::Armed);
+        assert!(!watchdog.check());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(watchdog.check());
+    }
+
+    #[test]
+    fn zero_timeout_never_trips() {
+        let watchdog = watchdog(0);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!watchdog.check());
+        assert_eq!(watchdog.state(), WatchdogState::Armed);
+    }
+}